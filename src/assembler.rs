@@ -0,0 +1,531 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::chip8::START_ADDRESS;
+use crate::instruction::{Instruction, Register};
+
+/// How deep `include` directives may nest before the assembler assumes a cycle.
+const MAX_INCLUDE_DEPTH: usize = 16;
+/// Operand keywords, which would hide symbols of the same name.
+const RESERVED: [&str; 8] = ["I", "B", "F", "K", "R", "DT", "ST", "HF"];
+
+const MNEMONICS: [&str; 31] = [
+    "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE", "SNE", "SAVE",
+    "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND", "DRW", "SKP", "SKNP",
+    "PLANE", "AUDIO", "PITCH",
+];
+
+/// An assembly error, pointing at the line that caused it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// A line of source after labels and comments have been stripped.
+struct Statement {
+    file: String,
+    line: usize,
+    address: u16,
+    mnemonic: String,
+    operands: Vec<String>,
+}
+
+impl Statement {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError { file: self.file.clone(), line: self.line, message: message.into() }
+    }
+}
+
+/// Operand of an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operand {
+    V(Register),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Flags,
+    Long(i64),
+    Value(i64),
+}
+
+/// A label's address, or a constant's expression, evaluated in the second pass so it
+/// can refer to labels further down.
+enum Symbol {
+    Address(i64),
+    Constant { expression: String, file: String, line: usize },
+}
+
+/// Two-pass assembler for the mnemonics documented on the `OP_*` handlers.
+///
+/// Source syntax, one statement per line:
+/// - `; comment` anywhere on a line
+/// - `label:` before a statement or on its own line
+/// - `NAME EQU value` or `NAME = value` defines a constant, which may use labels
+///   defined further down
+/// - register and operand names (`V0`, `I`, `DT`, `HF`...) cannot be labels or constants
+/// - `db 1, 0x2, #3, $4, %00000101` emits bytes, `dw 0x1234, label` emits big-endian words
+/// - `include "file.asm"` assembles another file in place, relative to the including file
+/// - numbers are decimal, `0x`/`#`/`$` hexadecimal or `0b`/`%` binary, and may be
+///   combined with labels and constants using `+` and `-`
+/// - `LD I, LONG addr` is the XO-CHIP long index load
+struct Assembler {
+    symbols: HashMap<String, Symbol>,
+    /// Constant names in the order they are defined.
+    constants: Vec<String>,
+    statements: Vec<Statement>,
+    address: u16,
+}
+
+/// Assembles a source file into a ROM image loaded at `START_ADDRESS`.
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let source = std::fs::read_to_string(path).map_err(|e| AsmError {
+        file: path.display().to_string(),
+        line: 0,
+        message: e.to_string(),
+    })?;
+    assemble_source(&source, path)
+}
+
+/// Assembles source text; includes are resolved relative to `path`.
+pub fn assemble_source(source: &str, path: &Path) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler {
+        symbols: HashMap::new(),
+        constants: Vec::new(),
+        statements: Vec::new(),
+        address: START_ADDRESS as u16,
+    };
+    assembler.parse(source, path, 0)?;
+    assembler.emit()
+}
+
+impl Assembler {
+    /// First pass: assigns addresses to statements and labels and collects constants.
+    fn parse(&mut self, source: &str, path: &Path, depth: usize) -> Result<(), AsmError> {
+        let file = path.display().to_string();
+        for (number, text) in source.lines().enumerate() {
+            let error = |message: String| AsmError { file: file.clone(), line: number + 1, message };
+            let mut rest = strip_comment(text).trim();
+
+            while let Some((label, after)) = split_label(rest) {
+                self.define(label, Symbol::Address(self.address as i64)).map_err(&error)?;
+                rest = after.trim();
+            }
+            if rest.is_empty() {
+                continue;
+            }
+
+            let (mnemonic, operand_text) = match rest.split_once(char::is_whitespace) {
+                Some((mnemonic, operands)) => (mnemonic, operands.trim()),
+                None => (rest, ""),
+            };
+
+            // NAME EQU value / NAME = value
+            let constant = match operand_text.split_once(char::is_whitespace) {
+                Some((keyword, value)) if keyword.eq_ignore_ascii_case("equ") => Some(value),
+                _ => operand_text.strip_prefix('='),
+            };
+            let constant = match constant.filter(|_| is_identifier(mnemonic)) {
+                Some(value) => Some((mnemonic, value)),
+                None => rest.split_once('=').filter(|(name, _)| is_identifier(name.trim())),
+            };
+            if let Some((name, value)) = constant {
+                let symbol = Symbol::Constant { expression: value.trim().to_owned(), file: file.clone(), line: number + 1 };
+                self.define(name.trim(), symbol).map_err(&error)?;
+                self.constants.push(name.trim().to_owned());
+                continue;
+            }
+
+            let mnemonic = mnemonic.to_ascii_uppercase();
+            let operands = split_operands(operand_text);
+            let size = match mnemonic.as_str() {
+                "INCLUDE" => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(error("includes nested too deeply".to_owned()));
+                    }
+                    let name = operand_text.trim_matches('"');
+                    let included: PathBuf = path.parent().unwrap_or(Path::new("")).join(name);
+                    let source = std::fs::read_to_string(&included)
+                        .map_err(|e| error(format!("cannot include {}: {}", included.display(), e)))?;
+                    self.parse(&source, &included, depth + 1)?;
+                    continue;
+                }
+                "DB" => operands.len(),
+                "DW" => operands.len() * 2,
+                "LD" if operands.len() == 2 && long_address(&operands[1]).is_some() => 4,
+                _ => 2,
+            };
+            self.statements.push(Statement {
+                file: file.clone(),
+                line: number + 1,
+                address: self.address,
+                mnemonic,
+                operands,
+            });
+            self.address = self
+                .address
+                .checked_add(size as u16)
+                .ok_or_else(|| error("program does not fit in memory".to_owned()))?;
+        }
+        Ok(())
+    }
+
+    /// Second pass: evaluates the constants, then encodes every statement now that all
+    /// labels are known.
+    fn emit(&self) -> Result<Vec<u8>, AsmError> {
+        for name in &self.constants {
+            if let Some(Symbol::Constant { expression, file, line }) = self.symbols.get(name) {
+                self.evaluate(expression)
+                    .map_err(|message| AsmError { file: file.clone(), line: *line, message })?;
+            }
+        }
+        let mut rom = Vec::new();
+        for statement in &self.statements {
+            debug_assert_eq!(START_ADDRESS as usize + rom.len(), statement.address as usize);
+            match statement.mnemonic.as_str() {
+                "DB" => {
+                    for operand in &statement.operands {
+                        let value = self.evaluate(operand).map_err(|e| statement.error(e))?;
+                        rom.push(check(value, -0x80, 0xFF, "byte").map_err(|e| statement.error(e))? as u8);
+                    }
+                }
+                "DW" => {
+                    for operand in &statement.operands {
+                        let value = self.evaluate(operand).map_err(|e| statement.error(e))?;
+                        let word = check(value, -0x8000, 0xFFFF, "word").map_err(|e| statement.error(e))? as u16;
+                        rom.extend(word.to_be_bytes());
+                    }
+                }
+                _ => {
+                    let operands = statement
+                        .operands
+                        .iter()
+                        .map(|operand| self.operand(operand))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|e| statement.error(e))?;
+                    let (instruction, long) =
+                        encode(&statement.mnemonic, &operands).map_err(|e| statement.error(e))?;
+                    rom.extend(instruction.encode().to_be_bytes());
+                    if let Some(address) = long {
+                        rom.extend(address.to_be_bytes());
+                    }
+                }
+            }
+        }
+        Ok(rom)
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol) -> Result<(), String> {
+        if !is_identifier(name) {
+            return Err(format!("invalid symbol name {:?}", name));
+        }
+        let upper = name.to_ascii_uppercase();
+        if RESERVED.contains(&upper.as_str()) || parse_register(&upper).is_some() {
+            return Err(format!("{} is an operand name and cannot be a symbol", name));
+        }
+        if self.symbols.insert(name.to_owned(), symbol).is_some() {
+            return Err(format!("{} is already defined", name));
+        }
+        Ok(())
+    }
+
+    fn operand(&self, text: &str) -> Result<Operand, String> {
+        let upper = text.to_ascii_uppercase();
+        let operand = match upper.as_str() {
+            "I" => Operand::I,
+            "[I]" => Operand::IndirectI,
+            "DT" => Operand::DelayTimer,
+            "ST" => Operand::SoundTimer,
+            "K" => Operand::Key,
+            "F" => Operand::Font,
+            "HF" => Operand::BigFont,
+            "B" => Operand::Bcd,
+            "R" => Operand::Flags,
+            _ => {
+                if let Some(register) = parse_register(&upper) {
+                    Operand::V(register)
+                } else if let Some(address) = long_address(text) {
+                    Operand::Long(self.evaluate(address)?)
+                } else {
+                    Operand::Value(self.evaluate(text)?)
+                }
+            }
+        };
+        Ok(operand)
+    }
+
+    /// Evaluates `term (+|- term)*`, where a term is a number or a symbol.
+    fn evaluate(&self, text: &str) -> Result<i64, String> {
+        self.evaluate_nested(text, 0)
+    }
+
+    /// `evaluate`, `depth` constants deep; deeper than there are constants means one
+    /// refers to itself.
+    fn evaluate_nested(&self, text: &str, depth: usize) -> Result<i64, String> {
+        let mut total = 0i64;
+        let mut sign = 1;
+        let mut expect_term = true;
+        let mut start = 0;
+        let bytes = text.as_bytes();
+        for i in 0..=bytes.len() {
+            let at_operator = i < bytes.len() && (bytes[i] == b'+' || bytes[i] == b'-');
+            if i < bytes.len() && !at_operator {
+                continue;
+            }
+            let term = text[start..i].trim();
+            if term.is_empty() {
+                if !expect_term || i == bytes.len() {
+                    return Err(format!("malformed expression {:?}", text));
+                }
+                // unary sign
+                if at_operator && bytes[i] == b'-' {
+                    sign = -sign;
+                }
+            } else {
+                total += sign * self.term(term, depth)?;
+                sign = 1;
+                if at_operator && bytes[i] == b'-' {
+                    sign = -1;
+                }
+            }
+            expect_term = true;
+            start = i + 1;
+        }
+        Ok(total)
+    }
+
+    fn term(&self, text: &str, depth: usize) -> Result<i64, String> {
+        let parsed = if let Some(hex) = text
+            .strip_prefix("0x")
+            .or_else(|| text.strip_prefix("0X"))
+            .or_else(|| text.strip_prefix('#'))
+            .or_else(|| text.strip_prefix('$'))
+        {
+            i64::from_str_radix(hex, 16).ok()
+        } else if let Some(binary) = text
+            .strip_prefix("0b")
+            .or_else(|| text.strip_prefix("0B"))
+            .or_else(|| text.strip_prefix('%'))
+        {
+            i64::from_str_radix(binary, 2).ok()
+        } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+            text.parse::<i64>().ok()
+        } else {
+            return match self.symbols.get(text) {
+                Some(Symbol::Address(address)) => Ok(*address),
+                Some(Symbol::Constant { .. }) if depth >= self.constants.len() => {
+                    Err(format!("{} is defined in terms of itself", text))
+                }
+                Some(Symbol::Constant { expression, .. }) => self.evaluate_nested(expression, depth + 1),
+                None => Err(format!("undefined symbol {}", text)),
+            };
+        };
+        parsed.ok_or_else(|| format!("invalid number {}", text))
+    }
+}
+
+/// Picks the instruction for a mnemonic and its operands, along with the address
+/// word following `LD I, LONG addr`.
+fn encode(mnemonic: &str, operands: &[Operand]) -> Result<(Instruction, Option<u16>), String> {
+    use Operand::*;
+    let addr = |value: i64| check(value, 0, 0xFFF, "address").map(|v| v as u16);
+    let byte = |value: i64| check(value, -0x80, 0xFF, "byte").map(|v| v as u8);
+    let nibble = |value: i64| check(value, 0, 0xF, "nibble").map(|v| v as u8);
+    let instruction = match (mnemonic, operands) {
+        ("CLS", []) => Instruction::Cls,
+        ("RET", []) => Instruction::Ret,
+        ("SCD", [Value(n)]) => Instruction::ScrollDown { n: nibble(*n)? },
+        ("SCU", [Value(n)]) => Instruction::ScrollUp { n: nibble(*n)? },
+        ("SCR", []) => Instruction::ScrollRight,
+        ("SCL", []) => Instruction::ScrollLeft,
+        ("EXIT", []) => Instruction::Exit,
+        ("LOW", []) => Instruction::LowRes,
+        ("HIGH", []) => Instruction::HighRes,
+        ("JP", [Value(nnn)]) => Instruction::Jump { nnn: addr(*nnn)? },
+        ("JP", [V(x), Value(nnn)]) if x.index() == 0 => Instruction::JumpOffset { nnn: addr(*nnn)? },
+        ("CALL", [Value(nnn)]) => Instruction::Call { nnn: addr(*nnn)? },
+        ("SE", [V(x), Value(kk)]) => Instruction::SkipEqByte { x: *x, kk: byte(*kk)? },
+        ("SE", [V(x), V(y)]) => Instruction::SkipEq { x: *x, y: *y },
+        ("SNE", [V(x), Value(kk)]) => Instruction::SkipNeByte { x: *x, kk: byte(*kk)? },
+        ("SNE", [V(x), V(y)]) => Instruction::SkipNe { x: *x, y: *y },
+        ("SAVE", [V(x), V(y)]) => Instruction::SaveRange { x: *x, y: *y },
+        ("LOAD", [V(x), V(y)]) => Instruction::LoadRange { x: *x, y: *y },
+        ("LD", [V(x), Value(kk)]) => Instruction::LoadByte { x: *x, kk: byte(*kk)? },
+        ("LD", [V(x), V(y)]) => Instruction::Move { x: *x, y: *y },
+        ("LD", [I, Value(nnn)]) => Instruction::LoadIndex { nnn: addr(*nnn)? },
+        ("LD", [I, Long(address)]) => {
+            let address = check(*address, 0, 0xFFFF, "address")? as u16;
+            return Ok((Instruction::LoadIndexLong, Some(address)));
+        }
+        ("LD", [V(x), DelayTimer]) => Instruction::GetDelay { x: *x },
+        ("LD", [V(x), Key]) => Instruction::WaitKey { x: *x },
+        ("LD", [DelayTimer, V(x)]) => Instruction::SetDelay { x: *x },
+        ("LD", [SoundTimer, V(x)]) => Instruction::SetSound { x: *x },
+        ("LD", [Font, V(x)]) => Instruction::Font { x: *x },
+        ("LD", [BigFont, V(x)]) => Instruction::BigFont { x: *x },
+        ("LD", [Bcd, V(x)]) => Instruction::Bcd { x: *x },
+        ("LD", [IndirectI, V(x)]) => Instruction::Store { x: *x },
+        ("LD", [V(x), IndirectI]) => Instruction::Restore { x: *x },
+        ("LD", [Flags, V(x)]) => Instruction::SaveFlags { x: *x },
+        ("LD", [V(x), Flags]) => Instruction::LoadFlags { x: *x },
+        ("ADD", [V(x), Value(kk)]) => Instruction::AddByte { x: *x, kk: byte(*kk)? },
+        ("ADD", [V(x), V(y)]) => Instruction::Add { x: *x, y: *y },
+        ("ADD", [I, V(x)]) => Instruction::AddIndex { x: *x },
+        ("OR", [V(x), V(y)]) => Instruction::Or { x: *x, y: *y },
+        ("AND", [V(x), V(y)]) => Instruction::And { x: *x, y: *y },
+        ("XOR", [V(x), V(y)]) => Instruction::Xor { x: *x, y: *y },
+        ("SUB", [V(x), V(y)]) => Instruction::Sub { x: *x, y: *y },
+        ("SHR", [V(x)]) => Instruction::ShiftRight { x: *x, y: *x },
+        ("SHR", [V(x), V(y)]) => Instruction::ShiftRight { x: *x, y: *y },
+        ("SUBN", [V(x), V(y)]) => Instruction::SubN { x: *x, y: *y },
+        ("SHL", [V(x)]) => Instruction::ShiftLeft { x: *x, y: *x },
+        ("SHL", [V(x), V(y)]) => Instruction::ShiftLeft { x: *x, y: *y },
+        ("RND", [V(x), Value(kk)]) => Instruction::Random { x: *x, kk: byte(*kk)? },
+        ("DRW", [V(x), V(y), Value(n)]) => Instruction::Draw { x: *x, y: *y, n: nibble(*n)? },
+        ("SKP", [V(x)]) => Instruction::SkipKey { x: *x },
+        ("SKNP", [V(x)]) => Instruction::SkipNotKey { x: *x },
+        ("PLANE", [Value(n)]) => Instruction::Plane { n: nibble(*n)? },
+        ("AUDIO", []) => Instruction::Audio,
+        ("PITCH", [V(x)]) => Instruction::Pitch { x: *x },
+        _ if MNEMONICS.contains(&mnemonic) => return Err(format!("invalid operands for {}", mnemonic)),
+        _ => return Err(format!("unknown instruction {}", mnemonic)),
+    };
+    Ok((instruction, None))
+}
+
+fn check(value: i64, min: i64, max: i64, what: &str) -> Result<i64, String> {
+    if value < min || value > max {
+        return Err(format!("{} out of range: {}", what, value));
+    }
+    // negative bytes and words are stored in two's complement
+    Ok(if value < 0 { value + max + 1 } else { value })
+}
+
+/// The address expression of `LONG addr`, the XO-CHIP long index load operand.
+fn long_address(text: &str) -> Option<&str> {
+    let (keyword, address) = text.split_once(char::is_whitespace)?;
+    keyword.eq_ignore_ascii_case("LONG").then(|| address.trim())
+}
+
+fn parse_register(text: &str) -> Option<Register> {
+    let digit = text.strip_prefix('V')?;
+    if digit.len() != 1 {
+        return None;
+    }
+    Register::new(u8::from_str_radix(digit, 16).ok()?)
+}
+
+fn strip_comment(text: &str) -> &str {
+    text.split_once(';').map_or(text, |(code, _)| code)
+}
+
+/// Splits `label: rest` into the label and what follows it.
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let (label, rest) = text.split_once(':')?;
+    is_identifier(label).then_some((label, rest))
+}
+
+fn split_operands(text: &str) -> Vec<String> {
+    if text.is_empty() {
+        return Vec::new();
+    }
+    text.split(',').map(|operand| operand.trim().to_owned()).collect()
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+        assemble_source(source, Path::new("test.asm"))
+    }
+
+    #[test]
+    fn labels_resolve_to_addresses() {
+        let rom = assemble("start: CLS\nloop:\n  JP loop\n  CALL start").unwrap();
+        assert_eq!(rom, [0x00, 0xE0, 0x12, 0x02, 0x22, 0x00]);
+    }
+
+    #[test]
+    fn forward_references() {
+        let rom = assemble("JP end\nLD I, sprite + 1\nend: EXIT\nsprite: db 0xF0, 0x90").unwrap();
+        assert_eq!(rom, [0x12, 0x04, 0xA2, 0x07, 0x00, 0xFD, 0xF0, 0x90]);
+    }
+
+    #[test]
+    fn constants_may_refer_to_later_labels_and_constants() {
+        let rom = assemble("SIZE EQU end - start\nDOUBLE = SIZE + SIZE\nstart: LD V0, DOUBLE\nend:").unwrap();
+        assert_eq!(rom, [0x60, 0x04]);
+    }
+
+    #[test]
+    fn self_referencing_constants_are_rejected() {
+        let error = assemble("A = C\nC = A + 1\nCLS").unwrap_err();
+        assert_eq!(error.line, 1);
+        assert!(error.message.contains("itself"), "{}", error.message);
+    }
+
+    #[test]
+    fn long_load_takes_four_bytes() {
+        let rom = assemble("LD I, LONG data\nCLS\ndata: db 1").unwrap();
+        assert_eq!(rom, [0xF0, 0x00, 0x02, 0x06, 0x00, 0xE0, 0x01]);
+    }
+
+    #[test]
+    fn labels_starting_with_long_are_plain_addresses() {
+        let rom = assemble("LD I, longtable\nlongtable: db 7").unwrap();
+        assert_eq!(rom, [0xA2, 0x02, 0x07]);
+    }
+
+    #[test]
+    fn db_and_dw() {
+        let rom = assemble("db 1, 0x2, #3, $4, %00000101, -1\ndw 0x1234, here\nhere:").unwrap();
+        assert_eq!(rom, [1, 2, 3, 4, 5, 0xFF, 0x12, 0x34, 0x02, 0x0A]);
+    }
+
+    #[test]
+    fn out_of_range_byte_is_an_error() {
+        let error = assemble("CLS\ndb 256").unwrap_err();
+        assert_eq!((error.file.as_str(), error.line), ("test.asm", 2));
+    }
+
+    #[test]
+    fn errors_point_at_their_line() {
+        let error = assemble("CLS\n\n; comment\nJP nowhere").unwrap_err();
+        assert_eq!(error.line, 4);
+        assert_eq!(error.message, "undefined symbol nowhere");
+        let error = assemble("CLS\nFOO V0").unwrap_err();
+        assert_eq!((error.line, error.message.as_str()), (2, "unknown instruction FOO"));
+        let error = assemble("a: CLS\na: RET").unwrap_err();
+        assert_eq!(error.line, 2);
+    }
+
+    #[test]
+    fn operand_names_cannot_be_symbols() {
+        for source in ["dt: CLS", "I = 3", "hf EQU 1", "v0: CLS"] {
+            let error = assemble(source).unwrap_err();
+            assert!(error.message.contains("operand name"), "{}: {}", source, error.message);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use chip8_h::frontend::Audio;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+use std::str::FromStr;
+
+/// Sample rate requested from SDL; the device may pick another one.
+const SAMPLE_RATE: i32 = 44100;
+/// Seconds the envelope takes to go from silence to full volume and back,
+/// short enough to be inaudible but long enough to avoid clicks.
+const RAMP_TIME: f32 = 0.005;
+
+/// Shape of the tone played while the sound timer is active.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Noise,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            "noise" => Ok(Waveform::Noise),
+            _ => Err(format!("unknown waveform {:?}, expected square, sine, triangle or noise", name)),
+        }
+    }
+}
+
+/// How the buzzer sounds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioSettings {
+    /// Pitch of the tone in Hz.
+    pub frequency: f32,
+    /// From 0.0, silent, to 1.0.
+    pub volume: f32,
+    pub waveform: Waveform,
+    pub muted: bool,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings { frequency: 440.0, volume: 0.25, waveform: Waveform::Square, muted: false }
+    }
+}
+
+/// Produces the samples on SDL's audio thread.
+struct Generator {
+    settings: AudioSettings,
+    sample_rate: f32,
+    /// Whether the sound timer is active.
+    playing: bool,
+    /// XO-CHIP audio pattern and its playback rate, played instead of `waveform`.
+    pattern: Option<([u8; 0x10], f32)>,
+    /// Position within the current period, from 0.0 to 1.0.
+    phase: f32,
+    /// Current volume of the envelope, moving towards the target volume.
+    gain: f32,
+    noise: u32,
+    noise_sample: f32,
+}
+
+impl AudioCallback for Generator {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let target = if self.playing && !self.settings.muted { self.settings.volume.clamp(0.0, 1.0) } else { 0.0 };
+        let step = 1.0 / (RAMP_TIME * self.sample_rate);
+        let (frequency, pattern) = match self.pattern {
+            // a pattern is 128 one-bit samples
+            Some((pattern, rate)) => (rate / 128.0, Some(pattern)),
+            None => (self.settings.frequency, None),
+        };
+        for sample in out.iter_mut() {
+            self.gain = if self.gain < target { (self.gain + step).min(target) } else { (self.gain - step).max(target) };
+            if self.gain == 0.0 {
+                *sample = 0.0;
+                continue;
+            }
+            let previous_phase = self.phase;
+            self.phase = (self.phase + frequency / self.sample_rate).fract();
+            let wave = match (pattern, self.settings.waveform) {
+                (Some(pattern), _) => {
+                    let bit = (self.phase * 128.0) as usize;
+                    if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 { 1.0 } else { -1.0 }
+                }
+                (None, Waveform::Square) => if self.phase < 0.5 { 1.0 } else { -1.0 },
+                (None, Waveform::Sine) => (self.phase * std::f32::consts::TAU).sin(),
+                (None, Waveform::Triangle) => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+                (None, Waveform::Noise) => {
+                    // a new random level every period, xorshift32
+                    if self.phase < previous_phase {
+                        self.noise ^= self.noise << 13;
+                        self.noise ^= self.noise >> 17;
+                        self.noise ^= self.noise << 5;
+                        self.noise_sample = (self.noise as f32 / u32::MAX as f32) * 2.0 - 1.0;
+                    }
+                    self.noise_sample
+                }
+            };
+            *sample = wave * self.gain;
+        }
+    }
+}
+
+/// Plays the buzzer through an SDL audio device.
+pub struct SdlAudio {
+    device: AudioDevice<Generator>,
+}
+
+impl SdlAudio {
+    /// Opens the default audio device. Works with SDL's dummy driver
+    /// (`SDL_AUDIODRIVER=dummy`) on machines without sound hardware.
+    pub fn new(subsystem: &AudioSubsystem, settings: AudioSettings) -> Result<Self, String> {
+        let desired = AudioSpecDesired { freq: Some(SAMPLE_RATE), channels: Some(1), samples: Some(512) };
+        let device = subsystem.open_playback(None, &desired, |spec| Generator {
+            settings,
+            sample_rate: spec.freq as f32,
+            playing: false,
+            pattern: None,
+            phase: 0.0,
+            gain: 0.0,
+            noise: 0x1234_5678,
+            noise_sample: 0.0,
+        })?;
+        device.resume();
+        Ok(SdlAudio { device })
+    }
+}
+
+impl Audio for SdlAudio {
+    fn set_playing(&mut self, playing: bool) {
+        self.device.lock().playing = playing;
+    }
+
+    fn set_pattern(&mut self, pattern: Option<([u8; 0x10], f32)>) {
+        self.device.lock().pattern = pattern;
+    }
+
+    fn toggle_mute(&mut self) -> bool {
+        let mut generator = self.device.lock();
+        generator.settings.muted = !generator.settings.muted;
+        generator.settings.muted
+    }
+}
@@ -1,12 +1,26 @@
 
-use rand::{rngs::OsRng, RngCore};
-use std::{io::{self, BufReader, Read, Seek}, ptr::null};
-const START_ADDRESS: u32 = 0x200;
-pub const VIDEO_WIDTH: u32 = 120;
-pub const VIDEO_HEIGHT: u32 = 60;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::error::{EmulationError, Fault, MachineState, RomError, StateError};
+use crate::instruction::{Instruction, Register};
+use crate::quirks::Quirks;
+use crate::random::{RandomSource, Seeded};
+use crate::savestate::{StateReader, StateWriter};
+use crate::trace::{TraceRecord, TraceSink};
+
+pub const START_ADDRESS: u32 = 0x200;
+const MEMORY_SIZE: usize = 4096;
+const XO_MEMORY_SIZE: usize = 0x10000;
+pub const VIDEO_WIDTH: u32 = 64;
+pub const VIDEO_HEIGHT: u32 = 32;
+pub const HIRES_VIDEO_WIDTH: u32 = 128;
+pub const HIRES_VIDEO_HEIGHT: u32 = 64;
 const FONTSET_SIZE: u32 = 80;
 const FONTSET_START_ADDRESS: u16 = 0x50;
-const fontset: [u8; FONTSET_SIZE as usize] = [
+const BIG_FONTSET_SIZE: u32 = 160;
+const BIG_FONTSET_START_ADDRESS: u16 = FONTSET_START_ADDRESS + FONTSET_SIZE as u16;
+const FONTSET: [u8; FONTSET_SIZE as usize] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -24,21 +38,72 @@ const fontset: [u8; FONTSET_SIZE as usize] = [
     0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
+/// SUPER-CHIP 8x10 digits, selected with Fx30.
+const BIG_FONTSET: [u8; BIG_FONTSET_SIZE as usize] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x3C, 0x7E, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// What happened during a successful `Chip8::cycle`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The instruction ran to completion.
+    Executed,
+    /// Fx0A found no key pressed and will run again on the next cycle.
+    WaitingForKey,
+    /// Dxyn is waiting for the next vertical blank (display wait quirk).
+    WaitingForVBlank,
+    /// 00FD stopped the interpreter; no instruction was executed.
+    Halted,
+}
+
+/// A read or write of a watched address, see `Chip8::watch`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub addr: usize,
+    /// The byte read, or the byte written.
+    pub value: u8,
+    pub write: bool,
+}
+
+/// The instruction set an interpreter understands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variant {
+    /// The original CHIP-8 instructions plus the SUPER-CHIP 1.1 extensions, with 4 KiB of memory.
+    Chip8,
+    /// XO-CHIP: 64 KiB of memory, two bit planes and an audio pattern buffer on top of SUPER-CHIP.
+    XoChip,
+}
 
 pub struct Chip8 {
     /// The CHIP-8 has sixteen 8-bit registers, labeled V0 to VF.
     /// Each register is able to hold any value from 0x00 to 0xFF.
     /// Register VF is a bit special. It’s used as a flag to hold information about the result of operations.
-    registers: [u8; 0x0f], // general purpose registers
+    registers: [u8; 0x10], // general purpose registers
     /// The CHIP-8 has 4096 bytes of memory, meaning the address space is from 0x000 to 0xFFF.
     /// The address space is segmented into three sections:
     /// 0x000-0x1FF: Originally reserved for the CHIP-8 interpreter, but in our modern emulator we will just never write to or read from that area. Except for…
     /// 0x050-0x0A0: Storage space for the 16 built-in characters (0 through F).
     /// 0x200-0xFFF: Instructions from the ROM will be stored starting at 0x200, and anything left after the ROM’s space is free to use.
-    memory: [u8; 4096],
+    /// XO-CHIP extends the address space to 64 KiB, up to 0xFFFF.
+    memory: Vec<u8>,
     index: u16,         // index register
     pc: u16,            // program counter reg
-    stack: [u16; 0x0f], // stack level
+    stack: [u16; 0x10], // stack level
     sp: u8,             // stack pointer reg
     /// The CHIP-8 has a simple timer used for timing.
     /// If the timer value is zero, it stays zero.
@@ -50,34 +115,52 @@ pub struct Chip8 {
     ///  Programmers used this for simple sound emission.
     sound_timer: u8,
     ///  The CHIP-8 has 16 input keys that match the first 16 hex values: 0 through F.
-    ///  Each key is either pressed or not pressed
-    pub keypad: [u8; 0x0f],
-    /// The CHIP-8 has an additional memory buffer used for storing the graphics to display. It is 64 pixels wide and 32 pixels high.
-    /// Each pixel is either on or off, so only two colors can be represented.
-    pub video: [u32; 64 * 32],
+    ///  Each key is either pressed or not pressed, see `press_key` and `release_key`.
+    pub(crate) keypad: [u8; 0x10],
+    /// The CHIP-8 has an additional memory buffer used for storing the graphics to display. It is 64 pixels wide and 32 pixels high,
+    /// or 128 by 64 once a SUPER-CHIP program switches to high resolution, so its length follows `video_width() * video_height()`.
+    /// Each pixel holds the bit planes it is lit in, bit 0 being the first plane, so it is
+    /// 0 or 1 unless an XO-CHIP program draws to the second plane too. Colours are up to
+    /// the frontend, see `palette::Palette`.
+    video: Vec<u8>,
+    /// The bit planes drawing instructions operate on, selected by Fn01.
+    plane_mask: u8,
+    /// XO-CHIP 1-bit audio samples played while the sound timer is active, loaded by F002.
+    audio_pattern: [u8; 0x10],
+    /// XO-CHIP playback rate of `audio_pattern`, set by Fx3A.
+    pitch: u8,
+    /// Set by 00FF and cleared by 00FE.
+    hires: bool,
+    /// Set by 00FD, no more instructions are executed afterwards.
+    halted: bool,
+    /// SUPER-CHIP RPL user flags, saved and restored by Fx75 and Fx85.
+    rpl_flags: [u8; 0x10],
+    /// Addresses whose reads and writes are recorded in `watch_hits`.
+    watched: BTreeSet<usize>,
+    watch_hits: Vec<MemoryAccess>,
 
     opcode: u16,
 
-    rand_gen: OsRng,
-
-    /// Function Pointer Table
-    /// $0 needs an array that can index up to $E+1
-    /// $8 needs an array that can index up to $E+1
-    /// $E needs an array that can index up to $E+1
-    /// $F needs an array that can index up to $65+1
-    table: Vec<fn(&mut Chip8)>,
-    table0: Vec<fn(&mut Chip8)>,
-    table8: Vec<fn(&mut Chip8)>,
-    tableE: Vec<fn(&mut Chip8)>,
-    tableF: Vec<fn(&mut Chip8)>,
-
+    /// Where Cxkk draws from, `Seeded` with `random::DEFAULT_SEED` unless replaced.
+    rand_gen: Box<dyn RandomSource>,
+    /// Instructions started since power-on, counted for traces.
+    cycles: u64,
+    /// Receives every instruction `cycle` starts, see `set_trace`.
+    trace: Option<Box<dyn TraceSink>>,
 
+    /// The instruction set `cycle` runs.
+    variant: Variant,
+    /// Behaviour of the opcodes that differ between interpreters.
+    quirks: Quirks,
+    /// Set by `tick_timers` at every 60 Hz frame and cleared by the first `Dxyn`
+    /// after it, used to emulate the display wait quirk.
+    vblank: bool,
 }
 impl Default for Chip8 {
     fn default() -> Self {
         Self {
             registers: Default::default(),
-            memory: [0; 4096],
+            memory: vec![0; MEMORY_SIZE],
             index: Default::default(),
             pc: Default::default(),
             stack: Default::default(),
@@ -85,505 +168,1124 @@ impl Default for Chip8 {
             delay_timer: Default::default(),
             sound_timer: Default::default(),
             keypad: Default::default(),
-            video: [0;  64 * 32],
+            video: vec![0; (VIDEO_WIDTH * VIDEO_HEIGHT) as usize],
+            plane_mask: 0x1,
+            audio_pattern: Default::default(),
+            pitch: 64,
+            hires: false,
+            halted: false,
+            rpl_flags: Default::default(),
+            watched: BTreeSet::new(),
+            watch_hits: Vec::new(),
             opcode: Default::default(),
-            rand_gen: OsRng {},
-            table: Vec::new(),
-            table0: Vec::new(),
-            table8: Vec::new(),
-            tableE: Vec::new(),
-            tableF: Vec::new(),
-
+            rand_gen: Box::new(Seeded::default()),
+            cycles: 0,
+            trace: None,
+            variant: Variant::Chip8,
+            quirks: Quirks::default(),
+            vblank: true,
         }
     }
 }
 
 impl Chip8 {
     pub fn new() -> Self {
-        let mut chip: Chip8 = Chip8::default();
-        chip.pc = START_ADDRESS as u16;
-        (0..FONTSET_SIZE).into_iter().for_each(|e| {
-            chip.memory[(FONTSET_START_ADDRESS as usize) + (e as usize)] = fontset[e as usize]
-        });
-        let mut table:Vec<fn(&mut Chip8)> = vec![Chip8::OP_NULL;0xF + 1];
-        let mut table0:Vec<fn(&mut Chip8)> = vec![Chip8::OP_NULL;0xE + 1];
-        let mut table8:Vec<fn(&mut Chip8)> = vec![Chip8::OP_NULL;0xE + 1];
-        let mut tableE:Vec<fn(&mut Chip8)> = vec![Chip8::OP_NULL;0xE + 1];
-        let mut tableF:Vec<fn(&mut Chip8)> = vec![Chip8::OP_NULL;0x65 + 1];
-        table.fill(Chip8::OP_NULL);
-        table0.fill(Chip8::OP_NULL);
-        table8.fill(Chip8::OP_NULL);
-        tableE.fill(Chip8::OP_NULL);
-        tableF.fill(Chip8::OP_NULL);
-
-        table[0x0] = Chip8::Table0;
-		table[0x1] = Chip8::OP_1nnn;
-		table[0x2] = Chip8::OP_2nnn;
-		table[0x3] = Chip8::OP_3xkk;
-		table[0x4] = Chip8::OP_4xkk;
-		table[0x5] = Chip8::OP_5xy0;
-		table[0x6] = Chip8::OP_6xkk;
-		table[0x7] = Chip8::OP_7xkk;
-		table[0x8] = Chip8::Table8;
-		table[0x9] = Chip8::OP_9xy0;
-		table[0xA] = Chip8::OP_Annn;
-		table[0xB] = Chip8::OP_Bnnn;
-		table[0xC] = Chip8::OP_Cxkk;
-		table[0xD] = Chip8::OP_Dxyn;
-		table[0xE] = Chip8::TableE;
-		table[0xF] = Chip8::TableF;
-        (0..=0xE).into_iter().for_each(|f| {
-            table0[f] = Chip8::OP_NULL;
-			table8[f] = Chip8::OP_NULL;
-			tableE[f] = Chip8::OP_NULL;
+        Chip8::with_quirks(Quirks::default())
+    }
+
+    /// Creates an interpreter that resolves ambiguous opcodes according to `quirks`.
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        Chip8::with_variant(Variant::Chip8, quirks)
+    }
+
+    /// Creates an interpreter for the given instruction set.
+    /// The XO-CHIP instructions are only run for `Variant::XoChip`.
+    pub fn with_variant(variant: Variant, quirks: Quirks) -> Self {
+        let memory_size = match variant {
+            Variant::Chip8 => MEMORY_SIZE,
+            Variant::XoChip => XO_MEMORY_SIZE,
+        };
+        let mut chip: Chip8 = Chip8 {
+            pc: START_ADDRESS as u16,
+            memory: vec![0; memory_size],
+            variant,
+            quirks,
+            ..Default::default()
+        };
+        (0..FONTSET_SIZE).for_each(|e| {
+            chip.memory[(FONTSET_START_ADDRESS as usize) + (e as usize)] = FONTSET[e as usize]
         });
-        table0[0x0] = Chip8::OP_00E0;
-		table0[0xE] = Chip8::OP_00EE;
-
-		table8[0x0] = Chip8::OP_8xy0;
-		table8[0x1] = Chip8::OP_8xy1;
-		table8[0x2] = Chip8::OP_8xy2;
-		table8[0x3] = Chip8::OP_8xy3;
-		table8[0x4] = Chip8::OP_8xy4;
-		table8[0x5] = Chip8::OP_8xy5;
-		table8[0x6] = Chip8::OP_8xy6;
-		table8[0x7] = Chip8::OP_8xy7;
-		table8[0xE] = Chip8::OP_8xyE;
-
-		tableE[0x1] = Chip8::OP_ExA1;
-		tableE[0xE] = Chip8::OP_Ex9E;
-
-        (0..=0x65).into_iter().for_each(|i|{
-            tableF[i] = Chip8::OP_NULL;
+        (0..BIG_FONTSET_SIZE).for_each(|e| {
+            chip.memory[(BIG_FONTSET_START_ADDRESS as usize) + (e as usize)] = BIG_FONTSET[e as usize]
         });
-        tableF[0x07] = Chip8::OP_Fx07;
-		tableF[0x0A] = Chip8::OP_Fx0A;
-		tableF[0x15] = Chip8::OP_Fx15;
-		tableF[0x18] = Chip8::OP_Fx18;
-		tableF[0x1E] = Chip8::OP_Fx1E;
-		tableF[0x29] = Chip8::OP_Fx29;
-		tableF[0x33] = Chip8::OP_Fx33;
-		tableF[0x55] = Chip8::OP_Fx55;
-		tableF[0x65] = Chip8::OP_Fx65;
-        chip.table = table;
-        chip.table0 = table0;
-        chip.table8 = table8;
-        chip.tableE = tableE;
-        chip.tableF = tableF;
         chip
     }
 
     /// Fetch the next instruction in the form of an opcode
     /// Decode the instruction to determine what operation needs to occur
     /// Execute the instruction
-    pub fn cycle(&mut self){
+    /// The timers are not touched, see `run_frame` and `tick_timers`.
+    /// Errors leave the machine as it was when the failing instruction started, except for
+    /// memory and registers an instruction such as Fx55 had already written.
+    pub fn cycle(&mut self) -> Result<StepOutcome, EmulationError> {
+        if self.halted {
+            return Ok(StepOutcome::Halted);
+        }
 
-        self.opcode = ((self.memory[(self.pc) as usize]).overflowing_shl(8).0 | self.memory[(self.pc+1) as usize]) as u16; // fetch
-        self.pc += 2;
+        let pc = self.pc as usize;
+        let (Some(high), Some(low)) = (self.peek(pc), self.peek(pc + 1)) else {
+            return Err(EmulationError::PcOutOfBounds { pc: self.pc, state: self.machine_state() });
+        };
+        self.opcode = ((high as u16) << 8) | low as u16; // fetch
+        let state = self.machine_state();
+        if let Some(trace) = &mut self.trace {
+            trace.record(&TraceRecord::new(self.cycles, &state));
+        }
+        self.cycles += 1;
+        let Some(next) = self.pc.checked_add(2) else {
+            return Err(EmulationError::PcOutOfBounds { pc: self.pc, state });
+        };
+        self.pc = next;
 
         // Decode and Execute
-        // (self.table[((self.opcode&0xF000 ) as usize) >> 12 as u8])(self);
-        let procedure = self.table.get(((self.opcode&0xF000) as usize)>>12 );
-        procedure.expect("No Function!")(self);
+        let outcome = Instruction::decode(self.opcode)
+            .ok_or(Fault::UnknownOpcode)
+            .and_then(|instruction| self.execute(instruction))
+            .map_err(|fault| {
+                self.pc = state.pc;
+                fault.with_state(state)
+            })?;
+        Ok(outcome)
+    }
+
+    /// Executes up to `instructions_per_frame` instructions, then ticks the timers once.
+    /// Call it 60 times a second. The frame ends early when Dxyn waits for the vertical
+    /// blank or the program halts; an error ends it without ticking the timers.
+    pub fn run_frame(&mut self, instructions_per_frame: u32) -> Result<StepOutcome, EmulationError> {
+        let mut outcome = StepOutcome::Executed;
+        for _ in 0..instructions_per_frame {
+            outcome = self.cycle()?;
+            if matches!(outcome, StepOutcome::WaitingForVBlank | StepOutcome::Halted) {
+                break;
+            }
+        }
+        self.tick_timers();
+        Ok(outcome)
+    }
+
+    /// Counts the delay and sound timers down by one and marks the vertical blank.
+    /// Frontends not using `run_frame` call this at 60 Hz.
+    pub fn tick_timers(&mut self) {
         if self.delay_timer > 0{
             self.delay_timer -= 1;
         }
         if self.sound_timer >0{
             self.sound_timer -=1;
         }
+        self.vblank = true;
+    }
+
+    /// Registers, stack and timers as they are now.
+    pub fn machine_state(&self) -> MachineState {
+        MachineState {
+            pc: self.pc,
+            opcode: self.opcode,
+            index: self.index,
+            sp: self.sp,
+            registers: self.registers,
+            stack: self.stack,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        }
+    }
+
+    /// The display row by row, `video_width()` by `video_height()`, each pixel the bit
+    /// planes it is lit in: 0 for unlit, 1 for lit and, on XO-CHIP, 2 or 3 for the second
+    /// plane alone or both. `palette::Palette::apply` turns it into colours.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.video
+    }
+
+    /// Marks one of the keys 0 to F as held down; other values are ignored.
+    pub fn press_key(&mut self, key: u8) {
+        if let Some(state) = self.keypad.get_mut(key as usize) {
+            *state = 1;
+        }
+    }
+
+    /// Marks one of the keys 0 to F as released; other values are ignored.
+    pub fn release_key(&mut self, key: u8) {
+        if let Some(state) = self.keypad.get_mut(key as usize) {
+            *state = 0;
+        }
+    }
+
+    /// Whether the key is held down.
+    pub fn key_pressed(&self, key: u8) -> bool {
+        self.keypad.get(key as usize).is_some_and(|&state| state != 0)
+    }
+
+    /// The whole address space, including the interpreter area below `START_ADDRESS`.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Mutable access to memory, for debuggers patching a paused program.
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    /// Moves the program counter, for debuggers.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Sets the index register, for debuggers.
+    pub fn set_index(&mut self, index: u16) {
+        self.index = index;
+    }
 
+    /// Sets register Vx, for debuggers.
+    pub fn set_register(&mut self, x: Register, value: u8) {
+        self.registers[x.index()] = value;
     }
 
-    fn Table0(&mut self){
-        let procedure = self.table0.get((self.opcode&0x000F) as usize).expect("No such Function at table0");
-        procedure(self)
-	}
+    /// Sets the delay timer, for debuggers.
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    /// Sets the sound timer, for debuggers.
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    /// Reports every read and write instructions make to `addr` through `take_watch_hits`.
+    pub fn watch(&mut self, addr: usize) {
+        self.watched.insert(addr);
+    }
+
+    /// Stops reporting accesses to `addr`.
+    pub fn unwatch(&mut self, addr: usize) {
+        self.watched.remove(&addr);
+    }
 
-	
-	fn Table8(&mut self){
-		let procedure = self.table8.get((self.opcode&0x000F) as usize);
-        procedure.expect("No Function!")(self);
-	}
+    /// Accesses to watched addresses since the last call, oldest first.
+    pub fn take_watch_hits(&mut self) -> Vec<MemoryAccess> {
+        std::mem::take(&mut self.watch_hits)
+    }
 
-	fn TableE(&mut self){
-		let procedure = self.tableE.get((self.opcode&0x000F) as usize);
-        procedure.expect("No Function!")(self);
-	}
+    /// Reads the byte at `addr` without triggering watchpoints, for instruction fetches.
+    fn peek(&self, addr: usize) -> Option<u8> {
+        self.memory.get(addr).copied()
+    }
 
-	fn TableF(&mut self){
-		let procedure = self.table.get((self.opcode&0x00FF) as usize);
-        procedure.expect("No Function!")(self);
-	}
+    /// Reads the byte at `addr` on behalf of an instruction.
+    fn read(&mut self, addr: usize) -> Result<u8, Fault> {
+        let value = self.peek(addr).ok_or(Fault::MemoryOutOfBounds(addr))?;
+        if self.watched.contains(&addr) {
+            self.watch_hits.push(MemoryAccess { addr, value, write: false });
+        }
+        Ok(value)
+    }
+
+    /// Writes the byte at `addr` on behalf of an instruction.
+    fn write(&mut self, addr: usize, value: u8) -> Result<(), Fault> {
+        let byte = self.memory.get_mut(addr).ok_or(Fault::MemoryOutOfBounds(addr))?;
+        *byte = value;
+        if self.watched.contains(&addr) {
+            self.watch_hits.push(MemoryAccess { addr, value, write: true });
+        }
+        Ok(())
+    }
+
+    /// Runs a decoded instruction. The XO-CHIP instructions are unknown opcodes to
+    /// `Variant::Chip8`.
+    fn execute(&mut self, instruction: Instruction) -> Result<StepOutcome, Fault> {
+        if instruction.is_xo_chip() && self.variant != Variant::XoChip {
+            return Err(Fault::UnknownOpcode);
+        }
+        match instruction {
+            Instruction::ScrollDown { n } => self.OP_00Cn(n),
+            Instruction::ScrollUp { n } => self.OP_00Dn(n),
+            Instruction::Cls => self.OP_00E0(),
+            Instruction::Ret => self.OP_00EE(),
+            Instruction::ScrollRight => self.OP_00FB(),
+            Instruction::ScrollLeft => self.OP_00FC(),
+            Instruction::Exit => self.OP_00FD(),
+            Instruction::LowRes => self.OP_00FE(),
+            Instruction::HighRes => self.OP_00FF(),
+            Instruction::Jump { nnn } => self.OP_1nnn(nnn),
+            Instruction::Call { nnn } => self.OP_2nnn(nnn),
+            Instruction::SkipEqByte { x, kk } => self.OP_3xkk(x, kk),
+            Instruction::SkipNeByte { x, kk } => self.OP_4xkk(x, kk),
+            Instruction::SkipEq { x, y } => self.OP_5xy0(x, y),
+            Instruction::SaveRange { x, y } => self.OP_5xy2(x, y),
+            Instruction::LoadRange { x, y } => self.OP_5xy3(x, y),
+            Instruction::LoadByte { x, kk } => self.OP_6xkk(x, kk),
+            Instruction::AddByte { x, kk } => self.OP_7xkk(x, kk),
+            Instruction::Move { x, y } => self.OP_8xy0(x, y),
+            Instruction::Or { x, y } => self.OP_8xy1(x, y),
+            Instruction::And { x, y } => self.OP_8xy2(x, y),
+            Instruction::Xor { x, y } => self.OP_8xy3(x, y),
+            Instruction::Add { x, y } => self.OP_8xy4(x, y),
+            Instruction::Sub { x, y } => self.OP_8xy5(x, y),
+            Instruction::ShiftRight { x, y } => self.OP_8xy6(x, y),
+            Instruction::SubN { x, y } => self.OP_8xy7(x, y),
+            Instruction::ShiftLeft { x, y } => self.OP_8xyE(x, y),
+            Instruction::SkipNe { x, y } => self.OP_9xy0(x, y),
+            Instruction::LoadIndex { nnn } => self.OP_Annn(nnn),
+            Instruction::JumpOffset { nnn } => self.OP_Bnnn(nnn),
+            Instruction::Random { x, kk } => self.OP_Cxkk(x, kk),
+            Instruction::Draw { x, y, n } => self.OP_Dxyn(x, y, n),
+            Instruction::SkipKey { x } => self.OP_Ex9E(x),
+            Instruction::SkipNotKey { x } => self.OP_ExA1(x),
+            Instruction::LoadIndexLong => self.OP_F000(),
+            Instruction::Plane { n } => self.OP_Fn01(n),
+            Instruction::Audio => self.OP_F002(),
+            Instruction::GetDelay { x } => self.OP_Fx07(x),
+            Instruction::WaitKey { x } => self.OP_Fx0A(x),
+            Instruction::SetDelay { x } => self.OP_Fx15(x),
+            Instruction::SetSound { x } => self.OP_Fx18(x),
+            Instruction::AddIndex { x } => self.OP_Fx1E(x),
+            Instruction::Font { x } => self.OP_Fx29(x),
+            Instruction::BigFont { x } => self.OP_Fx30(x),
+            Instruction::Bcd { x } => self.OP_Fx33(x),
+            Instruction::Pitch { x } => self.OP_Fx3A(x),
+            Instruction::Store { x } => self.OP_Fx55(x),
+            Instruction::Restore { x } => self.OP_Fx65(x),
+            Instruction::SaveFlags { x } => self.OP_Fx75(x),
+            Instruction::LoadFlags { x } => self.OP_Fx85(x),
+        }
+    }
 
-    fn OP_NULL(&mut self){}
     /// CLS
     /// Clear the display.
-    fn OP_00E0(&mut self) {
-        self.video.fill_with(|| 0);
+    fn OP_00E0(&mut self) -> Result<StepOutcome, Fault> {
+        let mask = self.plane_mask;
+        self.video.iter_mut().for_each(|pixel| *pixel &= !mask);
+        Ok(StepOutcome::Executed)
     }
 
-    /// RST
+    /// RET
     /// Return from a subroutine.
-    fn OP_00EE(&mut self) {
+    fn OP_00EE(&mut self) -> Result<StepOutcome, Fault> {
+        if self.sp == 0 {
+            return Err(Fault::StackUnderflow);
+        }
         self.sp -= 1;
         self.pc = self.stack[self.sp as usize];
+        Ok(StepOutcome::Executed)
+    }
+
+    /// SCD nibble
+    /// Scroll the display down by n pixels.
+    fn OP_00Cn(&mut self, n: u8) -> Result<StepOutcome, Fault> {
+        self.scroll(0, n as i32);
+        Ok(StepOutcome::Executed)
+    }
+
+    /// SCU nibble
+    /// Scroll the display up by n pixels (XO-CHIP).
+    fn OP_00Dn(&mut self, n: u8) -> Result<StepOutcome, Fault> {
+        self.scroll(0, -(n as i32));
+        Ok(StepOutcome::Executed)
+    }
+
+    /// SCR
+    /// Scroll the display right by 4 pixels.
+    fn OP_00FB(&mut self) -> Result<StepOutcome, Fault> {
+        self.scroll(4, 0);
+        Ok(StepOutcome::Executed)
+    }
+
+    /// SCL
+    /// Scroll the display left by 4 pixels.
+    fn OP_00FC(&mut self) -> Result<StepOutcome, Fault> {
+        self.scroll(-4, 0);
+        Ok(StepOutcome::Executed)
+    }
+
+    /// EXIT
+    /// Stop the interpreter.
+    fn OP_00FD(&mut self) -> Result<StepOutcome, Fault> {
+        self.halted = true;
+        Ok(StepOutcome::Executed)
+    }
+
+    /// LOW
+    /// Switch to the 64x32 display and clear it.
+    fn OP_00FE(&mut self) -> Result<StepOutcome, Fault> {
+        self.set_resolution(false);
+        Ok(StepOutcome::Executed)
+    }
+
+    /// HIGH
+    /// Switch to the 128x64 display and clear it.
+    fn OP_00FF(&mut self) -> Result<StepOutcome, Fault> {
+        self.set_resolution(true);
+        Ok(StepOutcome::Executed)
     }
 
     /// JP addr
     /// Jump to location nnn.
-    fn OP_1nnn(&mut self) {
-        self.pc = self.opcode & 0x0FFF;
+    fn OP_1nnn(&mut self, nnn: u16) -> Result<StepOutcome, Fault> {
+        self.pc = nnn;
+        Ok(StepOutcome::Executed)
     }
 
     /// CALL addr
     /// Call subroutine at nnn.
-    fn OP_2nnn(&mut self) {
+    fn OP_2nnn(&mut self, nnn: u16) -> Result<StepOutcome, Fault> {
+        if self.sp as usize >= self.stack.len() {
+            return Err(Fault::StackOverflow);
+        }
         self.stack[self.sp as usize] = self.pc;
         self.sp += 1;
-        self.pc = self.opcode & 0x0FFF;
+        self.pc = nnn;
+        Ok(StepOutcome::Executed)
     }
 
     /// SE Vx, byte
     /// Skip next instruction if Vx = kk.
-    fn OP_3xkk(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let byte = (self.opcode & 0x00FF) as u8;
-        if self.registers[Vx as usize] == byte {
-            self.pc += 2;
+    fn OP_3xkk(&mut self, x: Register, kk: u8) -> Result<StepOutcome, Fault> {
+        if self.registers[x.index()] == kk {
+            self.skip_next()?;
         }
+        Ok(StepOutcome::Executed)
     }
 
     /// SNE Vx, byte
     /// Skip next instruction if Vx != kk.
-    fn OP_4xkk(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let byte = (self.opcode & 0x00FF) as u8;
-        if self.registers[Vx as usize] != byte {
-            self.pc += 2;
+    fn OP_4xkk(&mut self, x: Register, kk: u8) -> Result<StepOutcome, Fault> {
+        if self.registers[x.index()] != kk {
+            self.skip_next()?;
         }
+        Ok(StepOutcome::Executed)
     }
     /// SE Vx, Vy
     /// Skip next instruction if Vx = Vy.
-    fn OP_5xy0(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-        if self.registers[Vx as usize] == self.registers[Vy as usize] {
-            self.pc += 2;
+    fn OP_5xy0(&mut self, x: Register, y: Register) -> Result<StepOutcome, Fault> {
+        if self.registers[x.index()] == self.registers[y.index()] {
+            self.skip_next()?;
         }
+        Ok(StepOutcome::Executed)
+    }
+
+    /// SAVE Vx - Vy
+    /// Store registers Vx through Vy in memory starting at location I, I is left unchanged (XO-CHIP).
+    /// The registers are stored in reverse order when x > y.
+    fn OP_5xy2(&mut self, x: Register, y: Register) -> Result<StepOutcome, Fault> {
+        let (x, y) = (x.index(), y.index());
+        for i in 0..=x.abs_diff(y) {
+            let register = if x <= y { x + i } else { x - i };
+            self.write((self.index as usize) + i, self.registers[register])?;
+        }
+        Ok(StepOutcome::Executed)
+    }
+
+    /// LOAD Vx - Vy
+    /// Read registers Vx through Vy from memory starting at location I, I is left unchanged (XO-CHIP).
+    /// The registers are loaded in reverse order when x > y.
+    fn OP_5xy3(&mut self, x: Register, y: Register) -> Result<StepOutcome, Fault> {
+        let (x, y) = (x.index(), y.index());
+        for i in 0..=x.abs_diff(y) {
+            let register = if x <= y { x + i } else { x - i };
+            self.registers[register] = self.read((self.index as usize) + i)?;
+        }
+        Ok(StepOutcome::Executed)
     }
 
     /// LD Vx, byte
     /// Set Vx = kk.
-    fn OP_6xkk(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let byte = (self.opcode & 0x00FF) as u8;
-        self.registers[Vx as usize] = byte;
+    fn OP_6xkk(&mut self, x: Register, kk: u8) -> Result<StepOutcome, Fault> {
+        self.registers[x.index()] = kk;
+        Ok(StepOutcome::Executed)
     }
 
     /// ADD Vx, byte
     /// Set Vx = Vx + kk.
-    fn OP_7xkk(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let byte = (self.opcode & 0x00FF) as u8;
-        self.registers[Vx as usize] = (self.registers[Vx as usize]).overflowing_add(byte).0;
+    fn OP_7xkk(&mut self, x: Register, kk: u8) -> Result<StepOutcome, Fault> {
+        self.registers[x.index()] = (self.registers[x.index()]).overflowing_add(kk).0;
+        Ok(StepOutcome::Executed)
     }
     /// LD Vx, Vy
     /// Set Vx = Vy.
-    fn OP_8xy0(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-        self.registers[Vx as usize] = self.registers[Vy as usize];
+    fn OP_8xy0(&mut self, x: Register, y: Register) -> Result<StepOutcome, Fault> {
+        self.registers[x.index()] = self.registers[y.index()];
+        Ok(StepOutcome::Executed)
     }
 
     /// OR Vx, Vy
     /// Set Vx = Vx OR Vy.
-    fn OP_8xy1(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-        self.registers[Vx as usize] |= self.registers[Vy as usize];
+    fn OP_8xy1(&mut self, x: Register, y: Register) -> Result<StepOutcome, Fault> {
+        self.registers[x.index()] |= self.registers[y.index()];
+        if self.quirks.logic_resets_vf {
+            self.registers[0xF] = 0;
+        }
+        Ok(StepOutcome::Executed)
     }
 
     /// AND Vx, Vy
     /// Set Vx = Vx OR Vy.
-    fn OP_8xy2(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-        self.registers[Vx as usize] &= self.registers[Vy as usize];
+    fn OP_8xy2(&mut self, x: Register, y: Register) -> Result<StepOutcome, Fault> {
+        self.registers[x.index()] &= self.registers[y.index()];
+        if self.quirks.logic_resets_vf {
+            self.registers[0xF] = 0;
+        }
+        Ok(StepOutcome::Executed)
     }
 
     /// XOR Vx, Vy
     /// Set Vx = Vx XOR Vy.
-    fn OP_8xy3(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-        self.registers[Vx as usize] ^= self.registers[Vy as usize];
+    fn OP_8xy3(&mut self, x: Register, y: Register) -> Result<StepOutcome, Fault> {
+        self.registers[x.index()] ^= self.registers[y.index()];
+        if self.quirks.logic_resets_vf {
+            self.registers[0xF] = 0;
+        }
+        Ok(StepOutcome::Executed)
     }
     /// ADD Vx, Vy
     /// Set Vx = Vx + Vy, set VF = carry.
     /// The values of Vx and Vy are added together.
     /// If the result is greater than 8 bits (i.e., > 255,) VF is set to 1,
     /// otherwise 0. Only the lowest 8 bits of the result are kept, and stored in Vx.
-    fn OP_8xy4(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-        let sum = (self.registers[Vx as usize] + self.registers[Vy as usize]) as u16;
+    fn OP_8xy4(&mut self, x: Register, y: Register) -> Result<StepOutcome, Fault> {
+        let sum = self.registers[x.index()] as u16 + self.registers[y.index()] as u16;
         if sum > 255 {
             self.registers[0xF] = 1;
         } else {
             self.registers[0xF] = 0;
         }
-        self.registers[Vx as usize] = (sum & 0xFF) as u8;
+        self.registers[x.index()] = (sum & 0xFF) as u8;
+        Ok(StepOutcome::Executed)
     }
     /// SUB Vx, Vy
     /// Set Vx = Vx - Vy, set VF = NOT borrow.
     /// If Vx > Vy, then VF is set to 1, otherwise 0.
     /// Then Vy is subtracted from Vx, and the results stored in Vx.
-    fn OP_8xy5(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-        if self.registers[Vx as usize] > self.registers[Vy as usize] {
+    fn OP_8xy5(&mut self, x: Register, y: Register) -> Result<StepOutcome, Fault> {
+        if self.registers[x.index()] > self.registers[y.index()] {
             self.registers[0xF] = 1;
         } else {
             self.registers[0xF] = 0;
         }
-        self.registers[Vx as usize] -= self.registers[Vy as usize];
+        self.registers[x.index()] = self.registers[x.index()].wrapping_sub(self.registers[y.index()]);
+        Ok(StepOutcome::Executed)
     }
-    /// SHR Vx
+    /// SHR Vx {, Vy}
     /// If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0.
     /// Then Vx is divided by 2.
     /// A right shift is performed (division by 2),
     /// and the least significant bit is saved in Register VF.
-    fn OP_8xy6(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        self.registers[0xF] = self.registers[Vx as usize] & 0x1;
-        self.registers[Vx as usize] >>= 1;
+    /// With the shift_uses_vy quirk, Vy is shifted and the result stored in Vx.
+    fn OP_8xy6(&mut self, x: Register, y: Register) -> Result<StepOutcome, Fault> {
+        let value = if self.quirks.shift_uses_vy {
+            self.registers[y.index()]
+        } else {
+            self.registers[x.index()]
+        };
+        self.registers[x.index()] = value >> 1;
+        self.registers[0xF] = value & 0x1;
+        Ok(StepOutcome::Executed)
     }
     /// SUBN Vx, Vy
     /// Set Vx = Vy - Vx, set VF = NOT borrow.
     /// If Vy > Vx, then VF is set to 1, otherwise 0.
     /// Then Vx is subtracted from Vy, and the results stored in Vx.
-    fn OP_8xy7(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-        if self.registers[Vx as usize] < self.registers[Vy as usize] {
+    fn OP_8xy7(&mut self, x: Register, y: Register) -> Result<StepOutcome, Fault> {
+        if self.registers[x.index()] < self.registers[y.index()] {
             self.registers[0xF] = 1;
         } else {
             self.registers[0xF] = 0;
         }
-        self.registers[Vx as usize] = self.registers[Vy as usize] - self.registers[Vx as usize];
+        self.registers[x.index()] = self.registers[y.index()].wrapping_sub(self.registers[x.index()]);
+        Ok(StepOutcome::Executed)
     }
     /// SHL Vx {, Vy}
     /// Set Vx = Vx SHL 1.
     /// If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is multiplied by 2.
     /// A left shift is performed (multiplication by 2), and the most significant bit is saved in Register VF.
-    fn OP_8xyE(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        // save most significant byte in VF
-        self.registers[0xF] = (self.registers[Vx as usize] & 0x80) >> 7;
-        self.registers[Vx as usize] <<= 1;
+    /// With the shift_uses_vy quirk, Vy is shifted and the result stored in Vx.
+    fn OP_8xyE(&mut self, x: Register, y: Register) -> Result<StepOutcome, Fault> {
+        let value = if self.quirks.shift_uses_vy {
+            self.registers[y.index()]
+        } else {
+            self.registers[x.index()]
+        };
+        self.registers[x.index()] = value << 1;
+        // save most significant bit in VF
+        self.registers[0xF] = (value & 0x80) >> 7;
+        Ok(StepOutcome::Executed)
     }
 
     /// SNE Vx, Vy
     /// Skip next instruction if Vx != Vy.
-    fn OP_9xy0(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-        if self.registers[Vx as usize] != self.registers[Vy as usize] {
-            self.pc += 2;
+    fn OP_9xy0(&mut self, x: Register, y: Register) -> Result<StepOutcome, Fault> {
+        if self.registers[x.index()] != self.registers[y.index()] {
+            self.skip_next()?;
         }
+        Ok(StepOutcome::Executed)
     }
 
     /// LD I, addr
     /// Set I = nnn.
-    fn OP_Annn(&mut self) {
-        self.index = (self.opcode & 0x0FFF) as u16;
+    fn OP_Annn(&mut self, nnn: u16) -> Result<StepOutcome, Fault> {
+        self.index = nnn;
+        Ok(StepOutcome::Executed)
     }
 
     /// JP V0, addr
     /// Jump to location nnn + V0.
-    fn OP_Bnnn(&mut self) {
-        let address = (self.opcode & 0x0FFF) as u16;
-        self.pc = (self.registers[0] as u16) + address;
+    /// With the jump_with_vx quirk, jump to location xnn + Vx.
+    fn OP_Bnnn(&mut self, nnn: u16) -> Result<StepOutcome, Fault> {
+        let offset = if self.quirks.jump_with_vx {
+            self.registers[(nnn >> 8) as usize]
+        } else {
+            self.registers[0]
+        };
+        self.pc = (offset as u16) + nnn;
+        Ok(StepOutcome::Executed)
     }
 
     /// RND Vx, byte
     /// Set Vx = random byte AND kk.
-    fn OP_Cxkk(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let byte = (self.opcode & 0x00FF) as u8;
-        self.registers[Vx as usize] = ((self.rand_gen.next_u32() % 256) as u8) & byte;
+    fn OP_Cxkk(&mut self, x: Register, kk: u8) -> Result<StepOutcome, Fault> {
+        self.registers[x.index()] = self.rand_gen.next_byte() & kk;
+        Ok(StepOutcome::Executed)
     }
     /// DRW Vx, Vy, nibble
     /// Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
-    fn OP_Dxyn(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let Vy = ((self.opcode & 0x00F0) >> 4) as u8;
-        let height = (self.opcode & 0x000F) as u8;
+    /// When n is 0, a 16x16 sprite made of 32 bytes is drawn instead (SUPER-CHIP).
+    /// With several XO-CHIP planes selected, one sprite is read per plane, one after the other.
+    /// The starting position always wraps; pixels running off the edge are clipped
+    /// or wrapped depending on the clip_sprites quirk.
+    fn OP_Dxyn(&mut self, x: Register, y: Register, n: u8) -> Result<StepOutcome, Fault> {
+        if self.quirks.display_wait && !self.vblank {
+            // try again once the display has been refreshed
+            self.pc -= 2;
+            return Ok(StepOutcome::WaitingForVBlank);
+        }
+        self.vblank = false;
 
-        let x_pos = self.registers[Vx as usize] % (VIDEO_WIDTH as u8);
-        let y_pos = self.registers[Vy as usize] % (VIDEO_HEIGHT as u8);
+        let (sprite_width, height) = match n as u32 {
+            0 => (16, 16),
+            n => (8, n),
+        };
+        let bytes_per_row = sprite_width / 8;
+        let width = self.video_width();
+        let video_height = self.video_height();
+
+        let x_pos = (self.registers[x.index()] as u32) % width;
+        let y_pos = (self.registers[y.index()] as u32) % video_height;
 
         self.registers[0xF] = 0;
-        (0..height).into_iter().for_each(|e| {
-            let sprite_byte = self.memory[(self.index + (e as u16)) as usize];
-            (0..8).into_iter().for_each(|c| {
-                let sprite_pixel = sprite_byte & (0x80 >> c);
-                let screen_pixel = &mut self.video
-                    [((y_pos + e) as usize) * ((VIDEO_WIDTH as u8) + (x_pos + (c) as u8)) as usize];
-                if sprite_pixel != 0 {
-                    if *screen_pixel == 0xFFFFFFFF {
-                        self.registers[0xF] = 1;
-                    }
-                    *screen_pixel ^=0xFFFFFFFF;
+        let mut sprite_address = self.index as usize;
+        let plane_mask = self.plane_mask;
+        for plane in (0..2u8).map(|p| 1 << p).filter(|plane| plane_mask & plane != 0) {
+            for e in 0..height {
+                let row_address = sprite_address + (e * bytes_per_row) as usize;
+                let mut sprite_row = 0u32;
+                for b in 0..bytes_per_row {
+                    sprite_row = (sprite_row << 8) | self.read(row_address + b as usize)? as u32;
                 }
-            })
-        });
+                (0..sprite_width).for_each(|c| {
+                    let (mut x, mut y) = (x_pos + c, y_pos + e);
+                    if x >= width || y >= video_height {
+                        if self.quirks.clip_sprites {
+                            return;
+                        }
+                        x %= width;
+                        y %= video_height;
+                    }
+                    let sprite_pixel = sprite_row & (1 << (sprite_width - 1 - c));
+                    let screen_pixel = &mut self.video[(y * width + x) as usize];
+                    if sprite_pixel != 0 {
+                        if *screen_pixel & plane != 0 {
+                            self.registers[0xF] = 1;
+                        }
+                        *screen_pixel ^= plane;
+                    }
+                })
+            }
+            sprite_address += (height * bytes_per_row) as usize;
+        }
+        Ok(StepOutcome::Executed)
     }
 
     /// SKP Vx
     /// Skip next instruction if key with the value of Vx is pressed.
-    fn OP_Ex9E(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let key = self.registers[Vx as usize];
+    fn OP_Ex9E(&mut self, x: Register) -> Result<StepOutcome, Fault> {
+        let key = self.registers[x.index()] & 0x0F;
         if self.keypad[key as usize] != 0 {
-            self.pc += 2;
+            self.skip_next()?;
         }
+        Ok(StepOutcome::Executed)
     }
 
     /// SKNP Vx
     /// Skip next instruction if key with the value of Vx is not pressed.
-    fn OP_ExA1(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let key = self.registers[Vx as usize];
+    fn OP_ExA1(&mut self, x: Register) -> Result<StepOutcome, Fault> {
+        let key = self.registers[x.index()] & 0x0F;
         if self.keypad[key as usize] == 0 {
-            self.pc += 2;
+            self.skip_next()?;
         }
+        Ok(StepOutcome::Executed)
     }
 
     /// LD Vx, DT
     /// Set Vx = delay timer value.
-    fn OP_Fx07(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        self.registers[Vx as usize] = self.delay_timer;
+    fn OP_Fx07(&mut self, x: Register) -> Result<StepOutcome, Fault> {
+        self.registers[x.index()] = self.delay_timer;
+        Ok(StepOutcome::Executed)
     }
 
     /// LD Vx, K
     /// Wait for a key press, store the value of the key in Vx.
-    fn OP_Fx0A(&mut self) {
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
+    fn OP_Fx0A(&mut self, x: Register) -> Result<StepOutcome, Fault> {
         if self.keypad[0] == 1 {
-            self.registers[Vx as usize] = 0;
+            self.registers[x.index()] = 0;
         } else if self.keypad[1] == 1 {
-            self.registers[Vx as usize] = 1;
+            self.registers[x.index()] = 1;
         } else if self.keypad[2] == 1 {
-            self.registers[Vx as usize] = 2;
+            self.registers[x.index()] = 2;
         } else if self.keypad[3] == 1 {
-            self.registers[Vx as usize] = 3;
+            self.registers[x.index()] = 3;
         } else if self.keypad[4] == 1 {
-            self.registers[Vx as usize] = 4;
+            self.registers[x.index()] = 4;
         } else if self.keypad[5] == 1 {
-            self.registers[Vx as usize] = 5;
+            self.registers[x.index()] = 5;
         } else if self.keypad[6] == 1 {
-            self.registers[Vx as usize] = 6;
+            self.registers[x.index()] = 6;
         } else if self.keypad[7] == 1 {
-            self.registers[Vx as usize] = 7;
+            self.registers[x.index()] = 7;
         } else if self.keypad[8] == 1 {
-            self.registers[Vx as usize] = 8;
+            self.registers[x.index()] = 8;
         } else if self.keypad[9] == 1 {
-            self.registers[Vx as usize] = 9;
+            self.registers[x.index()] = 9;
         } else if self.keypad[10] == 1 {
-            self.registers[Vx as usize] = 10;
+            self.registers[x.index()] = 10;
         } else if self.keypad[11] == 1 {
-            self.registers[Vx as usize] = 11;
+            self.registers[x.index()] = 11;
         } else if self.keypad[12] == 1 {
-            self.registers[Vx as usize] = 12;
+            self.registers[x.index()] = 12;
         } else if self.keypad[13] == 1 {
-            self.registers[Vx as usize] = 13;
+            self.registers[x.index()] = 13;
         } else if self.keypad[14] == 1 {
-            self.registers[Vx as usize] = 14;
+            self.registers[x.index()] = 14;
         } else if self.keypad[15] == 1 {
-            self.registers[Vx as usize] = 15;
+            self.registers[x.index()] = 15;
         } else {
             self.pc -= 2;
+            return Ok(StepOutcome::WaitingForKey);
         }
+        Ok(StepOutcome::Executed)
     }
 
     /// LD DT, Vx
     /// Set delay timer = Vx.
-    fn OP_Fx15(&mut self){
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        self.delay_timer = self.registers[Vx as usize];
+    fn OP_Fx15(&mut self, x: Register) -> Result<StepOutcome, Fault> {
+        self.delay_timer = self.registers[x.index()];
+        Ok(StepOutcome::Executed)
     }
 
     /// LD ST, Vx
     /// Set sound timer = Vx.
-    fn OP_Fx18(&mut self){
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        self.sound_timer = self.registers[Vx as usize];
+    fn OP_Fx18(&mut self, x: Register) -> Result<StepOutcome, Fault> {
+        self.sound_timer = self.registers[x.index()];
+        Ok(StepOutcome::Executed)
     }
 
     /// ADD I, Vx
     /// Set I = I + Vx.
-    fn OP_Fx1E(&mut self){
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        self.index += (self.registers[Vx as usize]) as u16;
+    fn OP_Fx1E(&mut self, x: Register) -> Result<StepOutcome, Fault> {
+        self.index = self.index.wrapping_add((self.registers[x.index()]) as u16);
+        Ok(StepOutcome::Executed)
     }
 
     /// LD F, Vx
     /// Set I = location of sprite for digit Vx.
-    fn OP_Fx29(&mut self){
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let digit = self.registers[Vx as usize] as u16;
+    fn OP_Fx29(&mut self, x: Register) -> Result<StepOutcome, Fault> {
+        let digit = self.registers[x.index()] as u16;
         self.index = FONTSET_START_ADDRESS + (5*digit);
+        Ok(StepOutcome::Executed)
+    }
+
+    /// LD I, long addr
+    /// Set I = the 16-bit address stored in the next two bytes, which are then skipped (XO-CHIP).
+    fn OP_F000(&mut self) -> Result<StepOutcome, Fault> {
+        let address = self.pc as usize;
+        self.index = ((self.read(address)? as u16) << 8) | self.read(address + 1)? as u16;
+        self.pc = self.pc.checked_add(2).ok_or(Fault::PcOutOfBounds)?;
+        Ok(StepOutcome::Executed)
+    }
+
+    /// PLANE n
+    /// Select the bit planes drawing instructions operate on (XO-CHIP).
+    fn OP_Fn01(&mut self, n: u8) -> Result<StepOutcome, Fault> {
+        self.plane_mask = n & 0x3;
+        Ok(StepOutcome::Executed)
+    }
+
+    /// AUDIO
+    /// Load the 16-byte audio pattern buffer from memory starting at location I (XO-CHIP).
+    fn OP_F002(&mut self) -> Result<StepOutcome, Fault> {
+        let start = self.index as usize;
+        for i in 0..self.audio_pattern.len() {
+            self.audio_pattern[i] = self.read(start + i)?;
+        }
+        Ok(StepOutcome::Executed)
+    }
+
+    /// LD HF, Vx
+    /// Set I = location of the 10-byte big sprite for digit Vx.
+    fn OP_Fx30(&mut self, x: Register) -> Result<StepOutcome, Fault> {
+        let digit = (self.registers[x.index()] & 0x0F) as u16;
+        self.index = BIG_FONTSET_START_ADDRESS + (10*digit);
+        Ok(StepOutcome::Executed)
+    }
+
+    /// PITCH Vx
+    /// Set the audio pattern playback rate = Vx (XO-CHIP).
+    fn OP_Fx3A(&mut self, x: Register) -> Result<StepOutcome, Fault> {
+        self.pitch = self.registers[x.index()];
+        Ok(StepOutcome::Executed)
     }
 
     /// LD B, Vx
     /// Store BCD representation of Vx in memory locations I, I+1, and I+2.
     /// The interpreter takes the decimal value of Vx, and places the hundreds digit in memory at location in I,
     /// the tens digit at location I+1, and the ones digit at location I+2.
-    fn OP_Fx33(&mut self){
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        let mut value = self.registers[Vx as usize];
-        self.memory[(self.index) as usize + 2] = value % 10;
+    fn OP_Fx33(&mut self, x: Register) -> Result<StepOutcome, Fault> {
+        let mut value = self.registers[x.index()];
+        self.write((self.index) as usize + 2, value % 10)?;
         value /= 10;
 
-        self.memory[(self.index) as usize + 1] = value % 10;
+        self.write((self.index) as usize + 1, value % 10)?;
         value /= 10;
 
-        self.memory[self.index as usize] = value % 10;
+        self.write(self.index as usize, value % 10)?;
+        Ok(StepOutcome::Executed)
     }
 
     /// LD [I], Vx
     /// Store registers V0 through Vx in memory starting at location I.
-    fn OP_Fx55(&mut self){
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        (0..=Vx).into_iter().for_each(|i|{
-            self.memory[(self.index + (i as u16)) as usize] = self.registers[i as usize];
-        });
+    fn OP_Fx55(&mut self, x: Register) -> Result<StepOutcome, Fault> {
+        for i in 0..=x.index() {
+            self.write((self.index as usize) + i, self.registers[i])?;
+        }
+        if self.quirks.load_store_increments_i {
+            self.index = self.index.wrapping_add((x.index() as u16) + 1);
+        }
+        Ok(StepOutcome::Executed)
     }
 
 
     /// LD Vx, [I]
     /// Read registers V0 through Vx from memory starting at location I.
-    fn OP_Fx65(&mut self){
-        let Vx = ((self.opcode & 0x0F00) >> 8) as u8;
-        (0..=Vx).into_iter().for_each(|i|{
-            self.registers[i as usize] = self.memory[(self.index + (i as u16)) as usize];
+    fn OP_Fx65(&mut self, x: Register) -> Result<StepOutcome, Fault> {
+        for i in 0..=x.index() {
+            self.registers[i] = self.read((self.index as usize) + i)?;
+        }
+        if self.quirks.load_store_increments_i {
+            self.index = self.index.wrapping_add((x.index() as u16) + 1);
+        }
+        Ok(StepOutcome::Executed)
+    }
+
+
+    /// LD R, Vx
+    /// Store registers V0 through Vx in the RPL user flags.
+    fn OP_Fx75(&mut self, x: Register) -> Result<StepOutcome, Fault> {
+        self.rpl_flags[..=x.index()].copy_from_slice(&self.registers[..=x.index()]);
+        Ok(StepOutcome::Executed)
+    }
+
+    /// LD Vx, R
+    /// Read registers V0 through Vx from the RPL user flags.
+    fn OP_Fx85(&mut self, x: Register) -> Result<StepOutcome, Fault> {
+        self.registers[..=x.index()].copy_from_slice(&self.rpl_flags[..=x.index()]);
+        Ok(StepOutcome::Executed)
+    }
+
+    /// Width of the display in the current resolution.
+    pub fn video_width(&self) -> u32 {
+        if self.hires { HIRES_VIDEO_WIDTH } else { VIDEO_WIDTH }
+    }
+
+    /// Height of the display in the current resolution.
+    pub fn video_height(&self) -> u32 {
+        if self.hires { HIRES_VIDEO_HEIGHT } else { VIDEO_HEIGHT }
+    }
+
+    /// Whether 00FD has stopped the interpreter.
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// XO-CHIP audio pattern buffer and its playback rate in Hz, `4000 * 2^((pitch - 64) / 48)`.
+    pub fn audio_pattern(&self) -> (&[u8; 0x10], f32) {
+        let rate = 4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0);
+        (&self.audio_pattern, rate)
+    }
+
+    /// Switches between the low and high resolution displays, resizing and clearing `video`.
+    fn set_resolution(&mut self, hires: bool) {
+        self.hires = hires;
+        self.video = vec![0; (self.video_width() * self.video_height()) as usize];
+    }
+
+    /// Skips the next instruction, which is four bytes long when it is the XO-CHIP long index load.
+    fn skip_next(&mut self) -> Result<(), Fault> {
+        let address = self.pc as usize;
+        let long_load = self.variant == Variant::XoChip
+            && self.peek(address) == Some(0xF0)
+            && self.peek(address + 1) == Some(0x00);
+        self.pc = self.pc.checked_add(if long_load { 4 } else { 2 }).ok_or(Fault::PcOutOfBounds)?;
+        Ok(())
+    }
+
+    /// Moves the selected bit planes by (dx, dy) pixels, filling the uncovered area with unlit pixels.
+    fn scroll(&mut self, dx: i32, dy: i32) {
+        let width = self.video_width() as i32;
+        let height = self.video_height() as i32;
+        let mask = self.plane_mask;
+        let source = self.video.clone();
+        (0..height).for_each(|y| {
+            (0..width).for_each(|x| {
+                let (from_x, from_y) = (x - dx, y - dy);
+                let moved = if (0..width).contains(&from_x) && (0..height).contains(&from_y) {
+                    source[(from_y * width + from_x) as usize] & mask
+                } else {
+                    0
+                };
+                let pixel = &mut self.video[(y * width + x) as usize];
+                *pixel = (*pixel & !mask) | moved;
+            })
         });
     }
 
+    /// Restarts the random number generator behind Cxkk from `seed`, so that the same
+    /// seed and the same input replay a program exactly.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rand_gen = Box::new(Seeded::new(seed));
+    }
+
+    /// Replaces the random number generator behind Cxkk.
+    pub fn set_rng(&mut self, source: Box<dyn RandomSource>) {
+        self.rand_gen = source;
+    }
+
+    /// Sends every instruction `cycle` starts to `sink`, with the machine state
+    /// before it runs.
+    pub fn set_trace(&mut self, sink: Box<dyn TraceSink>) {
+        self.trace = Some(sink);
+    }
 
-    /// loads the contents of a ROM file.
-    pub fn load_ROM(&mut self, filename: String) -> io::Result<()> {
-        let file = std::fs::File::open(filename).unwrap();
-        let file_size = file.metadata().unwrap().len() as usize;
-        let mut buffer: Vec<u8> = vec![0;file_size];
-        let mut reader = BufReader::new(file);
-        reader.seek(io::SeekFrom::Start(0)).unwrap();
-        reader.read(&mut buffer).unwrap();
-        (0..file_size)
-            .into_iter()
-            .for_each(|e| self.memory[(START_ADDRESS + ((e) as u32)) as usize] = buffer[e]);
+    /// Stops tracing, returning the sink so it can be flushed.
+    pub fn take_trace(&mut self) -> Option<Box<dyn TraceSink>> {
+        self.trace.take()
+    }
+
+    /// Instructions started since power-on, the cycle count in traces.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Instruction set this interpreter was built for.
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// Serializes the machine: memory, registers, stack, timers, keypad, the bit planes
+    /// `video` is drawn from, the current opcode and the RNG state.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.u8(match self.variant {
+            Variant::Chip8 => 0,
+            Variant::XoChip => 1,
+        });
+        let quirks = self.quirks;
+        let flags = [
+            quirks.shift_uses_vy,
+            quirks.load_store_increments_i,
+            quirks.jump_with_vx,
+            quirks.logic_resets_vf,
+            quirks.clip_sprites,
+            quirks.display_wait,
+        ];
+        state.u8(flags.iter().enumerate().fold(0, |bits, (i, &set)| bits | (set as u8) << i));
+        state.block(&self.memory);
+        state.bytes(&self.registers);
+        state.u16(self.index);
+        state.u16(self.pc);
+        self.stack.iter().for_each(|&address| state.u16(address));
+        state.u8(self.sp);
+        state.u8(self.delay_timer);
+        state.u8(self.sound_timer);
+        state.bytes(&self.keypad);
+        state.u8(self.hires as u8);
+        state.block(&self.video);
+        state.u8(self.plane_mask);
+        state.bytes(&self.audio_pattern);
+        state.u8(self.pitch);
+        state.u8(self.halted as u8);
+        state.bytes(&self.rpl_flags);
+        state.u16(self.opcode);
+        state.u8(self.vblank as u8);
+        state.block(self.rand_gen.name().as_bytes());
+        state.block(&self.rand_gen.state());
+        state.finish()
+    }
+
+    /// Restores a state written by `save_state`, including its variant and quirks.
+    /// Invalid states are rejected without changing the machine, as are states saved
+    /// with another random number generator than the one in use.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut state = StateReader::new(data)?;
+        let variant = match state.u8()? {
+            0 => Variant::Chip8,
+            1 => Variant::XoChip,
+            _ => return Err(StateError::Invalid("variant")),
+        };
+        let flags = state.u8()?;
+        let quirks = Quirks {
+            shift_uses_vy: flags & 0x01 != 0,
+            load_store_increments_i: flags & 0x02 != 0,
+            jump_with_vx: flags & 0x04 != 0,
+            logic_resets_vf: flags & 0x08 != 0,
+            clip_sprites: flags & 0x10 != 0,
+            display_wait: flags & 0x20 != 0,
+        };
+        let mut chip = Chip8::with_variant(variant, quirks);
+        let memory = state.block()?;
+        if memory.len() != chip.memory.len() {
+            return Err(StateError::Invalid("memory size"));
+        }
+        chip.memory.copy_from_slice(memory);
+        chip.registers = state.array()?;
+        chip.index = state.u16()?;
+        chip.pc = state.u16()?;
+        for level in chip.stack.iter_mut() {
+            *level = state.u16()?;
+        }
+        chip.sp = state.u8()?;
+        if chip.sp as usize > chip.stack.len() {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+        chip.delay_timer = state.u8()?;
+        chip.sound_timer = state.u8()?;
+        chip.keypad = state.array()?;
+        chip.hires = state.bool()?;
+        let planes = state.block()?;
+        if planes.len() != (chip.video_width() * chip.video_height()) as usize {
+            return Err(StateError::Invalid("display size"));
+        }
+        chip.video = planes.to_vec();
+        chip.plane_mask = state.u8()?;
+        chip.audio_pattern = state.array()?;
+        chip.pitch = state.u8()?;
+        chip.halted = state.bool()?;
+        chip.rpl_flags = state.array()?;
+        chip.opcode = state.u16()?;
+        chip.vblank = state.bool()?;
+        let name = state.block()?;
+        let rng_state = state.block()?;
+        state.finish()?;
+        if name != self.rand_gen.name().as_bytes() {
+            return Err(StateError::GeneratorMismatch {
+                saved: String::from_utf8_lossy(name).into_owned(),
+                current: self.rand_gen.name(),
+            });
+        }
+        if !self.rand_gen.restore(rng_state) {
+            return Err(StateError::Invalid("RNG state"));
+        }
+
+        // watchpoints belong to whoever is debugging, not to the program
+        chip.watched = std::mem::take(&mut self.watched);
+        chip.rand_gen = std::mem::replace(&mut self.rand_gen, Box::new(Seeded::default()));
+        // a trace goes on through loaded states, counting cycles as they were run
+        chip.trace = self.trace.take();
+        chip.cycles = self.cycles;
+        *self = chip;
+        Ok(())
+    }
+
+    /// Copies a ROM image into memory at `START_ADDRESS`.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), RomError> {
+        let start = START_ADDRESS as usize;
+        let capacity = self.memory.len() - start;
+        if rom.len() > capacity {
+            return Err(RomError::TooLarge { size: rom.len(), capacity });
+        }
+        self.memory[start..start + rom.len()].copy_from_slice(rom);
         Ok(())
     }
+
+    /// Loads the contents of a ROM file, see `load_rom`.
+    pub fn load_rom_file(&mut self, path: impl AsRef<Path>) -> Result<(), RomError> {
+        let rom = std::fs::read(path)?;
+        self.load_rom(&rom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn running(variant: Variant, rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::with_variant(variant, Quirks::default());
+        chip8.load_rom(rom).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn decoded_operands_reach_the_instruction() {
+        // LD V3, 0x12; ADD V3, 0x30; LD V5, V3; SE V5, 0x42; LD V0, 1; LD I, 0x345
+        let rom = [0x63, 0x12, 0x73, 0x30, 0x85, 0x30, 0x35, 0x42, 0x60, 0x01, 0xA3, 0x45];
+        let mut chip8 = running(Variant::Chip8, &rom);
+        (0..5).for_each(|_| assert_eq!(chip8.cycle().unwrap(), StepOutcome::Executed));
+        let state = chip8.machine_state();
+        assert_eq!(state.registers[3], 0x42);
+        assert_eq!(state.registers[5], 0x42);
+        assert_eq!(state.registers[0], 0);
+        assert_eq!(state.index, 0x345);
+        assert_eq!(state.pc, 0x20C);
+    }
+
+    #[test]
+    fn xo_chip_instructions_need_the_xo_chip_variant() {
+        // PLANE 2
+        let rom = [0xF2, 0x01];
+        let mut chip8 = running(Variant::Chip8, &rom);
+        assert!(matches!(chip8.cycle(), Err(EmulationError::UnknownOpcode { pc: 0x200, opcode: 0xF201, .. })));
+        assert_eq!(chip8.machine_state().pc, 0x200);
+
+        let mut chip8 = running(Variant::XoChip, &rom);
+        assert_eq!(chip8.cycle().unwrap(), StepOutcome::Executed);
+        assert_eq!(chip8.plane_mask, 2);
+    }
+
+    #[test]
+    fn words_that_are_not_instructions_are_unknown_opcodes() {
+        let mut chip8 = running(Variant::XoChip, &[0x80, 0x0F]);
+        assert!(matches!(chip8.cycle(), Err(EmulationError::UnknownOpcode { opcode: 0x800F, .. })));
+    }
+
+    #[test]
+    fn program_counter_stops_at_the_end_of_memory() {
+        // CLS, SE V0, 0 and LD I, long 0x1234 in the last words of memory, each of which
+        // would move the program counter past 0xFFFF
+        let programs: [(u16, &[u8]); 3] =
+            [(0xFFFE, &[0x00, 0xE0]), (0xFFFC, &[0x30, 0x00]), (0xFFFC, &[0xF0, 0x00, 0x12, 0x34])];
+        for (address, code) in programs {
+            let mut chip8 = running(Variant::XoChip, &[]);
+            chip8.memory[address as usize..][..code.len()].copy_from_slice(code);
+            chip8.pc = address;
+            let error = chip8.cycle().unwrap_err();
+            assert!(matches!(error, EmulationError::PcOutOfBounds { pc, .. } if pc == address), "{}", error);
+            assert_eq!(chip8.machine_state().pc, address);
+        }
+    }
 }
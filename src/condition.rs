@@ -0,0 +1,332 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chip8::Chip8;
+use crate::instruction::Register;
+
+/// A boolean expression over the machine state that breakpoints and watchpoints can be
+/// tagged with, e.g. `V3 == 0x10 && I > 0x300`.
+///
+/// Operands are the registers `V0`-`VF`, `I`, `PC`, `SP`, `DT` and `ST`, the memory byte
+/// `[addr]`, and numbers, decimal or hexadecimal with a `0x` prefix. From lowest to highest
+/// precedence the operators are `||`, `&&`, the comparisons `== != < <= > >=`,
+/// `+ - &` and the prefix `!`; parentheses group. Any non-zero value is true.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Condition {
+    text: String,
+    expr: Expr,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Expr {
+    Number(i64),
+    Register(Register),
+    Index,
+    Pc,
+    Sp,
+    DelayTimer,
+    SoundTimer,
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    BitAnd,
+}
+
+impl Condition {
+    /// Whether the condition holds for the current state of `chip8`.
+    pub fn evaluate(&self, chip8: &Chip8) -> bool {
+        evaluate(&self.expr, chip8) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens: &tokens, position: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected {} in condition", token));
+        }
+        Ok(Condition { text: text.trim().to_owned(), expr })
+    }
+}
+
+fn evaluate(expr: &Expr, chip8: &Chip8) -> i64 {
+    let state = chip8.machine_state();
+    match expr {
+        Expr::Number(value) => *value,
+        Expr::Register(x) => state.registers[x.index()] as i64,
+        Expr::Index => state.index as i64,
+        Expr::Pc => state.pc as i64,
+        Expr::Sp => state.sp as i64,
+        Expr::DelayTimer => state.delay_timer as i64,
+        Expr::SoundTimer => state.sound_timer as i64,
+        Expr::Memory(address) => {
+            let address = evaluate(address, chip8);
+            usize::try_from(address)
+                .ok()
+                .and_then(|address| chip8.memory().get(address))
+                .map_or(0, |&byte| byte as i64)
+        }
+        Expr::Not(operand) => (evaluate(operand, chip8) == 0) as i64,
+        Expr::Binary(op, left, right) => {
+            let left = evaluate(left, chip8);
+            // && and || short-circuit
+            match op {
+                Op::Or if left != 0 => return 1,
+                Op::And if left == 0 => return 0,
+                _ => {}
+            }
+            let right = evaluate(right, chip8);
+            match op {
+                Op::Or | Op::And => (right != 0) as i64,
+                Op::Eq => (left == right) as i64,
+                Op::Ne => (left != right) as i64,
+                Op::Lt => (left < right) as i64,
+                Op::Le => (left <= right) as i64,
+                Op::Gt => (left > right) as i64,
+                Op::Ge => (left >= right) as i64,
+                Op::Add => left.wrapping_add(right),
+                Op::Sub => left.wrapping_sub(right),
+                Op::BitAnd => left & right,
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    /// The value and the text it was written as.
+    Number(i64, String),
+    Name(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(_, text) => f.write_str(text),
+            Token::Name(name) => f.write_str(name),
+            Token::Symbol(symbol) => f.write_str(symbol),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    // longest first, so `<=` is not read as `<`
+    const SYMBOLS: [&str; 16] = ["||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "&", "!", "(", ")", "[", "]"];
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            let end = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+            if end == 0 {
+                return Err(format!("unexpected character {:?} in condition", rest.chars().next().unwrap_or(' ')));
+            }
+            let word = &rest[..end];
+            let number = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                Some(hex) => Some(i64::from_str_radix(hex, 16)),
+                None if word.starts_with(|c: char| c.is_ascii_digit()) => Some(word.parse::<i64>()),
+                None => None,
+            };
+            tokens.push(match number {
+                Some(Ok(value)) => Token::Number(value, word.to_owned()),
+                Some(Err(_)) => return Err(format!("invalid number {}", word)),
+                None => Token::Name(word.to_owned()),
+            });
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser, one method per precedence level.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(format!("expected {} in condition", symbol))
+        }
+    }
+
+    /// Parses `next (op next)*` for the operators of one precedence level.
+    fn binary(&mut self, ops: &[(&str, Op)], next: fn(&mut Self) -> Result<Expr, String>) -> Result<Expr, String> {
+        let mut left = next(self)?;
+        'outer: loop {
+            for (symbol, op) in ops {
+                if self.eat(symbol) {
+                    left = Expr::Binary(*op, Box::new(left), Box::new(next(self)?));
+                    continue 'outer;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.binary(&[("||", Op::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.binary(&[("&&", Op::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let ops = [
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ];
+        self.binary(&ops, Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        self.binary(&[("+", Op::Add), ("-", Op::Sub), ("&", Op::BitAnd)], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.or()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if self.eat("[") {
+            let address = self.or()?;
+            self.expect("]")?;
+            return Ok(Expr::Memory(Box::new(address)));
+        }
+        let token = self.peek().cloned().ok_or_else(|| "incomplete condition".to_owned())?;
+        self.position += 1;
+        match token {
+            Token::Number(value, _) => Ok(Expr::Number(value)),
+            Token::Name(name) => match name.to_ascii_uppercase().as_str() {
+                "I" => Ok(Expr::Index),
+                "PC" => Ok(Expr::Pc),
+                "SP" => Ok(Expr::Sp),
+                "DT" => Ok(Expr::DelayTimer),
+                "ST" => Ok(Expr::SoundTimer),
+                upper => upper
+                    .strip_prefix('V')
+                    .filter(|digit| digit.len() == 1)
+                    .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+                    .and_then(Register::new)
+                    .map(Expr::Register)
+                    .ok_or_else(|| format!("unknown name {} in condition", name)),
+            },
+            Token::Symbol(symbol) => Err(format!("unexpected {} in condition", symbol)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A machine after LD V3, 0x10 and LD I, 0x301, with 0xAB at 0x301.
+    fn machine() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&[0x63, 0x10, 0xA3, 0x01]).unwrap();
+        chip8.cycle().unwrap();
+        chip8.cycle().unwrap();
+        chip8.memory_mut()[0x301] = 0xAB;
+        chip8
+    }
+
+    fn holds(text: &str) -> bool {
+        text.parse::<Condition>().unwrap().evaluate(&machine())
+    }
+
+    #[test]
+    fn operators_bind_by_precedence() {
+        assert!(holds("1 || 0 && 0"));
+        assert!(!holds("(1 || 0) && 0"));
+        assert!(holds("2 + 3 == 5"));
+        assert!(holds("6 & 3 == 2"));
+        assert!(holds("1 - 2 < 0"));
+        assert!(holds("!0 && !!5"));
+        assert!(holds("0x10 >= 16 && 16 <= 0X10 && 3 != 4"));
+    }
+
+    #[test]
+    fn operands_read_the_machine() {
+        assert!(holds("V3 == 0x10 && I > 0x300"));
+        assert!(holds("pc == 0x204 && sp == 0 && dt == 0 && st == 0 && v0 == 0"));
+        assert!(holds("[I] == 0xAB && [I - 1] == 0"));
+        // memory outside the address space reads as 0
+        assert!(holds("[0x10000] == 0 && [0 - 1] == 0"));
+    }
+
+    #[test]
+    fn conditions_keep_their_text() {
+        let condition: Condition = "  V3 == 0x10 ".parse().unwrap();
+        assert_eq!(condition.to_string(), "V3 == 0x10");
+    }
+
+    #[test]
+    fn bad_conditions_are_errors() {
+        let error = |text: &str| text.parse::<Condition>().unwrap_err();
+        assert_eq!(error("V3 =="), "incomplete condition");
+        assert_eq!(error("VG == 1"), "unknown name VG in condition");
+        assert_eq!(error("V10 == 1"), "unknown name V10 in condition");
+        assert_eq!(error("(1"), "expected ) in condition");
+        assert_eq!(error("[I"), "expected ] in condition");
+        assert_eq!(error("1 2"), "unexpected 2 in condition");
+        assert_eq!(error("V1 pc"), "unexpected pc in condition");
+        assert_eq!(error("vg == 1"), "unknown name vg in condition");
+        assert_eq!(error("(1))"), "unexpected ) in condition");
+        assert_eq!(error("V1 0x1F"), "unexpected 0x1F in condition");
+        assert_eq!(error("V1 $ 2"), "unexpected character '$' in condition");
+        assert_eq!(error("0xZZ"), "invalid number 0xZZ");
+        assert_eq!(error("== 1"), "unexpected == in condition");
+    }
+}
@@ -0,0 +1,636 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+use chip8_h::chip8::{Chip8, MemoryAccess, StepOutcome};
+use chip8_h::condition::Condition;
+use chip8_h::disasm::{self, Syntax};
+use chip8_h::error::{EmulationError, MachineState};
+use chip8_h::instruction::{Instruction, Register};
+
+const HELP: &str = "\
+commands (addresses and values are hexadecimal):
+  s, step [count]          execute one or more instructions
+  n, next                  step over a CALL
+  f, finish                run until the current subroutine returns
+  c, continue              run until a breakpoint or error
+  p, pause                 stop a running program
+  b, break [addr] [if cond]
+                           set a breakpoint, or list them
+  d, delete <addr>         remove a breakpoint
+  watch [addr|V0-VF|I] [if cond]
+                           stop when memory is written or a register changes,
+                           or list watchpoints
+  rwatch <addr> [if cond]  stop when memory is read
+  awatch <addr> [if cond]  stop when memory is read or written
+  unwatch <n>              remove watchpoint n
+  r, regs                  show registers, stack and the current instruction
+  x <addr> [len]           dump memory
+  l, list [addr] [count]   disassemble instructions
+  set <V0-VF|I|PC|DT|ST> <value>
+                           change a register
+  poke <addr> <byte>...    change memory
+  q, quit                  stop the emulator
+conditions compare V0-VF, I, PC, SP, DT, ST and memory bytes [addr],
+for example: V3 == 0x10 && I > 0x300";
+
+/// What to do before the next instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    /// Run one `Chip8::cycle`.
+    Execute,
+    /// The machine is paused.
+    Wait,
+    /// The user asked to quit.
+    Quit,
+}
+
+/// What a watchpoint stops on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Watched {
+    Write(usize),
+    Read(usize),
+    Access(usize),
+    /// The register changing value.
+    Register(Register),
+    Index,
+}
+
+impl Watched {
+    fn address(self) -> Option<usize> {
+        match self {
+            Watched::Write(addr) | Watched::Read(addr) | Watched::Access(addr) => Some(addr),
+            Watched::Register(_) | Watched::Index => None,
+        }
+    }
+}
+
+struct Watchpoint {
+    watched: Watched,
+    condition: Option<Condition>,
+    /// Value of a watched register when it was last checked.
+    last: u16,
+}
+
+/// How far execution may go before the console takes over again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RunState {
+    Paused,
+    /// Execute this many more instructions.
+    Stepping(u32),
+    /// Run until `pc` returns to `address` with the stack back at `sp`.
+    StepOver { address: u16, sp: u8 },
+    /// Run until the stack drops below `sp`.
+    Finish { sp: u8 },
+    Running,
+}
+
+/// Interactive console driving a `Chip8` from commands typed on stdin.
+pub struct Debugger {
+    /// PC breakpoints, stopping only when their condition holds.
+    breakpoints: BTreeMap<u16, Option<Condition>>,
+    watchpoints: Vec<Watchpoint>,
+    state: RunState,
+    /// Set when resuming from a breakpoint so it does not trigger again immediately.
+    resumed_at: Option<u16>,
+    commands: Receiver<String>,
+    /// Commands received while the program was running.
+    pending: VecDeque<String>,
+}
+
+impl Debugger {
+    /// Starts a debugger with the machine paused before its first instruction, taking
+    /// its commands from `commands`, see `stdin_commands`.
+    // only the windowed frontend offers --debug, the terminal one owns stdin
+    #[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+    pub fn new(commands: Receiver<String>) -> Self {
+        println!("debugger started, type h for help");
+        prompt();
+        Debugger {
+            breakpoints: BTreeMap::new(),
+            watchpoints: Vec::new(),
+            state: RunState::Paused, resumed_at: None,
+            commands,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Runs a frame like `Chip8::run_frame`, stopping wherever the user or a breakpoint
+    /// pauses the program. The timers only tick for frames that ran to completion, so
+    /// they stay frozen while paused. Returns false once the user quits.
+    pub fn run_frame(&mut self, chip8: &mut Chip8, instructions_per_frame: u32) -> bool {
+        for _ in 0..instructions_per_frame {
+            match self.before_cycle(chip8) {
+                Action::Execute => {
+                    let result = chip8.cycle();
+                    self.after_cycle(chip8, &result);
+                    if matches!(result, Ok(StepOutcome::WaitingForVBlank | StepOutcome::Halted)) {
+                        break;
+                    }
+                }
+                Action::Wait => return true,
+                Action::Quit => return false,
+            }
+        }
+        chip8.tick_timers();
+        true
+    }
+
+    /// Handles pending commands and decides whether the next instruction may run.
+    fn before_cycle(&mut self, chip8: &mut Chip8) -> Action {
+        let mut disconnected = false;
+        loop {
+            match self.commands.try_recv() {
+                Ok(line) => self.pending.push_back(line),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    disconnected = true;
+                    break;
+                }
+            }
+        }
+        // p and q interrupt a running program, anything else typed ahead waits until it stops
+        if self.state != RunState::Paused {
+            let interrupt = self.pending.front().is_some_and(|line| {
+                matches!(line.split_whitespace().next(), Some("p" | "pause" | "q" | "quit"))
+            });
+            if let Some(line) = interrupt.then(|| self.pending.pop_front()).flatten() {
+                if self.command(chip8, &line) == Action::Quit {
+                    return Action::Quit;
+                }
+            }
+        }
+        while self.state == RunState::Paused {
+            let Some(line) = self.pending.pop_front() else {
+                if disconnected {
+                    return Action::Quit;
+                }
+                break;
+            };
+            if self.command(chip8, &line) == Action::Quit {
+                return Action::Quit;
+            }
+            if self.state == RunState::Paused {
+                prompt();
+            }
+        }
+
+        let state = chip8.machine_state();
+        let resumed_at = self.resumed_at.take();
+        let stop = match self.state {
+            RunState::Paused => return Action::Wait,
+            RunState::Stepping(_) => false,
+            RunState::StepOver { address, sp } => state.pc == address && state.sp == sp,
+            RunState::Finish { sp } => state.sp < sp,
+            RunState::Running => false,
+        };
+        let breakpoint = self.breakpoints.get(&state.pc).filter(|_| resumed_at != Some(state.pc));
+        if breakpoint.is_some_and(|condition| condition.as_ref().is_none_or(|condition| condition.evaluate(chip8))) {
+            println!("breakpoint at {:04X}", state.pc);
+            self.pause(chip8);
+            return Action::Wait;
+        }
+        if stop {
+            self.pause(chip8);
+            return Action::Wait;
+        }
+        Action::Execute
+    }
+
+    /// Inspects the result of a cycle, pausing on errors instead of ending the program.
+    fn after_cycle(&mut self, chip8: &mut Chip8, result: &Result<StepOutcome, EmulationError>) {
+        let hits = chip8.take_watch_hits();
+        let chip8 = &*chip8;
+        if self.state != RunState::Paused && result.is_ok() && self.check_watchpoints(chip8, &hits) {
+            self.pause(chip8);
+            return;
+        }
+        match result {
+            Err(error) => {
+                // the error already shows the registers
+                println!("{}", error);
+                self.state = RunState::Paused;
+                print_instruction(chip8);
+                prompt();
+            }
+            Ok(StepOutcome::Halted) => {
+                println!("program exited");
+                self.pause(chip8);
+            }
+            Ok(_) => {
+                if let RunState::Stepping(count) = self.state {
+                    if count <= 1 {
+                        self.pause(chip8);
+                    } else {
+                        self.state = RunState::Stepping(count - 1);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reports the watchpoints triggered by the last instruction, returning whether any did.
+    fn check_watchpoints(&mut self, chip8: &Chip8, hits: &[MemoryAccess]) -> bool {
+        let state = chip8.machine_state();
+        let mut triggered = false;
+        for (number, watchpoint) in self.watchpoints.iter_mut().enumerate() {
+            let find = |write: Option<bool>| {
+                hits.iter().find(|hit| {
+                    Some(hit.addr) == watchpoint.watched.address() && write.is_none_or(|write| hit.write == write)
+                })
+            };
+            let describe = |hit: &MemoryAccess| match hit.write {
+                true => format!("wrote {:02X} to {:04X}", hit.value, hit.addr),
+                false => format!("read {:02X} from {:04X}", hit.value, hit.addr),
+            };
+            let event = match watchpoint.watched {
+                Watched::Write(_) => find(Some(true)).map(describe),
+                Watched::Read(_) => find(Some(false)).map(describe),
+                Watched::Access(_) => find(None).map(describe),
+                Watched::Register(_) | Watched::Index => {
+                    let value = register_value(&state, watchpoint.watched);
+                    let old = std::mem::replace(&mut watchpoint.last, value);
+                    (value != old).then(|| format!("{} changed from {:X} to {:X}", watched_name(watchpoint.watched), old, value))
+                }
+            };
+            let Some(event) = event else { continue };
+            if watchpoint.condition.as_ref().is_none_or(|condition| condition.evaluate(chip8)) {
+                println!("watchpoint {}: {}", number, event);
+                triggered = true;
+            }
+        }
+        triggered
+    }
+
+    fn pause(&mut self, chip8: &Chip8) {
+        self.state = RunState::Paused;
+        print_location(chip8);
+        prompt();
+    }
+
+    fn resume(&mut self, chip8: &Chip8, state: RunState) {
+        let machine_state = chip8.machine_state();
+        // registers edited while paused are not reported as changes
+        for watchpoint in &mut self.watchpoints {
+            watchpoint.last = register_value(&machine_state, watchpoint.watched);
+        }
+        self.resumed_at = Some(machine_state.pc);
+        self.state = state;
+    }
+
+    fn command(&mut self, chip8: &mut Chip8, line: &str) -> Action {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&name, args)) = words.split_first() else {
+            return Action::Wait;
+        };
+        if let Err(message) = self.run_command(chip8, name, args) {
+            println!("{}", message);
+        }
+        if matches!(name, "q" | "quit") {
+            return Action::Quit;
+        }
+        Action::Wait
+    }
+
+    fn run_command(&mut self, chip8: &mut Chip8, name: &str, args: &[&str]) -> Result<(), String> {
+        let state = chip8.machine_state();
+        match (name, args) {
+            ("h" | "help", _) => println!("{}", HELP),
+            ("q" | "quit", _) => {}
+            ("p" | "pause", _) => self.pause(chip8),
+            ("s" | "step", []) => self.resume(chip8, RunState::Stepping(1)),
+            ("s" | "step", [count]) => {
+                let count = u32::from_str_radix(count, 16).map_err(|_| format!("invalid count {}", count))?;
+                self.resume(chip8, RunState::Stepping(count.max(1)));
+            }
+            ("n" | "next", []) => {
+                let word = (chip8.memory().get(state.pc as usize).copied(), chip8.memory().get(state.pc as usize + 1).copied());
+                let is_call = match word {
+                    (Some(high), Some(low)) => {
+                        matches!(Instruction::decode(((high as u16) << 8) | low as u16), Some(Instruction::Call { .. }))
+                    }
+                    _ => false,
+                };
+                let next = if is_call {
+                    RunState::StepOver { address: state.pc.wrapping_add(2), sp: state.sp }
+                } else {
+                    RunState::Stepping(1)
+                };
+                self.resume(chip8, next);
+            }
+            ("f" | "finish", []) => {
+                if state.sp == 0 {
+                    return Err("not in a subroutine".to_owned());
+                }
+                self.resume(chip8, RunState::Finish { sp: state.sp });
+            }
+            ("c" | "continue", []) => self.resume(chip8, RunState::Running),
+            ("b" | "break", []) => {
+                for (address, condition) in &self.breakpoints {
+                    match condition {
+                        Some(condition) => println!("{:04X} if {}", address, condition),
+                        None => println!("{:04X}", address),
+                    }
+                }
+            }
+            ("b" | "break", [address, rest @ ..]) => {
+                let condition = parse_condition(rest)?;
+                self.breakpoints.insert(parse_hex(address)? as u16, condition);
+            }
+            ("d" | "delete", [address]) => {
+                if self.breakpoints.remove(&(parse_hex(address)? as u16)).is_none() {
+                    return Err(format!("no breakpoint at {}", address));
+                }
+            }
+            ("watch", []) => {
+                for (number, watchpoint) in self.watchpoints.iter().enumerate() {
+                    let kind = match watchpoint.watched {
+                        Watched::Write(_) => "write",
+                        Watched::Read(_) => "read",
+                        Watched::Access(_) => "access",
+                        Watched::Register(_) | Watched::Index => "change",
+                    };
+                    let condition = watchpoint.condition.as_ref().map(|c| format!(" if {}", c)).unwrap_or_default();
+                    println!("{}: {} {}{}", number, kind, watched_name(watchpoint.watched), condition);
+                }
+            }
+            ("watch" | "rwatch" | "awatch", [target, rest @ ..]) => {
+                let condition = parse_condition(rest)?;
+                let upper = target.to_ascii_uppercase();
+                let watched = match (name, parse_register(&upper)) {
+                    ("watch", Some(register)) => Watched::Register(register),
+                    ("watch", None) if upper == "I" => Watched::Index,
+                    ("watch", None) => Watched::Write(parse_hex(target)? as usize),
+                    ("rwatch", _) => Watched::Read(parse_hex(target)? as usize),
+                    _ => Watched::Access(parse_hex(target)? as usize),
+                };
+                if let Some(addr) = watched.address() {
+                    chip8.watch(addr);
+                }
+                let last = register_value(&state, watched);
+                self.watchpoints.push(Watchpoint { watched, condition, last });
+            }
+            ("unwatch", [number]) => {
+                let number = parse_hex(number)? as usize;
+                if number >= self.watchpoints.len() {
+                    return Err(format!("no watchpoint {}", number));
+                }
+                let removed = self.watchpoints.remove(number).watched.address();
+                // other watchpoints may still need the address
+                if let Some(addr) = removed.filter(|&addr| !self.watchpoints.iter().any(|w| w.watched.address() == Some(addr))) {
+                    chip8.unwatch(addr);
+                }
+            }
+            ("r" | "regs", []) => print_location(chip8),
+            ("x", [address]) => dump(chip8.memory(), parse_hex(address)? as usize, 0x40),
+            ("x", [address, len]) => dump(chip8.memory(), parse_hex(address)? as usize, parse_hex(len)? as usize),
+            ("l" | "list", _) => {
+                let mut address = match args.first() {
+                    Some(address) => parse_hex(address)? as usize,
+                    None => state.pc as usize,
+                };
+                let count = match args.get(1) {
+                    Some(count) => parse_hex(count)?,
+                    None => 10,
+                };
+                for _ in 0..count {
+                    let Some((text, size)) = disasm::instruction_at(chip8.memory(), address, Syntax::Cowgod) else {
+                        break;
+                    };
+                    println!("{:04X}  {}", address, text);
+                    address += size;
+                }
+            }
+            ("set", [target, value]) => {
+                let value = parse_hex(value)?;
+                let upper = target.to_ascii_uppercase();
+                match upper.as_str() {
+                    "I" => chip8.set_index(value as u16),
+                    "PC" => chip8.set_pc(value as u16),
+                    "DT" => chip8.set_delay_timer(value as u8),
+                    "ST" => chip8.set_sound_timer(value as u8),
+                    _ => {
+                        let register = parse_register(&upper).ok_or_else(|| format!("unknown register {}", target))?;
+                        chip8.set_register(register, value as u8);
+                    }
+                }
+            }
+            ("poke", [address, bytes @ ..]) if !bytes.is_empty() => {
+                let address = parse_hex(address)? as usize;
+                let values = bytes.iter().map(|byte| parse_hex(byte)).collect::<Result<Vec<_>, _>>()?;
+                let memory = chip8.memory_mut();
+                let target = memory
+                    .get_mut(address..address + values.len())
+                    .ok_or_else(|| "address out of range".to_owned())?;
+                target.iter_mut().zip(values).for_each(|(byte, value)| *byte = value as u8);
+            }
+            _ => return Err(format!("unknown command {:?}, type h for help", name)),
+        }
+        Ok(())
+    }
+}
+
+/// Lines typed on stdin, read on a separate thread so the window stays responsive
+/// while the debugger waits for a command.
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+pub fn stdin_commands() -> Receiver<String> {
+    let (sender, commands) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    commands
+}
+
+fn prompt() {
+    print!("(chip8) ");
+    let _ = io::stdout().flush();
+}
+
+/// Prints the registers, stack and the instruction about to run.
+fn print_location(chip8: &Chip8) {
+    println!("{}", chip8.machine_state());
+    print_instruction(chip8);
+}
+
+/// Prints the instruction about to run.
+fn print_instruction(chip8: &Chip8) {
+    let state = chip8.machine_state();
+    match disasm::instruction_at(chip8.memory(), state.pc as usize, Syntax::Cowgod) {
+        Some((text, _)) => println!("=> {:04X}  {}", state.pc, text),
+        None => println!("=> {:04X}  (not an instruction)", state.pc),
+    }
+}
+
+/// Prints `len` bytes of memory from `address`, 16 to a line.
+fn dump(memory: &[u8], address: usize, len: usize) {
+    let end = address.saturating_add(len).min(memory.len());
+    for start in (address.min(end)..end).step_by(0x10) {
+        let bytes = &memory[start..(start + 0x10).min(end)];
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        println!("{:04X}  {}", start, hex.join(" "));
+    }
+}
+
+/// Parses the optional `if <condition>` following a breakpoint or watchpoint.
+fn parse_condition(args: &[&str]) -> Result<Option<Condition>, String> {
+    match args {
+        [] => Ok(None),
+        ["if", condition @ ..] if !condition.is_empty() => condition.join(" ").parse().map(Some),
+        _ => Err("expected if <condition>".to_owned()),
+    }
+}
+
+fn parse_register(upper: &str) -> Option<Register> {
+    upper
+        .strip_prefix('V')
+        .filter(|digit| digit.len() == 1)
+        .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+        .and_then(Register::new)
+}
+
+/// Current value of a watched register, 0 for memory watchpoints.
+fn register_value(state: &MachineState, watched: Watched) -> u16 {
+    match watched {
+        Watched::Register(x) => state.registers[x.index()] as u16,
+        Watched::Index => state.index,
+        _ => 0,
+    }
+}
+
+fn watched_name(watched: Watched) -> String {
+    match watched {
+        Watched::Register(x) => x.to_string(),
+        Watched::Index => "I".to_owned(),
+        Watched::Write(addr) | Watched::Read(addr) | Watched::Access(addr) => format!("{:04X}", addr),
+    }
+}
+
+fn parse_hex(text: &str) -> Result<u32, String> {
+    let digits = text.trim_start_matches("0x").trim_start_matches("0X");
+    u32::from_str_radix(digits, 16).map_err(|_| format!("invalid hexadecimal number {}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Sender;
+
+    /// CALL 0x208, LD V0 5, JP 0x204, padding, then the subroutine LD V1 1, LD V2 2, RET.
+    const ROM: [u8; 14] = [0x22, 0x08, 0x60, 0x05, 0x12, 0x04, 0x00, 0x00, 0x61, 0x01, 0x62, 0x02, 0x00, 0xEE];
+
+    fn start(rom: &[u8]) -> (Sender<String>, Debugger, Chip8) {
+        let (sender, commands) = mpsc::channel();
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(rom).unwrap();
+        (sender, Debugger::new(commands), chip8)
+    }
+
+    /// Types `lines`, then runs instructions until the debugger pauses or quits, or
+    /// `Action::Execute` after 100 instructions without either.
+    fn run(sender: &Sender<String>, debugger: &mut Debugger, chip8: &mut Chip8, lines: &[&str]) -> Action {
+        lines.iter().for_each(|line| sender.send(line.to_string()).unwrap());
+        for _ in 0..100 {
+            match debugger.before_cycle(chip8) {
+                Action::Execute => {
+                    let result = chip8.cycle();
+                    debugger.after_cycle(chip8, &result);
+                }
+                action => return action,
+            }
+        }
+        Action::Execute
+    }
+
+    #[test]
+    fn step_runs_one_instruction_or_a_count() {
+        let (sender, mut debugger, mut chip8) = start(&ROM);
+        assert_eq!(run(&sender, &mut debugger, &mut chip8, &["s"]), Action::Wait);
+        assert_eq!(chip8.machine_state().pc, 0x208);
+        assert_eq!(run(&sender, &mut debugger, &mut chip8, &["step 2"]), Action::Wait);
+        assert_eq!(chip8.machine_state().pc, 0x20C);
+        assert_eq!(debugger.state, RunState::Paused);
+    }
+
+    #[test]
+    fn next_steps_over_calls() {
+        let (sender, mut debugger, mut chip8) = start(&ROM);
+        assert_eq!(run(&sender, &mut debugger, &mut chip8, &["n"]), Action::Wait);
+        let state = chip8.machine_state();
+        assert_eq!((state.pc, state.sp), (0x202, 0));
+        assert_eq!(state.registers[1..3], [1, 2]);
+        // anything else is a single step
+        run(&sender, &mut debugger, &mut chip8, &["next"]);
+        assert_eq!(chip8.machine_state().pc, 0x204);
+    }
+
+    #[test]
+    fn finish_stops_at_the_caller() {
+        let (sender, mut debugger, mut chip8) = start(&ROM);
+        run(&sender, &mut debugger, &mut chip8, &["s", "s"]);
+        assert_eq!(chip8.machine_state().pc, 0x20A);
+        assert_eq!(run(&sender, &mut debugger, &mut chip8, &["f"]), Action::Wait);
+        let state = chip8.machine_state();
+        assert_eq!((state.pc, state.sp), (0x202, 0));
+        assert_eq!(debugger.run_command(&mut chip8, "finish", &[]), Err("not in a subroutine".to_owned()));
+    }
+
+    #[test]
+    fn continue_stops_at_breakpoints_once_per_visit() {
+        let (sender, mut debugger, mut chip8) = start(&ROM);
+        run(&sender, &mut debugger, &mut chip8, &["b 20a", "b 204 if V0 == 6", "c"]);
+        assert_eq!(chip8.machine_state().pc, 0x20A);
+        // resuming from the breakpoint does not stop there again, and V0 never becomes 6
+        assert_eq!(run(&sender, &mut debugger, &mut chip8, &["c"]), Action::Execute);
+        assert_eq!(chip8.machine_state().pc, 0x204);
+        assert_eq!(run(&sender, &mut debugger, &mut chip8, &["p"]), Action::Wait);
+        assert_eq!(debugger.state, RunState::Paused);
+
+        run(&sender, &mut debugger, &mut chip8, &["d 204", "b 204 if V0 == 5", "c"]);
+        assert_eq!(debugger.state, RunState::Paused);
+        assert_eq!(debugger.run_command(&mut chip8, "d", &["300"]), Err("no breakpoint at 300".to_owned()));
+    }
+
+    #[test]
+    fn registers_and_memory_can_be_edited() {
+        let (sender, mut debugger, mut chip8) = start(&ROM);
+        let lines = ["set V3 2a", "set i 0x300", "set DT 10", "poke 300 12 34", "set PC 202", "s"];
+        run(&sender, &mut debugger, &mut chip8, &lines);
+        let state = chip8.machine_state();
+        assert_eq!((state.registers[3], state.index, state.pc), (0x2A, 0x300, 0x204));
+        assert_eq!(state.registers[0], 5);
+        assert_eq!(state.delay_timer, 0x10);
+        assert_eq!(chip8.memory()[0x300..0x302], [0x12, 0x34]);
+
+        let mut command = |name, args: &[&str]| debugger.run_command(&mut chip8, name, args);
+        assert_eq!(command("set", &["VG", "1"]), Err("unknown register VG".to_owned()));
+        assert_eq!(command("set", &["V1", "zz"]), Err("invalid hexadecimal number zz".to_owned()));
+        assert_eq!(command("poke", &["ffff", "1", "2"]), Err("address out of range".to_owned()));
+        assert_eq!(command("jump", &[]), Err("unknown command \"jump\", type h for help".to_owned()));
+    }
+
+    #[test]
+    fn errors_pause_before_the_failing_instruction() {
+        let (sender, mut debugger, mut chip8) = start(&[0x60, 0x01, 0xFF, 0xFF]);
+        assert_eq!(run(&sender, &mut debugger, &mut chip8, &["c"]), Action::Wait);
+        assert_eq!(debugger.state, RunState::Paused);
+        let state = chip8.machine_state();
+        assert_eq!((state.pc, state.registers[0]), (0x202, 1));
+    }
+
+    #[test]
+    fn quitting_ends_the_session() {
+        let (sender, mut debugger, mut chip8) = start(&ROM);
+        assert_eq!(run(&sender, &mut debugger, &mut chip8, &["c"]), Action::Execute);
+        assert_eq!(run(&sender, &mut debugger, &mut chip8, &["q"]), Action::Quit);
+
+        // and so does closing the console while paused
+        let (sender, mut debugger, mut chip8) = start(&ROM);
+        drop(sender);
+        assert_eq!(debugger.before_cycle(&mut chip8), Action::Quit);
+    }
+}
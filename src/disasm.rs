@@ -0,0 +1,271 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::chip8::START_ADDRESS;
+use crate::instruction::{Instruction, Register};
+
+/// Mnemonic style of the disassembly listing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syntax {
+    /// The syntax of Cowgod's technical reference, as documented on the `OP_*` handlers.
+    Cowgod,
+    /// The syntax of the Octo assembler.
+    Octo,
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "cowgod" => Ok(Syntax::Cowgod),
+            "octo" => Ok(Syntax::Octo),
+            _ => Err(format!("unknown syntax {:?}, expected octo or cowgod", name)),
+        }
+    }
+}
+
+/// Bytes of a ROM between `START_ADDRESS` and the end of the 64 KiB address space.
+/// Anything past them cannot be loaded, so it is neither followed nor listed.
+pub const MAX_ROM_LEN: usize = 0x10000 - START_ADDRESS as usize;
+
+/// Finds the addresses of the instructions reachable from `START_ADDRESS`, following jumps,
+/// calls and both sides of skips. `Bnnn` targets depend on V0 and are not followed.
+/// Everything else in the ROM is treated as data.
+pub fn reachable(rom: &[u8]) -> BTreeSet<u16> {
+    let rom = &rom[..rom.len().min(MAX_ROM_LEN)];
+    let end = START_ADDRESS as usize + rom.len();
+    let word = |address: u16| -> Option<u16> {
+        let offset = (address as usize).checked_sub(START_ADDRESS as usize)?;
+        Some(((*rom.get(offset)? as u16) << 8) | *rom.get(offset + 1)? as u16)
+    };
+    let mut code = BTreeSet::new();
+    let mut pending = vec![START_ADDRESS as u16];
+    while let Some(address) = pending.pop() {
+        if (address as usize) + 1 >= end || code.contains(&address) {
+            continue;
+        }
+        let Some(instruction) = word(address).and_then(Instruction::decode) else {
+            continue;
+        };
+        code.insert(address);
+        let next = address.wrapping_add(instruction.size());
+        match instruction {
+            Instruction::Jump { nnn } => pending.push(nnn),
+            Instruction::Call { nnn } => pending.extend([nnn, next]),
+            Instruction::Ret | Instruction::Exit | Instruction::JumpOffset { .. } => {}
+            Instruction::SkipEqByte { .. }
+            | Instruction::SkipNeByte { .. }
+            | Instruction::SkipEq { .. }
+            | Instruction::SkipNe { .. }
+            | Instruction::SkipKey { .. }
+            | Instruction::SkipNotKey { .. } => {
+                // a skipped XO-CHIP long load is four bytes long
+                let skipped = word(next)
+                    .and_then(Instruction::decode)
+                    .map_or(2, Instruction::size);
+                pending.extend([next, next.wrapping_add(skipped)]);
+            }
+            _ => pending.push(next),
+        }
+    }
+    code
+}
+
+/// Disassembles a ROM loaded at `START_ADDRESS` into a listing with one line per
+/// instruction: address, raw word and mnemonic. Bytes that are not reachable code are
+/// listed one per line as data, with their bit pattern to make sprites recognisable.
+/// Only the first `MAX_ROM_LEN` bytes are listed.
+pub fn disassemble(rom: &[u8], syntax: Syntax) -> String {
+    let rom = &rom[..rom.len().min(MAX_ROM_LEN)];
+    let code = reachable(rom);
+    let mut listing = String::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        // in range, since the ROM ends with the address space
+        let address = (START_ADDRESS as usize + offset) as u16;
+        if code.contains(&address) {
+            let opcode = ((rom[offset] as u16) << 8) | rom[offset + 1] as u16;
+            let instruction = Instruction::decode(opcode).expect("reachable code decodes");
+            let long_address = (instruction == Instruction::LoadIndexLong)
+                .then(|| rom.get(offset + 2..offset + 4))
+                .flatten()
+                .map(|bytes| ((bytes[0] as u16) << 8) | bytes[1] as u16);
+            let raw = match long_address {
+                Some(long) => format!("{:04X} {:04X}", opcode, long),
+                None => format!("{:04X}", opcode),
+            };
+            let text = match syntax {
+                Syntax::Cowgod => cowgod(instruction, long_address),
+                Syntax::Octo => octo(instruction, long_address),
+            };
+            let _ = writeln!(listing, "{:04X}  {:<9}  {}", address, raw, text);
+            offset += if long_address.is_some() { 4 } else { 2 };
+        } else {
+            let byte = rom[offset];
+            let text = match syntax {
+                Syntax::Cowgod => format!("DB 0x{:02X}", byte),
+                Syntax::Octo => format!("0x{:02X}", byte),
+            };
+            let comment = if syntax == Syntax::Octo { '#' } else { ';' };
+            let pattern: String = (0..8)
+                .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+                .collect();
+            let _ = writeln!(listing, "{:04X}  {:<9}  {:<20} {} {}", address, format!("{:02X}", byte), text, comment, pattern);
+            offset += 1;
+        }
+    }
+    listing
+}
+
+/// Disassembles the instruction at `address` in a full memory image, returning its text
+/// and size in bytes, or `None` when the word there is not an instruction.
+pub fn instruction_at(memory: &[u8], address: usize, syntax: Syntax) -> Option<(String, usize)> {
+    let word = |address: usize| Some(((*memory.get(address)? as u16) << 8) | *memory.get(address + 1)? as u16);
+    let instruction = Instruction::decode(word(address)?)?;
+    let long_address = (instruction == Instruction::LoadIndexLong)
+        .then(|| word(address + 2))
+        .flatten();
+    let text = match syntax {
+        Syntax::Cowgod => cowgod(instruction, long_address),
+        Syntax::Octo => octo(instruction, long_address),
+    };
+    Some((text, instruction.size() as usize))
+}
+
+fn cowgod(instruction: Instruction, long_address: Option<u16>) -> String {
+    match (instruction, long_address) {
+        (Instruction::LoadIndexLong, Some(address)) => format!("LD I, LONG 0x{:04X}", address),
+        _ => instruction.to_string(),
+    }
+}
+
+fn octo(instruction: Instruction, long_address: Option<u16>) -> String {
+    let v = |register: Register| format!("v{:x}", register.index());
+    match instruction {
+        Instruction::ScrollDown { n } => format!("scroll-down {}", n),
+        Instruction::ScrollUp { n } => format!("scroll-up {}", n),
+        Instruction::Cls => "clear".to_owned(),
+        Instruction::Ret => "return".to_owned(),
+        Instruction::ScrollRight => "scroll-right".to_owned(),
+        Instruction::ScrollLeft => "scroll-left".to_owned(),
+        Instruction::Exit => "exit".to_owned(),
+        Instruction::LowRes => "lores".to_owned(),
+        Instruction::HighRes => "hires".to_owned(),
+        Instruction::Jump { nnn } => format!("jump 0x{:03X}", nnn),
+        Instruction::Call { nnn } => format!(":call 0x{:03X}", nnn),
+        // Octo writes skips as the condition under which the next instruction runs
+        Instruction::SkipEqByte { x, kk } => format!("if {} != 0x{:02X} then", v(x), kk),
+        Instruction::SkipNeByte { x, kk } => format!("if {} == 0x{:02X} then", v(x), kk),
+        Instruction::SkipEq { x, y } => format!("if {} != {} then", v(x), v(y)),
+        Instruction::SaveRange { x, y } => format!("save {} - {}", v(x), v(y)),
+        Instruction::LoadRange { x, y } => format!("load {} - {}", v(x), v(y)),
+        Instruction::LoadByte { x, kk } => format!("{} := 0x{:02X}", v(x), kk),
+        Instruction::AddByte { x, kk } => format!("{} += 0x{:02X}", v(x), kk),
+        Instruction::Move { x, y } => format!("{} := {}", v(x), v(y)),
+        Instruction::Or { x, y } => format!("{} |= {}", v(x), v(y)),
+        Instruction::And { x, y } => format!("{} &= {}", v(x), v(y)),
+        Instruction::Xor { x, y } => format!("{} ^= {}", v(x), v(y)),
+        Instruction::Add { x, y } => format!("{} += {}", v(x), v(y)),
+        Instruction::Sub { x, y } => format!("{} -= {}", v(x), v(y)),
+        Instruction::ShiftRight { x, y } => format!("{} >>= {}", v(x), v(y)),
+        Instruction::SubN { x, y } => format!("{} =- {}", v(x), v(y)),
+        Instruction::ShiftLeft { x, y } => format!("{} <<= {}", v(x), v(y)),
+        Instruction::SkipNe { x, y } => format!("if {} == {} then", v(x), v(y)),
+        Instruction::LoadIndex { nnn } => format!("i := 0x{:03X}", nnn),
+        Instruction::JumpOffset { nnn } => format!("jump0 0x{:03X}", nnn),
+        Instruction::Random { x, kk } => format!("{} := random 0x{:02X}", v(x), kk),
+        Instruction::Draw { x, y, n } => format!("sprite {} {} {}", v(x), v(y), n),
+        Instruction::SkipKey { x } => format!("if {} -key then", v(x)),
+        Instruction::SkipNotKey { x } => format!("if {} key then", v(x)),
+        Instruction::LoadIndexLong => match long_address {
+            Some(address) => format!("i := long 0x{:04X}", address),
+            None => "i := long".to_owned(),
+        },
+        Instruction::Plane { n } => format!("plane {}", n),
+        Instruction::Audio => "audio".to_owned(),
+        Instruction::GetDelay { x } => format!("{} := delay", v(x)),
+        Instruction::WaitKey { x } => format!("{} := key", v(x)),
+        Instruction::SetDelay { x } => format!("delay := {}", v(x)),
+        Instruction::SetSound { x } => format!("buzzer := {}", v(x)),
+        Instruction::AddIndex { x } => format!("i += {}", v(x)),
+        Instruction::Font { x } => format!("i := hex {}", v(x)),
+        Instruction::BigFont { x } => format!("i := bighex {}", v(x)),
+        Instruction::Bcd { x } => format!("bcd {}", v(x)),
+        Instruction::Pitch { x } => format!("pitch := {}", v(x)),
+        Instruction::Store { x } => format!("save {}", v(x)),
+        Instruction::Restore { x } => format!("load {}", v(x)),
+        Instruction::SaveFlags { x } => format!("saveflags {}", v(x)),
+        Instruction::LoadFlags { x } => format!("loadflags {}", v(x)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// CALL 0x208, SE V0 0x00, JP 0x206, JP 0x206, RET, then two bytes of sprite data.
+    const ROM: [u8; 12] = [0x22, 0x08, 0x30, 0x00, 0x12, 0x06, 0x12, 0x06, 0x00, 0xEE, 0xF0, 0x90];
+
+    #[test]
+    fn code_is_found_through_calls_jumps_and_skips() {
+        assert_eq!(reachable(&ROM), BTreeSet::from([0x200, 0x202, 0x204, 0x206, 0x208]));
+        // nothing follows a jump back to itself or a jump out of the ROM
+        assert_eq!(reachable(&[0x12, 0x00, 0x00, 0xE0]), BTreeSet::from([0x200]));
+        assert_eq!(reachable(&[0x1F, 0xFE, 0x00, 0xE0]), BTreeSet::from([0x200]));
+        // words that are not instructions end the path
+        assert_eq!(reachable(&[0x00, 0xE0, 0xFF, 0xFF, 0x00, 0xE0]), BTreeSet::from([0x200]));
+    }
+
+    #[test]
+    fn skips_step_over_long_loads() {
+        // SE V0 0x00, LD I LONG 0x1234, EXIT
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xFD];
+        assert_eq!(reachable(&rom), BTreeSet::from([0x200, 0x202, 0x206]));
+        let listing = disassemble(&rom, Syntax::Cowgod);
+        assert_eq!(listing.lines().nth(1), Some("0202  F000 1234  LD I, LONG 0x1234"));
+        let long_load = instruction_at(&[0xF0, 0x00, 0x12, 0x34], 0, Syntax::Octo);
+        assert_eq!(long_load, Some(("i := long 0x1234".to_owned(), 4)));
+    }
+
+    #[test]
+    fn listings_show_code_and_data() {
+        let listing = disassemble(&ROM, Syntax::Cowgod);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[0], "0200  2208       CALL 0x208");
+        assert_eq!(lines[1], "0202  3000       SE V0, 0x00");
+        assert_eq!(lines[5], "020A  F0         DB 0xF0              ; ####....");
+
+        let listing = disassemble(&ROM, Syntax::Octo);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], "0200  2208       :call 0x208");
+        assert_eq!(lines[1], "0202  3000       if v0 != 0x00 then");
+        assert_eq!(lines[6], "020B  90         0x90                 # #..#....");
+    }
+
+    #[test]
+    fn odd_bytes_at_the_end_are_data() {
+        let listing = disassemble(&[0x00, 0xE0, 0x12], Syntax::Cowgod);
+        assert_eq!(listing, "0200  00E0       CLS\n0202  12         DB 0x12              ; ...#..#.\n");
+        assert_eq!(instruction_at(&[0x12], 0, Syntax::Cowgod), None);
+    }
+
+    #[test]
+    fn roms_stop_at_the_end_of_the_address_space() {
+        // CLS all the way through memory and past it
+        let rom: Vec<u8> = [0x00, 0xE0].repeat(MAX_ROM_LEN / 2 + 2);
+        assert_eq!(reachable(&rom).last(), Some(&0xFFFE));
+        let listing = disassemble(&rom, Syntax::Cowgod);
+        assert_eq!(listing.lines().count(), MAX_ROM_LEN / 2);
+        assert_eq!(listing.lines().last(), Some("FFFE  00E0       CLS"));
+    }
+
+    #[test]
+    fn syntaxes_parse_by_name() {
+        assert_eq!("Octo".parse(), Ok(Syntax::Octo));
+        assert_eq!("cowgod".parse(), Ok(Syntax::Cowgod));
+        assert!("intel".parse::<Syntax>().is_err());
+    }
+}
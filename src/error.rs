@@ -0,0 +1,174 @@
+use std::fmt;
+use std::io;
+
+/// Copy of the interpreter registers taken when an instruction fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MachineState {
+    pub pc: u16,
+    pub opcode: u16,
+    pub index: u16,
+    pub sp: u8,
+    pub registers: [u8; 0x10],
+    pub stack: [u16; 0x10],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl fmt::Display for MachineState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "PC={:04X} OP={:04X} I={:04X} SP={:X} DT={:02X} ST={:02X}",
+            self.pc, self.opcode, self.index, self.sp, self.delay_timer, self.sound_timer
+        )?;
+        for (i, value) in self.registers.iter().enumerate() {
+            write!(f, "{}V{:X}={:02X}", if i % 8 == 0 { "\n" } else { " " }, i, value)?;
+        }
+        write!(f, "\nstack:")?;
+        for address in &self.stack[..(self.sp as usize).min(self.stack.len())] {
+            write!(f, " {:04X}", address)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reasons `Chip8::cycle` can stop executing a program.
+/// Every variant carries the machine state at the start of the failing instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EmulationError {
+    /// The opcode at `pc` is not part of the selected instruction set.
+    UnknownOpcode { pc: u16, opcode: u16, state: MachineState },
+    /// A 2nnn call was made with all stack levels in use.
+    StackOverflow { state: MachineState },
+    /// A 00EE return was made with an empty stack.
+    StackUnderflow { state: MachineState },
+    /// An instruction read or wrote past the end of memory.
+    MemoryOutOfBounds { addr: usize, state: MachineState },
+    /// The program counter left memory, or the instruction at `pc` would have moved it
+    /// past 0xFFFF.
+    PcOutOfBounds { pc: u16, state: MachineState },
+}
+
+impl EmulationError {
+    /// Machine state at the start of the failing instruction.
+    pub fn state(&self) -> &MachineState {
+        match self {
+            EmulationError::UnknownOpcode { state, .. }
+            | EmulationError::StackOverflow { state }
+            | EmulationError::StackUnderflow { state }
+            | EmulationError::MemoryOutOfBounds { state, .. }
+            | EmulationError::PcOutOfBounds { state, .. } => state,
+        }
+    }
+}
+
+impl fmt::Display for EmulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulationError::UnknownOpcode { pc, opcode, .. } => {
+                write!(f, "unknown opcode {:04X} at {:04X}", opcode, pc)?
+            }
+            EmulationError::StackOverflow { .. } => write!(f, "stack overflow")?,
+            EmulationError::StackUnderflow { .. } => write!(f, "return with an empty stack")?,
+            EmulationError::MemoryOutOfBounds { addr, .. } => {
+                write!(f, "memory access out of bounds at {:04X}", addr)?
+            }
+            EmulationError::PcOutOfBounds { pc, .. } => {
+                write!(f, "program counter out of bounds at {:04X}", pc)?
+            }
+        }
+        write!(f, "\n{}", self.state())
+    }
+}
+
+impl std::error::Error for EmulationError {}
+
+/// Reasons `Chip8::load_state` rejects a save state. The machine is left untouched.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// The data does not start with the save state magic.
+    BadMagic,
+    /// The state was written by an incompatible version of the emulator.
+    UnsupportedVersion(u16),
+    /// The data was corrupted after it was saved.
+    ChecksumMismatch,
+    /// The data ends before the state does.
+    Truncated,
+    /// A field holds a value the interpreter cannot be in.
+    Invalid(&'static str),
+    /// The state was saved with a random number generator other than the one in use,
+    /// by the names `RandomSource::name` gives.
+    GeneratorMismatch { saved: String, current: &'static str },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "unsupported save state version {}", version),
+            StateError::ChecksumMismatch => write!(f, "save state checksum mismatch"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(field) => write!(f, "save state has an invalid {}", field),
+            StateError::GeneratorMismatch { saved, current } => {
+                write!(f, "save state uses the {} random number generator, not {}", saved, current)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// Reasons a ROM cannot be loaded.
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    /// The ROM does not fit between `START_ADDRESS` and the end of memory.
+    TooLarge { size: usize, capacity: usize },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::Io(error) => write!(f, "{}", error),
+            RomError::TooLarge { size, capacity } => {
+                write!(f, "ROM is {} bytes, only {} fit in memory", size, capacity)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+impl From<io::Error> for RomError {
+    fn from(error: io::Error) -> Self {
+        RomError::Io(error)
+    }
+}
+
+/// Error raised by an instruction handler, turned into an `EmulationError`
+/// by `Chip8::cycle` once the machine state is attached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Fault {
+    UnknownOpcode,
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds(usize),
+    /// The instruction would move the program counter past the end of the address space.
+    PcOutOfBounds,
+}
+
+impl Fault {
+    pub(crate) fn with_state(self, state: MachineState) -> EmulationError {
+        match self {
+            Fault::UnknownOpcode => EmulationError::UnknownOpcode {
+                pc: state.pc,
+                opcode: state.opcode,
+                state,
+            },
+            Fault::StackOverflow => EmulationError::StackOverflow { state },
+            Fault::StackUnderflow => EmulationError::StackUnderflow { state },
+            Fault::MemoryOutOfBounds(addr) => EmulationError::MemoryOutOfBounds { addr, state },
+            Fault::PcOutOfBounds => EmulationError::PcOutOfBounds { pc: state.pc, state },
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::time::{Duration, Instant};
+
+use crate::keymap::Keymap;
+
+/// Number of save state slots a frontend offers.
+pub const SAVE_SLOTS: usize = 4;
+/// Length of a 60 Hz frame.
+pub const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// Emulator functions bound to keys outside the CHIP-8 keypad.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hotkey {
+    /// Saves to a slot from 1 to `SAVE_SLOTS`.
+    SaveState(usize),
+    /// Loads a slot from 1 to `SAVE_SLOTS`.
+    LoadState(usize),
+    /// Mutes and unmutes the buzzer.
+    ToggleMute,
+    /// The player finished remapping the keypad, see `Input::keymap`.
+    KeymapChanged,
+    /// Saves the display as a PNG image.
+    Screenshot,
+    /// Starts recording an animated GIF, or stops and saves the one being recorded.
+    ToggleGifRecording,
+}
+
+/// The pieces of a frontend a run loop drives, each replaceable on its own.
+pub struct Frontend<D, I, A, C> {
+    pub display: D,
+    pub input: I,
+    pub audio: A,
+    pub clock: C,
+}
+
+/// Shows the emulated display.
+pub trait Display {
+    /// Draws a frame, `width` by `height`, as returned by `Chip8::framebuffer`, in the
+    /// display's palette. The size changes when a program switches resolution.
+    fn present(&mut self, framebuffer: &[u8], width: u32, height: u32);
+
+    /// Tells the player about something a hotkey did, such as saving a state.
+    fn message(&mut self, text: &str) {
+        println!("{}", text);
+    }
+}
+
+/// Reads the keypad and the emulator hotkeys.
+pub trait Input {
+    /// Handles pending events, updating `keypad` with the keys held down.
+    /// Returns true when the user asked to quit.
+    fn poll(&mut self, keypad: &mut [bool; 0x10]) -> bool;
+
+    /// Hotkeys pressed since the last call, oldest first.
+    fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        Vec::new()
+    }
+
+    /// Whether the rewind key is held down.
+    fn rewinding(&self) -> bool {
+        false
+    }
+
+    /// The host keys bound to the keypad, for inputs that can be remapped.
+    fn keymap(&self) -> Option<&Keymap> {
+        None
+    }
+}
+
+/// Plays the buzzer.
+pub trait Audio {
+    /// Starts or stops the tone, called every frame with `sound_timer > 0`.
+    fn set_playing(&mut self, playing: bool);
+
+    /// Plays an XO-CHIP audio pattern at the given rate instead of the plain tone.
+    fn set_pattern(&mut self, _pattern: Option<([u8; 0x10], f32)>) {}
+
+    /// Mutes or unmutes the tone, returning whether it is now muted.
+    fn toggle_mute(&mut self) -> bool {
+        false
+    }
+}
+
+/// Plays nothing, for frontends without sound.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Silent;
+
+impl Audio for Silent {
+    fn set_playing(&mut self, _playing: bool) {}
+}
+
+/// `None` stays silent, for when no audio device could be opened.
+impl<A: Audio> Audio for Option<A> {
+    fn set_playing(&mut self, playing: bool) {
+        if let Some(audio) = self {
+            audio.set_playing(playing);
+        }
+    }
+
+    fn set_pattern(&mut self, pattern: Option<([u8; 0x10], f32)>) {
+        if let Some(audio) = self {
+            audio.set_pattern(pattern);
+        }
+    }
+
+    fn toggle_mute(&mut self) -> bool {
+        self.as_mut().is_some_and(|audio| audio.toggle_mute())
+    }
+}
+
+/// Paces the run loop.
+pub trait Clock {
+    /// Returns true once the next frame is due; the run loop polls input while it
+    /// returns false.
+    fn frame_due(&mut self) -> bool;
+}
+
+/// Runs frames at 60 Hz of wall clock time.
+pub struct SystemClock {
+    next_frame: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock { next_frame: Instant::now() }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn frame_due(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next_frame {
+            // short enough to keep polling input, long enough not to spin a whole core
+            std::thread::sleep((self.next_frame - now).min(Duration::from_millis(1)));
+            return false;
+        }
+        // frames missed after a stall are dropped rather than run in a burst
+        self.next_frame = (self.next_frame + FRAME_TIME).max(now);
+        true
+    }
+}
+
+/// Runs a frame every time it is asked, as fast as the host allows, for tests and
+/// fast-forwarding.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UnthrottledClock;
+
+impl Clock for UnthrottledClock {
+    fn frame_due(&mut self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crate::chip8::{Chip8, HIRES_VIDEO_HEIGHT, HIRES_VIDEO_WIDTH};
+use crate::image::scale;
+use crate::palette::Palette;
+
+/// Recordings stop by themselves after this long unless told otherwise.
+pub const DEFAULT_MAX_DURATION: Duration = Duration::from_secs(60);
+/// Frames captured per second.
+const FRAME_RATE: u64 = 60;
+/// Shortest frame delay, in hundredths of a second, that browsers play as written;
+/// most slow shorter ones down to a tenth of a second.
+const MIN_DELAY: u64 = 2;
+/// Largest LZW code width GIF allows.
+const MAX_CODE_BITS: u32 = 12;
+
+/// Records the frames a frontend presents as a looping animated GIF.
+///
+/// Frames are captured at 60 Hz in the palette the frontend shows. A frame shown for
+/// several captures in a row is stored once with a longer delay, so idle screens cost
+/// nothing. Since GIF delays are in hundredths of a second, a frame that
+/// would stay up for less than two of them is dropped in favour of the next, which
+/// caps motion at about 50 frames per second.
+///
+/// Low and high resolution frames are both stored at the high resolution size, times
+/// `scale`, since the size of a GIF is fixed by its first bytes.
+pub struct GifRecorder<W: Write> {
+    out: W,
+    scale: u32,
+    max_frames: u64,
+    /// Frames captured so far.
+    frames: u64,
+    /// The last frame that differed from the one before, upscaled, and the capture
+    /// it first appeared in. Written out once it is known how long it stayed up.
+    pending: Option<(Vec<u32>, u64)>,
+}
+
+impl<W: Write> GifRecorder<W> {
+    /// Starts a recording that stops capturing after `max_duration`.
+    pub fn new(mut out: W, scale: u32, max_duration: Duration) -> io::Result<Self> {
+        let scale = scale.max(1);
+        out.write_all(b"GIF89a")?;
+        out.write_all(&(HIRES_VIDEO_WIDTH * scale).to_le_bytes()[..2])?;
+        out.write_all(&(HIRES_VIDEO_HEIGHT * scale).to_le_bytes()[..2])?;
+        // no global colour table, every frame brings its own
+        out.write_all(&[0, 0, 0])?;
+        // NETSCAPE2.0 extension: loop forever
+        out.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+        let max_frames = max_duration.as_millis() as u64 * FRAME_RATE / 1000;
+        Ok(GifRecorder { out, scale, max_frames, frames: 0, pending: None })
+    }
+
+    /// Frames captured so far, at 60 a second.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Captures RGBA8888 pixels, `width` by `height`, as the next frame. Returns false,
+    /// capturing nothing, once the recording is as long as it may get.
+    pub fn capture(&mut self, width: u32, height: u32, pixels: &[u32]) -> io::Result<bool> {
+        if self.frames >= self.max_frames {
+            return Ok(false);
+        }
+        let factor = (HIRES_VIDEO_WIDTH / width.max(1)).max(1) * self.scale;
+        let (_, _, frame) = scale(width, height, pixels, factor);
+        let frame_number = self.frames;
+        self.frames += 1;
+        let Some((image, start)) = self.pending.take() else {
+            self.pending = Some((frame, frame_number));
+            return Ok(true);
+        };
+        if image == frame {
+            self.pending = Some((image, start));
+        } else if delay(start, frame_number) < MIN_DELAY {
+            // too short to show, the new frame takes its place
+            self.pending = Some((frame, start));
+        } else {
+            self.write_frame(&image, delay(start, frame_number))?;
+            self.pending = Some((frame, frame_number));
+        }
+        Ok(true)
+    }
+
+    /// Captures the display as it is now in `palette`, see `capture`.
+    pub fn capture_chip8(&mut self, chip8: &Chip8, palette: &Palette) -> io::Result<bool> {
+        self.capture(chip8.video_width(), chip8.video_height(), &palette.apply(chip8.framebuffer()))
+    }
+
+    /// Writes the last frame and the end of the GIF, returning the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some((image, start)) = self.pending.take() {
+            self.write_frame(&image, delay(start, self.frames).max(MIN_DELAY))?;
+        }
+        self.out.write_all(&[0x3B])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn write_frame(&mut self, image: &[u32], delay: u64) -> io::Result<()> {
+        // GIF has no alpha, and a frame's colours index a table of up to 256 of them
+        let mut colours: Vec<u32> = Vec::new();
+        let indices: Vec<u8> = image
+            .iter()
+            .map(|&pixel| {
+                let rgb = pixel >> 8;
+                match colours.iter().position(|&colour| colour == rgb) {
+                    Some(index) => index as u8,
+                    None if colours.len() < 256 => {
+                        colours.push(rgb);
+                        (colours.len() - 1) as u8
+                    }
+                    None => 0,
+                }
+            })
+            .collect();
+        let table_bits = (colours.len().max(2) as u32).next_power_of_two().trailing_zeros();
+
+        // graphic control extension: the delay, no transparency
+        let delay = delay.min(u16::MAX as u64) as u16;
+        self.out.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0x00, 0x00])?;
+
+        // image descriptor covering the whole screen, with a local colour table
+        let (width, height) = (HIRES_VIDEO_WIDTH * self.scale, HIRES_VIDEO_HEIGHT * self.scale);
+        self.out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.out.write_all(&width.to_le_bytes()[..2])?;
+        self.out.write_all(&height.to_le_bytes()[..2])?;
+        self.out.write_all(&[0x80 | (table_bits - 1) as u8])?;
+        for i in 0..1 << table_bits {
+            let colour = colours.get(i).copied().unwrap_or(0);
+            self.out.write_all(&colour.to_be_bytes()[1..])?;
+        }
+
+        let min_code_bits = table_bits.max(2);
+        self.out.write_all(&[min_code_bits as u8])?;
+        for block in lzw(&indices, min_code_bits).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0])
+    }
+}
+
+/// Hundredths of a second between captures `start` and `end`, rounded so that delays
+/// add up to the time actually recorded.
+fn delay(start: u64, end: u64) -> u64 {
+    let hundredths = |frame: u64| (frame * 100 + FRAME_RATE / 2) / FRAME_RATE;
+    hundredths(end) - hundredths(start)
+}
+
+/// Compresses colour indices with GIF's variable-width LZW.
+fn lzw(indices: &[u8], min_code_bits: u32) -> Vec<u8> {
+    let clear = 1u32 << min_code_bits;
+    let end = clear + 1;
+    let mut output = BitWriter::default();
+    let mut codes: HashMap<(u32, u8), u32> = HashMap::new();
+    let mut next_code = end + 1;
+    let mut bits = min_code_bits + 1;
+    output.write(clear, bits);
+
+    let mut indices = indices.iter();
+    let Some(&first) = indices.next() else {
+        output.write(end, bits);
+        return output.finish();
+    };
+    let mut prefix = first as u32;
+    for &index in indices {
+        if let Some(&code) = codes.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        output.write(prefix, bits);
+        if next_code < 1 << MAX_CODE_BITS {
+            codes.insert((prefix, index), next_code);
+            // the decoder adds each code a step later, so widen once it could need the next one
+            if next_code >= 1 << bits && bits < MAX_CODE_BITS {
+                bits += 1;
+            }
+            next_code += 1;
+        } else {
+            output.write(clear, bits);
+            codes.clear();
+            next_code = end + 1;
+            bits = min_code_bits + 1;
+        }
+        prefix = index as u32;
+    }
+    output.write(prefix, bits);
+    output.write(end, bits);
+    output.finish()
+}
+
+/// Packs codes least significant bit first, as GIF stores them.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u32, bits: u32) {
+        self.buffer |= code << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = HIRES_VIDEO_WIDTH as usize;
+    const HEIGHT: usize = HIRES_VIDEO_HEIGHT as usize;
+
+    /// A plain GIF LZW decoder, to check `lzw` against.
+    fn unlzw(data: &[u8], min_code_bits: u32) -> Vec<u8> {
+        let clear = 1usize << min_code_bits;
+        let reset = || -> Vec<Vec<u8>> { (0..clear + 2).map(|i| vec![i as u8]).collect() };
+        let mut table = reset();
+        let mut bits = min_code_bits + 1;
+        let (mut position, mut output, mut previous) = (0, Vec::new(), None::<Vec<u8>>);
+        loop {
+            let code = (0..bits).fold(0, |code, bit| {
+                let bit_position = position + bit as usize;
+                code | ((data[bit_position / 8] >> (bit_position % 8)) as usize & 1) << bit
+            });
+            position += bits as usize;
+            if code == clear {
+                (table, bits, previous) = (reset(), min_code_bits + 1, None);
+                continue;
+            }
+            if code == clear + 1 {
+                return output;
+            }
+            let entry = match (table.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) => [previous.as_slice(), &previous[..1]].concat(),
+                (None, None) => panic!("code {} before the table has it", code),
+            };
+            if let Some(previous) = previous {
+                table.push([previous.as_slice(), &entry[..1]].concat());
+            }
+            if table.len() == 1 << bits && bits < MAX_CODE_BITS {
+                bits += 1;
+            }
+            output.extend_from_slice(&entry);
+            previous = Some(entry);
+        }
+    }
+
+    /// The delay and RGB pixels of every frame of a GIF, checking its structure on the way.
+    fn frames(gif: &[u8]) -> Vec<(u16, Vec<u32>)> {
+        assert_eq!(gif[..6], *b"GIF89a");
+        assert_eq!(gif[6..13], [WIDTH as u8, 0, HEIGHT as u8, 0, 0, 0, 0]);
+        assert_eq!(gif[13..32], *b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00");
+        let mut frames = Vec::new();
+        let mut i = 32;
+        while gif[i] != 0x3B {
+            assert_eq!(gif[i..i + 4], [0x21, 0xF9, 0x04, 0x00]);
+            let delay = u16::from_le_bytes([gif[i + 4], gif[i + 5]]);
+            i += 8;
+            assert_eq!(gif[i..i + 9], [0x2C, 0, 0, 0, 0, WIDTH as u8, 0, HEIGHT as u8, 0]);
+            let table_len = 2 << (gif[i + 9] & 0x7);
+            let table: Vec<u32> = gif[i + 10..i + 10 + table_len * 3]
+                .chunks(3)
+                .map(|rgb| u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]]))
+                .collect();
+            i += 10 + table_len * 3;
+            let min_code_bits = gif[i] as u32;
+            i += 1;
+            let mut data = Vec::new();
+            while gif[i] != 0 {
+                data.extend_from_slice(&gif[i + 1..i + 1 + gif[i] as usize]);
+                i += 1 + gif[i] as usize;
+            }
+            i += 1;
+            let pixels = unlzw(&data, min_code_bits).iter().map(|&index| table[index as usize]).collect();
+            frames.push((delay, pixels));
+        }
+        assert_eq!(i, gif.len() - 1);
+        frames
+    }
+
+    /// A high resolution frame with the top `rows` rows lit.
+    fn frame(rows: usize) -> Vec<u32> {
+        (0..WIDTH * HEIGHT).map(|i| if i < rows * WIDTH { 0xFFFFFFFF } else { 0x000000FF }).collect()
+    }
+
+    fn rgb(pixels: &[u32]) -> Vec<u32> {
+        pixels.iter().map(|pixel| pixel >> 8).collect()
+    }
+
+    #[test]
+    fn lzw_round_trips() {
+        // a small LCG, so the long inputs fill the code table and force clear codes
+        let mut state = 1u32;
+        let mut noise = |modulus: u32| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            ((state >> 16) % modulus) as u8
+        };
+        let two_bits: Vec<u8> = (0..50_000).map(|_| noise(4)).collect();
+        let eight_bits: Vec<u8> = (0..50_000).map(|_| noise(256)).collect();
+        for (indices, min_code_bits) in
+            [(vec![], 2), (vec![3], 2), (vec![0; 10_000], 2), (two_bits, 2), (eight_bits, 8), ((0..=255).collect(), 8)]
+        {
+            assert_eq!(unlzw(&lzw(&indices, min_code_bits), min_code_bits), indices);
+        }
+    }
+
+    #[test]
+    fn repeated_frames_are_stored_once() {
+        let mut recorder = GifRecorder::new(Vec::new(), 1, DEFAULT_MAX_DURATION).unwrap();
+        // 31 captures of one frame, a frame too short to show, then 28 of a third
+        for capture in 0..60 {
+            let rows = match capture {
+                0..=30 => 1,
+                31 => 2,
+                _ => 3,
+            };
+            assert!(recorder.capture(WIDTH as u32, HEIGHT as u32, &frame(rows)).unwrap());
+        }
+        assert_eq!(recorder.frames(), 60);
+        let frames = frames(&recorder.finish().unwrap());
+        assert_eq!(frames, [(52, rgb(&frame(1))), (48, rgb(&frame(3)))]);
+    }
+
+    #[test]
+    fn low_resolution_frames_are_scaled_up() {
+        let mut pixels = vec![0x000000FF; WIDTH * HEIGHT / 4];
+        pixels[0] = 0x123456FF;
+        let mut recorder = GifRecorder::new(Vec::new(), 1, DEFAULT_MAX_DURATION).unwrap();
+        recorder.capture(WIDTH as u32 / 2, HEIGHT as u32 / 2, &pixels).unwrap();
+        let frames = frames(&recorder.finish().unwrap());
+        let (_, _, expected) = scale(WIDTH as u32 / 2, HEIGHT as u32 / 2, &pixels, 2);
+        assert_eq!(frames, [(2, rgb(&expected))]);
+        assert_eq!(frames[0].1[..3], [0x123456, 0x123456, 0]);
+    }
+
+    #[test]
+    fn recordings_stop_at_the_maximum_duration() {
+        let mut recorder = GifRecorder::new(Vec::new(), 1, Duration::from_millis(100)).unwrap();
+        for _ in 0..6 {
+            assert!(recorder.capture(WIDTH as u32, HEIGHT as u32, &frame(0)).unwrap());
+        }
+        assert!(!recorder.capture(WIDTH as u32, HEIGHT as u32, &frame(1)).unwrap());
+        assert_eq!(recorder.frames(), 6);
+        assert_eq!(frames(&recorder.finish().unwrap()), [(10, rgb(&frame(0)))]);
+    }
+
+    #[test]
+    fn empty_recordings_are_still_gifs() {
+        let gif = GifRecorder::new(Vec::new(), 1, DEFAULT_MAX_DURATION).unwrap().finish().unwrap();
+        assert_eq!(gif.len(), 33);
+        assert_eq!(gif.last(), Some(&0x3B));
+        assert!(frames(&gif).is_empty());
+    }
+}
@@ -0,0 +1,216 @@
+use crate::chip8::Chip8;
+use crate::condition::Condition;
+use crate::error::EmulationError;
+use crate::movie::{Desync, Movie};
+
+/// A key held down from `frame` for `hold` frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyPress {
+    pub frame: u32,
+    pub key: u8,
+    pub hold: u32,
+}
+
+impl std::str::FromStr for KeyPress {
+    type Err = String;
+
+    /// Parses `FRAME:KEY[:HOLD]`, with the key in hexadecimal and HOLD defaulting to 5 frames,
+    /// long enough for programs that wait for a key press and its release.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid key press {:?}, expected FRAME:KEY[:HOLD]", text);
+        let mut parts = text.split(':');
+        let frame = parts.next().and_then(|frame| frame.parse().ok()).ok_or_else(invalid)?;
+        let key = parts
+            .next()
+            .and_then(|key| u8::from_str_radix(key, 16).ok())
+            .filter(|&key| key < 0x10)
+            .ok_or_else(invalid)?;
+        let hold = match parts.next() {
+            Some(hold) => hold.parse().map_err(|_| invalid())?,
+            None => 5,
+        };
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(KeyPress { frame, key, hold })
+    }
+}
+
+/// How long to run a ROM without a window.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Frames to run before giving up.
+    pub frames: u32,
+    pub instructions_per_frame: u32,
+    /// Stop as soon as this holds at the end of a frame.
+    pub until: Option<Condition>,
+    pub keys: Vec<KeyPress>,
+    /// Plays the movie's input instead of `keys`, checking its display hashes.
+    pub movie: Option<Movie>,
+}
+
+/// Why a headless run stopped.
+#[derive(Debug)]
+pub enum Outcome {
+    /// `until` held after this many frames.
+    ConditionMet(u32),
+    /// 00FD stopped the program after this many frames.
+    Halted(u32),
+    /// All frames ran and there was no condition to wait for.
+    Finished,
+    /// All frames ran without `until` holding.
+    TimedOut,
+    Error(EmulationError),
+    /// The display stopped matching the movie being played.
+    Desync(Desync),
+}
+
+/// Runs `chip8` at `instructions_per_frame` for up to `frames` frames, pressing the
+/// scripted keys or playing the movie along the way, and handing the machine to
+/// `on_frame` after each frame, as a frontend would present it.
+pub fn run(chip8: &mut Chip8, options: &Options, mut on_frame: impl FnMut(&Chip8)) -> Outcome {
+    for frame in 0..options.frames {
+        if let Some(movie) = &options.movie {
+            movie.play_input(frame, chip8);
+        } else {
+            for key in 0..0x10 {
+                chip8.release_key(key);
+            }
+            let held = |press: &&KeyPress| (press.frame..press.frame.saturating_add(press.hold)).contains(&frame);
+            for press in options.keys.iter().filter(held) {
+                chip8.press_key(press.key);
+            }
+        }
+        if let Err(error) = chip8.run_frame(options.instructions_per_frame) {
+            return Outcome::Error(error);
+        }
+        on_frame(chip8);
+        if let Some(Err(desync)) = options.movie.as_ref().map(|movie| movie.verify(frame + 1, chip8)) {
+            return Outcome::Desync(desync);
+        }
+        if options.until.as_ref().is_some_and(|until| until.evaluate(chip8)) {
+            return Outcome::ConditionMet(frame + 1);
+        }
+        if chip8.halted() {
+            return Outcome::Halted(frame + 1);
+        }
+    }
+    match options.until {
+        Some(_) => Outcome::TimedOut,
+        None => Outcome::Finished,
+    }
+}
+
+/// The display as text, `#` for lit pixels and `.` for unlit ones.
+pub fn ascii(chip8: &Chip8) -> String {
+    let mut text = String::new();
+    for row in chip8.framebuffer().chunks(chip8.video_width() as usize) {
+        text.extend(row.iter().map(|&pixel| if pixel != 0 { '#' } else { '.' }));
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Variant;
+    use crate::error::EmulationError;
+    use crate::instruction::Register;
+    use crate::movie::CHECK_INTERVAL;
+    use crate::quirks::Quirks;
+    use crate::random::Generator;
+
+    /// ADD V0, 1; JP 0x200 - at two instructions a frame, counts frames in V0.
+    const COUNTER: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+    /// LD F, V0; DRW V0, V0, 5; JP 0x204 - draws the digit in V0 at (V0, V0), then waits.
+    const DIGIT: [u8; 6] = [0xF0, 0x29, 0xD0, 0x05, 0x12, 0x04];
+
+    fn options(frames: u32) -> Options {
+        Options { frames, instructions_per_frame: 2, until: None, keys: Vec::new(), movie: None }
+    }
+
+    fn machine(rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(rom).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn key_presses_parse() {
+        assert_eq!("10:a".parse(), Ok(KeyPress { frame: 10, key: 0xA, hold: 5 }));
+        assert_eq!("0:F:1".parse(), Ok(KeyPress { frame: 0, key: 0xF, hold: 1 }));
+        for bad in ["", "10", "10:10", "10:g", "x:1", "10:1:x", "10:1:2:3", "-1:1"] {
+            let expected = format!("invalid key press {:?}, expected FRAME:KEY[:HOLD]", bad);
+            assert_eq!(bad.parse::<KeyPress>(), Err(expected));
+        }
+    }
+
+    #[test]
+    fn keys_are_held_for_their_frames() {
+        let mut options = options(6);
+        options.keys = vec!["2:a:3".parse().unwrap(), "1:1:4294967295".parse().unwrap()];
+        let mut held = Vec::new();
+        run(&mut machine(&COUNTER), &options, |chip8| held.push((chip8.key_pressed(0xA), chip8.key_pressed(1))));
+        let a: Vec<bool> = held.iter().map(|&(a, _)| a).collect();
+        assert_eq!(a, [false, false, true, true, true, false]);
+        assert!(held[1..].iter().all(|&(_, one)| one));
+    }
+
+    #[test]
+    fn runs_end_with_their_outcome() {
+        let mut chip8 = machine(&COUNTER);
+        let mut options = options(10);
+        options.until = Some("V0 == 3".parse().unwrap());
+        let mut frames = 0;
+        assert!(matches!(run(&mut chip8, &options, |_| frames += 1), Outcome::ConditionMet(3)));
+        assert_eq!(frames, 3);
+
+        options.until = Some("V0 == 100".parse().unwrap());
+        assert!(matches!(run(&mut machine(&COUNTER), &options, |_| {}), Outcome::TimedOut));
+
+        options.until = None;
+        let mut chip8 = machine(&COUNTER);
+        assert!(matches!(run(&mut chip8, &options, |_| {}), Outcome::Finished));
+        assert_eq!(chip8.machine_state().registers[0], 10);
+
+        // CLS, EXIT
+        assert!(matches!(run(&mut machine(&[0x00, 0xE0, 0x00, 0xFD]), &options, |_| {}), Outcome::Halted(1)));
+        let outcome = run(&mut machine(&[0xFF, 0xFF]), &options, |_| {});
+        assert!(matches!(outcome, Outcome::Error(EmulationError::UnknownOpcode { pc: 0x200, opcode: 0xFFFF, .. })));
+    }
+
+    #[test]
+    fn movies_are_checked_while_playing() {
+        let mut movie = Movie::new(&DIGIT, Generator::Seeded, 0, Variant::Chip8, Quirks::default(), 2);
+        let mut chip8 = movie.machine(&DIGIT).unwrap();
+        for _ in 0..CHECK_INTERVAL {
+            movie.record_input(&chip8);
+            chip8.run_frame(2).unwrap();
+            movie.record_check(&chip8);
+        }
+        let mut options = options(CHECK_INTERVAL);
+        options.movie = Some(movie.clone());
+        assert!(matches!(run(&mut movie.machine(&DIGIT).unwrap(), &options, |_| {}), Outcome::Finished));
+
+        let mut chip8 = movie.machine(&DIGIT).unwrap();
+        chip8.set_register(Register::new(0).unwrap(), 1);
+        match run(&mut chip8, &options, |_| {}) {
+            Outcome::Desync(desync) => assert_eq!(desync.frame, CHECK_INTERVAL),
+            outcome => panic!("{:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn the_display_is_drawn_in_text() {
+        let mut chip8 = machine(&DIGIT);
+        chip8.run_frame(3).unwrap();
+        let text = ascii(&chip8);
+        let rows: Vec<&str> = text.lines().collect();
+        assert_eq!(rows.len(), 32);
+        assert!(rows.iter().all(|row| row.len() == 64));
+        let digit: Vec<&str> = rows[..5].iter().map(|row| &row[..5]).collect();
+        assert_eq!(digit, ["####.", "#..#.", "#..#.", "#..#.", "####."]);
+        assert_eq!(text.matches('#').count(), 14);
+    }
+}
@@ -0,0 +1,294 @@
+use std::path::Path;
+
+use crate::chip8::Chip8;
+use crate::palette::Palette;
+use crate::savestate::crc32;
+
+/// Image file formats the display can be exported to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    /// Binary PPM (P6), 8-bit RGB.
+    Ppm,
+    /// Binary PBM (P4), one bit per pixel: lit pixels clear and unlit ones set, so
+    /// that viewers show lit pixels white on black as on screen. XO-CHIP colours are lost.
+    Pbm,
+}
+
+impl Format {
+    /// The format named by a file's extension.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "png" => Some(Format::Png),
+            "ppm" => Some(Format::Ppm),
+            "pbm" => Some(Format::Pbm),
+            _ => None,
+        }
+    }
+}
+
+/// How to export the display: the format, the colours, an integer scale factor and
+/// text to store along with the image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Screenshot {
+    pub format: Format,
+    /// The colours `capture` draws the display in, the palette the frontend shows.
+    pub palette: Palette,
+    /// Each pixel becomes a `scale` by `scale` square; 0 counts as 1.
+    pub scale: u32,
+    /// Key and value pairs such as the ROM name and frame number, stored as PNG `tEXt`
+    /// chunks or as comments in the PPM and PBM headers.
+    pub metadata: Vec<(String, String)>,
+}
+
+impl Screenshot {
+    pub fn new(format: Format) -> Self {
+        Screenshot { format, palette: Palette::default(), scale: 1, metadata: Vec::new() }
+    }
+
+    /// Encodes RGBA8888 pixels, `width` by `height`.
+    pub fn encode(&self, width: u32, height: u32, pixels: &[u32]) -> Vec<u8> {
+        let (width, height, pixels) = scale(width, height, pixels, self.scale.max(1));
+        match self.format {
+            Format::Png => png(width, height, &pixels, &self.metadata),
+            Format::Ppm => ppm(width, height, &pixels, &self.metadata),
+            Format::Pbm => pbm(width, height, &pixels, &self.metadata),
+        }
+    }
+
+    /// Encodes the display as it is now, in `palette`.
+    pub fn capture(&self, chip8: &Chip8) -> Vec<u8> {
+        self.encode(chip8.video_width(), chip8.video_height(), &self.palette.apply(chip8.framebuffer()))
+    }
+}
+
+/// Repeats every pixel `factor` times across and down.
+pub(crate) fn scale(width: u32, height: u32, pixels: &[u32], factor: u32) -> (u32, u32, Vec<u32>) {
+    let mut scaled = Vec::with_capacity(pixels.len() * (factor * factor) as usize);
+    for row in pixels.chunks(width as usize).take(height as usize) {
+        let start = scaled.len();
+        scaled.extend(row.iter().flat_map(|&pixel| std::iter::repeat_n(pixel, factor as usize)));
+        let end = scaled.len();
+        for _ in 1..factor {
+            scaled.extend_from_within(start..end);
+        }
+    }
+    (width * factor, height * factor, scaled)
+}
+
+/// Encodes RGBA8888 pixels, such as `Palette::apply` gives, as a PNG image.
+/// The image data is stored uncompressed, which is fine at CHIP-8 resolutions.
+pub fn encode_png(width: u32, height: u32, pixels: &[u32]) -> Vec<u8> {
+    png(width, height, pixels, &[])
+}
+
+fn png(width: u32, height: u32, pixels: &[u32], metadata: &[(String, String)]) -> Vec<u8> {
+    // every scanline starts with filter type 0, none
+    let mut raw = Vec::with_capacity((width as usize * 4 + 1) * height as usize);
+    for row in pixels.chunks(width as usize).take(height as usize) {
+        raw.push(0);
+        row.iter().for_each(|pixel| raw.extend(pixel.to_be_bytes()));
+    }
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut header = Vec::new();
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    // 8 bits per channel, colour type 6 (RGBA), default compression, filtering and no interlace
+    header.extend([8, 6, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &header);
+    for (key, value) in metadata {
+        // tEXt is Latin-1 with a keyword of 1 to 79 characters
+        let latin1 = |text: &str| -> Vec<u8> { text.chars().map(|c| u8::try_from(c).unwrap_or(b'?')).collect() };
+        let mut text: Vec<u8> = latin1(key).into_iter().filter(|&byte| byte != 0).take(79).collect();
+        text.push(0);
+        text.extend(latin1(value));
+        write_chunk(&mut png, b"tEXt", &text);
+    }
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn ppm(width: u32, height: u32, pixels: &[u32], metadata: &[(String, String)]) -> Vec<u8> {
+    let mut ppm = netpbm_header("P6", width, height, metadata);
+    ppm.extend(b"255\n");
+    for pixel in pixels {
+        ppm.extend(&pixel.to_be_bytes()[..3]);
+    }
+    ppm
+}
+
+fn pbm(width: u32, height: u32, pixels: &[u32], metadata: &[(String, String)]) -> Vec<u8> {
+    let mut pbm = netpbm_header("P4", width, height, metadata);
+    // rows are padded to whole bytes
+    for row in pixels.chunks(width as usize) {
+        for byte in row.chunks(8) {
+            let bits = byte.iter().enumerate().filter(|(_, &pixel)| pixel == 0).fold(0, |bits, (i, _)| bits | 0x80 >> i);
+            pbm.push(bits);
+        }
+    }
+    pbm
+}
+
+/// The magic number, metadata comments and size that start PPM and PBM files.
+fn netpbm_header(magic: &str, width: u32, height: u32, metadata: &[(String, String)]) -> Vec<u8> {
+    let mut header = format!("{}\n", magic);
+    for (key, value) in metadata {
+        // a comment ends at the line break
+        header.push_str(&format!("# {}: {}\n", key, value.replace(['\r', '\n'], " ")));
+    }
+    header.push_str(&format!("{} {}\n", width, height));
+    header.into_bytes()
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+/// Wraps `data` in a zlib stream made of uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        stream.extend([1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        stream.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        stream.extend(len.to_le_bytes());
+        stream.extend((!len).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+    stream.extend(adler32(data).to_be_bytes());
+    stream
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Splits a PNG into its chunks, checking the signature and every CRC.
+    fn chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (body, crc) = (&rest[4..8 + len], &rest[8 + len..12 + len]);
+            assert_eq!(crc32(body).to_be_bytes(), crc);
+            chunks.push((String::from_utf8(body[..4].to_vec()).unwrap(), body[4..].to_vec()));
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+
+    /// Reassembles the stored deflate blocks of a zlib stream, checking the Adler-32.
+    fn unzlib(stream: &[u8]) -> Vec<u8> {
+        assert_eq!(stream[..2], [0x78, 0x01]);
+        let mut data = Vec::new();
+        let mut i = 2;
+        loop {
+            let last = stream[i] == 1;
+            let len = u16::from_le_bytes([stream[i + 1], stream[i + 2]]);
+            assert_eq!(!len, u16::from_le_bytes([stream[i + 3], stream[i + 4]]));
+            data.extend_from_slice(&stream[i + 5..i + 5 + len as usize]);
+            i += 5 + len as usize;
+            if last {
+                break;
+            }
+        }
+        assert_eq!(stream[i..], adler32(&data).to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn checksums_match_the_reference_values() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+    }
+
+    #[test]
+    fn png_chunks_hold_the_image() {
+        let mut screenshot = Screenshot::new(Format::Png);
+        screenshot.metadata = vec![("Title".to_owned(), "Pong \u{e9}\u{3c0}".to_owned())];
+        let png = screenshot.encode(2, 2, &[0x000000FF, 0xFFFFFFFF, 0x11223344, 0x55667788]);
+        let chunks = chunks(&png);
+        let kinds: Vec<&str> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+        assert_eq!(kinds, ["IHDR", "tEXt", "IDAT", "IEND"]);
+        assert_eq!(chunks[0].1, [0, 0, 0, 2, 0, 0, 0, 2, 8, 6, 0, 0, 0]);
+        assert_eq!(chunks[1].1, b"Title\0Pong \xE9?");
+        let raw = [
+            [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF].as_slice(),
+            &[0, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88],
+        ]
+        .concat();
+        assert_eq!(unzlib(&chunks[2].1), raw);
+        assert!(chunks[3].1.is_empty());
+    }
+
+    #[test]
+    fn large_pngs_span_several_deflate_blocks() {
+        let pixels: Vec<u32> = (0..128 * 128).collect();
+        let chunks = chunks(&encode_png(128, 128, &pixels));
+        let raw = unzlib(&chunks[1].1);
+        assert_eq!(raw.len(), 128 * (128 * 4 + 1));
+        assert!(raw.len() > 0xFFFF);
+        assert_eq!(raw[1 + 4 * 5..1 + 4 * 6], 5u32.to_be_bytes());
+        assert_eq!(unzlib(&zlib_stored(&[])), []);
+    }
+
+    #[test]
+    fn netpbm_images_have_headers_and_pixels() {
+        let mut screenshot = Screenshot::new(Format::Ppm);
+        screenshot.metadata = vec![("rom".to_owned(), "a\nb".to_owned())];
+        let ppm = screenshot.encode(2, 1, &[0x010203FF, 0xFFFFFFFF]);
+        assert_eq!(ppm, b"P6\n# rom: a b\n2 1\n255\n\x01\x02\x03\xFF\xFF\xFF");
+
+        // lit pixels are clear and rows are padded to whole bytes
+        let lit = 0xFFFFFFFF;
+        let mut pixels = vec![0; 10];
+        pixels[1] = lit;
+        pixels[9] = lit;
+        let pbm = Screenshot::new(Format::Pbm).encode(5, 2, &pixels);
+        assert_eq!(pbm, b"P4\n5 2\n\xB8\xF0");
+    }
+
+    #[test]
+    fn scaling_repeats_pixels() {
+        let (width, height, pixels) = scale(2, 2, &[1, 2, 3, 4], 2);
+        assert_eq!((width, height), (4, 4));
+        assert_eq!(pixels, [1, 1, 2, 2, 1, 1, 2, 2, 3, 3, 4, 4, 3, 3, 4, 4]);
+        assert_eq!(scale(2, 1, &[1, 2], 1), (2, 1, vec![1, 2]));
+
+        let mut screenshot = Screenshot::new(Format::Ppm);
+        screenshot.scale = 0;
+        assert!(screenshot.encode(2, 1, &[0, 0]).starts_with(b"P6\n2 1\n"));
+        screenshot.scale = 3;
+        assert!(screenshot.encode(2, 1, &[0, 0]).starts_with(b"P6\n6 3\n"));
+    }
+
+    #[test]
+    fn formats_follow_the_extension() {
+        assert_eq!(Format::from_path(Path::new("shot.PNG")), Some(Format::Png));
+        assert_eq!(Format::from_path(Path::new("a/shot.pbm")), Some(Format::Pbm));
+        assert_eq!(Format::from_path(Path::new("shot.ppm")), Some(Format::Ppm));
+        assert_eq!(Format::from_path(Path::new("shot.jpg")), None);
+        assert_eq!(Format::from_path(Path::new("shot")), None);
+    }
+}
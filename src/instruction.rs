@@ -0,0 +1,394 @@
+use std::fmt;
+
+/// One of the sixteen V registers, V0 to VF.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Register(u8);
+
+impl Register {
+    /// Register Vn, for n in 0x0..=0xF.
+    pub fn new(n: u8) -> Option<Self> {
+        (n < 0x10).then_some(Register(n))
+    }
+
+    /// Position of the register in the register file.
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "V{:X}", self.0)
+    }
+}
+
+/// A decoded CHIP-8, SUPER-CHIP or XO-CHIP instruction.
+/// `x` and `y` are the registers in the second and third nibble,
+/// `nnn` the lowest 12 bits, `kk` the lowest byte and `n` the lowest nibble.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    /// 00Cn SCD nibble
+    ScrollDown { n: u8 },
+    /// 00Dn SCU nibble (XO-CHIP)
+    ScrollUp { n: u8 },
+    /// 00E0 CLS
+    Cls,
+    /// 00EE RET
+    Ret,
+    /// 00FB SCR
+    ScrollRight,
+    /// 00FC SCL
+    ScrollLeft,
+    /// 00FD EXIT
+    Exit,
+    /// 00FE LOW
+    LowRes,
+    /// 00FF HIGH
+    HighRes,
+    /// 1nnn JP addr
+    Jump { nnn: u16 },
+    /// 2nnn CALL addr
+    Call { nnn: u16 },
+    /// 3xkk SE Vx, byte
+    SkipEqByte { x: Register, kk: u8 },
+    /// 4xkk SNE Vx, byte
+    SkipNeByte { x: Register, kk: u8 },
+    /// 5xy0 SE Vx, Vy
+    SkipEq { x: Register, y: Register },
+    /// 5xy2 SAVE Vx - Vy (XO-CHIP)
+    SaveRange { x: Register, y: Register },
+    /// 5xy3 LOAD Vx - Vy (XO-CHIP)
+    LoadRange { x: Register, y: Register },
+    /// 6xkk LD Vx, byte
+    LoadByte { x: Register, kk: u8 },
+    /// 7xkk ADD Vx, byte
+    AddByte { x: Register, kk: u8 },
+    /// 8xy0 LD Vx, Vy
+    Move { x: Register, y: Register },
+    /// 8xy1 OR Vx, Vy
+    Or { x: Register, y: Register },
+    /// 8xy2 AND Vx, Vy
+    And { x: Register, y: Register },
+    /// 8xy3 XOR Vx, Vy
+    Xor { x: Register, y: Register },
+    /// 8xy4 ADD Vx, Vy
+    Add { x: Register, y: Register },
+    /// 8xy5 SUB Vx, Vy
+    Sub { x: Register, y: Register },
+    /// 8xy6 SHR Vx {, Vy}
+    ShiftRight { x: Register, y: Register },
+    /// 8xy7 SUBN Vx, Vy
+    SubN { x: Register, y: Register },
+    /// 8xyE SHL Vx {, Vy}
+    ShiftLeft { x: Register, y: Register },
+    /// 9xy0 SNE Vx, Vy
+    SkipNe { x: Register, y: Register },
+    /// Annn LD I, addr
+    LoadIndex { nnn: u16 },
+    /// Bnnn JP V0, addr
+    JumpOffset { nnn: u16 },
+    /// Cxkk RND Vx, byte
+    Random { x: Register, kk: u8 },
+    /// Dxyn DRW Vx, Vy, nibble
+    Draw { x: Register, y: Register, n: u8 },
+    /// Ex9E SKP Vx
+    SkipKey { x: Register },
+    /// ExA1 SKNP Vx
+    SkipNotKey { x: Register },
+    /// F000 nnnn LD I, long addr (XO-CHIP), the address is in the following word
+    LoadIndexLong,
+    /// Fn01 PLANE n (XO-CHIP)
+    Plane { n: u8 },
+    /// F002 AUDIO (XO-CHIP)
+    Audio,
+    /// Fx07 LD Vx, DT
+    GetDelay { x: Register },
+    /// Fx0A LD Vx, K
+    WaitKey { x: Register },
+    /// Fx15 LD DT, Vx
+    SetDelay { x: Register },
+    /// Fx18 LD ST, Vx
+    SetSound { x: Register },
+    /// Fx1E ADD I, Vx
+    AddIndex { x: Register },
+    /// Fx29 LD F, Vx
+    Font { x: Register },
+    /// Fx30 LD HF, Vx
+    BigFont { x: Register },
+    /// Fx33 LD B, Vx
+    Bcd { x: Register },
+    /// Fx3A PITCH Vx (XO-CHIP)
+    Pitch { x: Register },
+    /// Fx55 LD [I], Vx
+    Store { x: Register },
+    /// Fx65 LD Vx, [I]
+    Restore { x: Register },
+    /// Fx75 LD R, Vx
+    SaveFlags { x: Register },
+    /// Fx85 LD Vx, R
+    LoadFlags { x: Register },
+}
+
+impl Instruction {
+    /// Decodes an opcode, returning `None` for words that are not instructions.
+    pub fn decode(opcode: u16) -> Option<Instruction> {
+        let x = Register(((opcode & 0x0F00) >> 8) as u8);
+        let y = Register(((opcode & 0x00F0) >> 4) as u8);
+        let nnn = opcode & 0x0FFF;
+        let kk = (opcode & 0x00FF) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let instruction = match (opcode & 0xF000) >> 12 {
+            0x0 => match nnn {
+                0x0C0..=0x0CF => Instruction::ScrollDown { n },
+                0x0D0..=0x0DF => Instruction::ScrollUp { n },
+                0x0E0 => Instruction::Cls,
+                0x0EE => Instruction::Ret,
+                0x0FB => Instruction::ScrollRight,
+                0x0FC => Instruction::ScrollLeft,
+                0x0FD => Instruction::Exit,
+                0x0FE => Instruction::LowRes,
+                0x0FF => Instruction::HighRes,
+                _ => return None,
+            },
+            0x1 => Instruction::Jump { nnn },
+            0x2 => Instruction::Call { nnn },
+            0x3 => Instruction::SkipEqByte { x, kk },
+            0x4 => Instruction::SkipNeByte { x, kk },
+            0x5 => match n {
+                0x0 => Instruction::SkipEq { x, y },
+                0x2 => Instruction::SaveRange { x, y },
+                0x3 => Instruction::LoadRange { x, y },
+                _ => return None,
+            },
+            0x6 => Instruction::LoadByte { x, kk },
+            0x7 => Instruction::AddByte { x, kk },
+            0x8 => match n {
+                0x0 => Instruction::Move { x, y },
+                0x1 => Instruction::Or { x, y },
+                0x2 => Instruction::And { x, y },
+                0x3 => Instruction::Xor { x, y },
+                0x4 => Instruction::Add { x, y },
+                0x5 => Instruction::Sub { x, y },
+                0x6 => Instruction::ShiftRight { x, y },
+                0x7 => Instruction::SubN { x, y },
+                0xE => Instruction::ShiftLeft { x, y },
+                _ => return None,
+            },
+            0x9 if n == 0 => Instruction::SkipNe { x, y },
+            0xA => Instruction::LoadIndex { nnn },
+            0xB => Instruction::JumpOffset { nnn },
+            0xC => Instruction::Random { x, kk },
+            0xD => Instruction::Draw { x, y, n },
+            0xE => match kk {
+                0x9E => Instruction::SkipKey { x },
+                0xA1 => Instruction::SkipNotKey { x },
+                _ => return None,
+            },
+            0xF => match kk {
+                0x00 if x.0 == 0 => Instruction::LoadIndexLong,
+                0x01 => Instruction::Plane { n: x.0 },
+                0x02 if x.0 == 0 => Instruction::Audio,
+                0x07 => Instruction::GetDelay { x },
+                0x0A => Instruction::WaitKey { x },
+                0x15 => Instruction::SetDelay { x },
+                0x18 => Instruction::SetSound { x },
+                0x1E => Instruction::AddIndex { x },
+                0x29 => Instruction::Font { x },
+                0x30 => Instruction::BigFont { x },
+                0x33 => Instruction::Bcd { x },
+                0x3A => Instruction::Pitch { x },
+                0x55 => Instruction::Store { x },
+                0x65 => Instruction::Restore { x },
+                0x75 => Instruction::SaveFlags { x },
+                0x85 => Instruction::LoadFlags { x },
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(instruction)
+    }
+
+    /// Encodes the instruction back into its opcode.
+    /// Operands are masked to the width of their field.
+    pub fn encode(self) -> u16 {
+        let xy = |base: u16, x: Register, y: Register, n: u16| {
+            base | ((x.0 as u16) << 8) | ((y.0 as u16) << 4) | n
+        };
+        let xkk = |base: u16, x: Register, kk: u8| base | ((x.0 as u16) << 8) | kk as u16;
+        let fx = |x: Register, kk: u16| 0xF000 | ((x.0 as u16) << 8) | kk;
+        match self {
+            Instruction::ScrollDown { n } => 0x00C0 | (n & 0xF) as u16,
+            Instruction::ScrollUp { n } => 0x00D0 | (n & 0xF) as u16,
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::Jump { nnn } => 0x1000 | (nnn & 0x0FFF),
+            Instruction::Call { nnn } => 0x2000 | (nnn & 0x0FFF),
+            Instruction::SkipEqByte { x, kk } => xkk(0x3000, x, kk),
+            Instruction::SkipNeByte { x, kk } => xkk(0x4000, x, kk),
+            Instruction::SkipEq { x, y } => xy(0x5000, x, y, 0x0),
+            Instruction::SaveRange { x, y } => xy(0x5000, x, y, 0x2),
+            Instruction::LoadRange { x, y } => xy(0x5000, x, y, 0x3),
+            Instruction::LoadByte { x, kk } => xkk(0x6000, x, kk),
+            Instruction::AddByte { x, kk } => xkk(0x7000, x, kk),
+            Instruction::Move { x, y } => xy(0x8000, x, y, 0x0),
+            Instruction::Or { x, y } => xy(0x8000, x, y, 0x1),
+            Instruction::And { x, y } => xy(0x8000, x, y, 0x2),
+            Instruction::Xor { x, y } => xy(0x8000, x, y, 0x3),
+            Instruction::Add { x, y } => xy(0x8000, x, y, 0x4),
+            Instruction::Sub { x, y } => xy(0x8000, x, y, 0x5),
+            Instruction::ShiftRight { x, y } => xy(0x8000, x, y, 0x6),
+            Instruction::SubN { x, y } => xy(0x8000, x, y, 0x7),
+            Instruction::ShiftLeft { x, y } => xy(0x8000, x, y, 0xE),
+            Instruction::SkipNe { x, y } => xy(0x9000, x, y, 0x0),
+            Instruction::LoadIndex { nnn } => 0xA000 | (nnn & 0x0FFF),
+            Instruction::JumpOffset { nnn } => 0xB000 | (nnn & 0x0FFF),
+            Instruction::Random { x, kk } => xkk(0xC000, x, kk),
+            Instruction::Draw { x, y, n } => xy(0xD000, x, y, (n & 0xF) as u16),
+            Instruction::SkipKey { x } => xkk(0xE000, x, 0x9E),
+            Instruction::SkipNotKey { x } => xkk(0xE000, x, 0xA1),
+            Instruction::LoadIndexLong => 0xF000,
+            Instruction::Plane { n } => 0xF001 | (((n & 0xF) as u16) << 8),
+            Instruction::Audio => 0xF002,
+            Instruction::GetDelay { x } => fx(x, 0x07),
+            Instruction::WaitKey { x } => fx(x, 0x0A),
+            Instruction::SetDelay { x } => fx(x, 0x15),
+            Instruction::SetSound { x } => fx(x, 0x18),
+            Instruction::AddIndex { x } => fx(x, 0x1E),
+            Instruction::Font { x } => fx(x, 0x29),
+            Instruction::BigFont { x } => fx(x, 0x30),
+            Instruction::Bcd { x } => fx(x, 0x33),
+            Instruction::Pitch { x } => fx(x, 0x3A),
+            Instruction::Store { x } => fx(x, 0x55),
+            Instruction::Restore { x } => fx(x, 0x65),
+            Instruction::SaveFlags { x } => fx(x, 0x75),
+            Instruction::LoadFlags { x } => fx(x, 0x85),
+        }
+    }
+
+    /// Length in bytes, including the address word following `LoadIndexLong`.
+    pub fn size(self) -> u16 {
+        match self {
+            Instruction::LoadIndexLong => 4,
+            _ => 2,
+        }
+    }
+
+    /// Whether the instruction only exists in XO-CHIP.
+    pub fn is_xo_chip(self) -> bool {
+        matches!(
+            self,
+            Instruction::ScrollUp { .. }
+                | Instruction::SaveRange { .. }
+                | Instruction::LoadRange { .. }
+                | Instruction::LoadIndexLong
+                | Instruction::Plane { .. }
+                | Instruction::Audio
+                | Instruction::Pitch { .. }
+        )
+    }
+}
+
+impl fmt::Display for Instruction {
+    /// Formats the instruction in the mnemonic syntax documented on the `OP_*` handlers.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::ScrollDown { n } => write!(f, "SCD {}", n),
+            Instruction::ScrollUp { n } => write!(f, "SCU {}", n),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LowRes => write!(f, "LOW"),
+            Instruction::HighRes => write!(f, "HIGH"),
+            Instruction::Jump { nnn } => write!(f, "JP 0x{:03X}", nnn),
+            Instruction::Call { nnn } => write!(f, "CALL 0x{:03X}", nnn),
+            Instruction::SkipEqByte { x, kk } => write!(f, "SE {}, 0x{:02X}", x, kk),
+            Instruction::SkipNeByte { x, kk } => write!(f, "SNE {}, 0x{:02X}", x, kk),
+            Instruction::SkipEq { x, y } => write!(f, "SE {}, {}", x, y),
+            Instruction::SaveRange { x, y } => write!(f, "SAVE {}, {}", x, y),
+            Instruction::LoadRange { x, y } => write!(f, "LOAD {}, {}", x, y),
+            Instruction::LoadByte { x, kk } => write!(f, "LD {}, 0x{:02X}", x, kk),
+            Instruction::AddByte { x, kk } => write!(f, "ADD {}, 0x{:02X}", x, kk),
+            Instruction::Move { x, y } => write!(f, "LD {}, {}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR {}, {}", x, y),
+            Instruction::And { x, y } => write!(f, "AND {}, {}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR {}, {}", x, y),
+            Instruction::Add { x, y } => write!(f, "ADD {}, {}", x, y),
+            Instruction::Sub { x, y } => write!(f, "SUB {}, {}", x, y),
+            Instruction::ShiftRight { x, y } => write!(f, "SHR {}, {}", x, y),
+            Instruction::SubN { x, y } => write!(f, "SUBN {}, {}", x, y),
+            Instruction::ShiftLeft { x, y } => write!(f, "SHL {}, {}", x, y),
+            Instruction::SkipNe { x, y } => write!(f, "SNE {}, {}", x, y),
+            Instruction::LoadIndex { nnn } => write!(f, "LD I, 0x{:03X}", nnn),
+            Instruction::JumpOffset { nnn } => write!(f, "JP V0, 0x{:03X}", nnn),
+            Instruction::Random { x, kk } => write!(f, "RND {}, 0x{:02X}", x, kk),
+            Instruction::Draw { x, y, n } => write!(f, "DRW {}, {}, {}", x, y, n),
+            Instruction::SkipKey { x } => write!(f, "SKP {}", x),
+            Instruction::SkipNotKey { x } => write!(f, "SKNP {}", x),
+            Instruction::LoadIndexLong => write!(f, "LD I, LONG"),
+            Instruction::Plane { n } => write!(f, "PLANE {}", n),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::GetDelay { x } => write!(f, "LD {}, DT", x),
+            Instruction::WaitKey { x } => write!(f, "LD {}, K", x),
+            Instruction::SetDelay { x } => write!(f, "LD DT, {}", x),
+            Instruction::SetSound { x } => write!(f, "LD ST, {}", x),
+            Instruction::AddIndex { x } => write!(f, "ADD I, {}", x),
+            Instruction::Font { x } => write!(f, "LD F, {}", x),
+            Instruction::BigFont { x } => write!(f, "LD HF, {}", x),
+            Instruction::Bcd { x } => write!(f, "LD B, {}", x),
+            Instruction::Pitch { x } => write!(f, "PITCH {}", x),
+            Instruction::Store { x } => write!(f, "LD [I], {}", x),
+            Instruction::Restore { x } => write!(f, "LD {}, [I]", x),
+            Instruction::SaveFlags { x } => write!(f, "LD R, {}", x),
+            Instruction::LoadFlags { x } => write!(f, "LD {}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_instruction_encodes_back_to_its_opcode() {
+        for opcode in 0..=u16::MAX {
+            if let Some(instruction) = Instruction::decode(opcode) {
+                assert_eq!(instruction.encode(), opcode, "{}", instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn operands_are_typed() {
+        let v = |n| Register::new(n).unwrap();
+        assert_eq!(Instruction::decode(0xD12F), Some(Instruction::Draw { x: v(1), y: v(2), n: 0xF }));
+        assert_eq!(Instruction::decode(0xB345), Some(Instruction::JumpOffset { nnn: 0x345 }));
+        assert_eq!(Instruction::decode(0xFA1E), Some(Instruction::AddIndex { x: v(0xA) }));
+        assert_eq!(Instruction::decode(0xF301), Some(Instruction::Plane { n: 3 }));
+        assert_eq!(Register::new(0x10), None);
+    }
+
+    #[test]
+    fn words_outside_the_instruction_set_do_not_decode() {
+        for opcode in [0x0000, 0x0123, 0x5121, 0x800F, 0x9001, 0xE19F, 0xF100, 0xF102, 0xF0FF] {
+            assert_eq!(Instruction::decode(opcode), None, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn mnemonics_follow_the_handlers() {
+        let text = |opcode| Instruction::decode(opcode).unwrap().to_string();
+        assert_eq!(text(0x8AB6), "SHR VA, VB");
+        assert_eq!(text(0xF355), "LD [I], V3");
+        assert_eq!(text(0x00C4), "SCD 4");
+        assert_eq!(text(0xC7F0), "RND V7, 0xF0");
+        assert_eq!(Instruction::decode(0xF000).map(Instruction::size), Some(4));
+        assert!(Instruction::decode(0x5013).is_some_and(Instruction::is_xo_chip));
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::savestate::crc32;
+
+/// Host keys bound to each CHIP-8 key, by name. The names are SDL's key names
+/// ("X", "1", "Left", "Space", "Keypad 4"...) compared without regard to case;
+/// the terminal frontend uses the same names.
+///
+/// Game controller inputs are named after SDL's controller mapping names with a
+/// "Pad " prefix: buttons such as "Pad A", "Pad Start" or "Pad DPUp", and stick or
+/// trigger directions such as "Pad LeftX-", "Pad LeftY+" or "Pad LeftTrigger+".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [Vec<String>; 0x10],
+}
+
+impl Keymap {
+    /// The standard layout: 1234/QWER/ASDF/ZXCV on the left of a QWERTY keyboard, and
+    /// on a controller the D-pad and left stick on 2/4/6/8, the keys most games steer
+    /// with, and A on 5.
+    pub fn standard() -> Self {
+        let names = ["X", "1", "2", "3", "Q", "W", "E", "A", "S", "D", "Z", "C", "4", "R", "F", "V"];
+        let mut keymap = Keymap { keys: names.map(|name| vec![name.to_owned()]) };
+        let pad = [
+            (0x2, "Pad DPUp"),
+            (0x2, "Pad LeftY-"),
+            (0x4, "Pad DPLeft"),
+            (0x4, "Pad LeftX-"),
+            (0x5, "Pad A"),
+            (0x6, "Pad DPRight"),
+            (0x6, "Pad LeftX+"),
+            (0x8, "Pad DPDown"),
+            (0x8, "Pad LeftY+"),
+        ];
+        for (key, name) in pad {
+            keymap.keys[key].push(name.to_owned());
+        }
+        keymap
+    }
+
+    /// The CHIP-8 key a host key is bound to.
+    pub fn key_for(&self, name: &str) -> Option<u8> {
+        self.keys
+            .iter()
+            .position(|names| names.iter().any(|bound| bound.eq_ignore_ascii_case(name)))
+            .map(|key| key as u8)
+    }
+
+    /// The host keys bound to a CHIP-8 key.
+    pub fn names(&self, key: u8) -> &[String] {
+        &self.keys[key as usize & 0xF]
+    }
+
+    /// Replaces the host keys bound to a CHIP-8 key.
+    pub fn bind(&mut self, key: u8, names: Vec<String>) {
+        self.keys[key as usize & 0xF] = names;
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::standard()
+    }
+}
+
+/// Identifies a ROM in the config file, the CRC-32 of its contents.
+pub fn rom_hash(rom: &[u8]) -> u32 {
+    crc32(rom)
+}
+
+/// The keymap config file: a default layout and per-ROM overrides.
+///
+/// ```toml
+/// [default]
+/// 5 = ["W", "Up"]
+///
+/// [rom.6f5a3e7c]
+/// 4 = "Left"
+/// 5 = "Up"
+/// 6 = "Right"
+/// ```
+///
+/// Keys are the CHIP-8 keys 0 to F, values a host key or controller input name or a
+/// list of them.
+/// Keys missing from `[default]` keep the standard layout, keys missing from a
+/// `[rom.<hash>]` section keep the default ones.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeymapConfig {
+    default: BTreeMap<u8, Vec<String>>,
+    roms: BTreeMap<u32, BTreeMap<u8, Vec<String>>>,
+}
+
+impl KeymapConfig {
+    /// Reads a config file; a missing file gives the standard layout.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => text.parse().map_err(|e| format!("{}:{}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(KeymapConfig::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_string())
+    }
+
+    /// The layout for a ROM: the standard one, then `[default]`, then the ROM's section.
+    pub fn keymap_for(&self, rom: &[u8]) -> Keymap {
+        let mut keymap = Keymap::standard();
+        let overrides = self.roms.get(&rom_hash(rom));
+        for (&key, names) in self.default.iter().chain(overrides.into_iter().flatten()) {
+            keymap.bind(key, names.clone());
+        }
+        keymap
+    }
+
+    /// Stores a whole layout as the ROM's section.
+    pub fn set_rom_keymap(&mut self, rom: &[u8], keymap: &Keymap) {
+        let keys = (0..0x10).map(|key| (key, keymap.names(key).to_vec())).collect();
+        self.roms.insert(rom_hash(rom), keys);
+    }
+}
+
+/// `$XDG_CONFIG_HOME/chip8-h/keymap.toml`, or under `~/.config` without it.
+pub fn default_config_path() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("chip8-h").join("keymap.toml"))
+}
+
+impl std::str::FromStr for KeymapConfig {
+    type Err = String;
+
+    /// Parses the subset of TOML the config uses. Errors start with the line number.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut config = KeymapConfig::default();
+        let mut section: Option<&mut BTreeMap<u8, Vec<String>>> = None;
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: String| format!("{}: {}", number + 1, message);
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                let header = header.trim();
+                section = if header == "default" {
+                    Some(&mut config.default)
+                } else if let Some(hash) = header.strip_prefix("rom.") {
+                    let hash = u32::from_str_radix(hash.trim_matches('"'), 16)
+                        .map_err(|_| error(format!("invalid ROM hash {:?}", hash)))?;
+                    Some(config.roms.entry(hash).or_default())
+                } else {
+                    return Err(error(format!("unknown section [{}], expected [default] or [rom.<hash>]", header)));
+                };
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error(format!("expected KEY = \"name\", found {:?}", line)));
+            };
+            let key = key.trim().trim_matches('"');
+            let key = u8::from_str_radix(key, 16)
+                .ok()
+                .filter(|&key| key < 0x10)
+                .ok_or_else(|| error(format!("invalid CHIP-8 key {:?}, expected 0 to F", key)))?;
+            let names = parse_names(value.trim()).map_err(error)?;
+            let Some(section) = section.as_mut() else {
+                return Err(error("key outside of a [default] or [rom.<hash>] section".to_owned()));
+            };
+            section.insert(key, names);
+        }
+        Ok(config)
+    }
+}
+
+impl fmt::Display for KeymapConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let section = |f: &mut fmt::Formatter<'_>, keys: &BTreeMap<u8, Vec<String>>| -> fmt::Result {
+            for (key, names) in keys {
+                let names: Vec<String> = names.iter().map(|name| quote(name)).collect();
+                writeln!(f, "{:X} = [{}]", key, names.join(", "))?;
+            }
+            Ok(())
+        };
+        writeln!(f, "[default]")?;
+        section(f, &self.default)?;
+        for (hash, keys) in &self.roms {
+            writeln!(f, "\n[rom.{:08x}]", hash)?;
+            section(f, keys)?;
+        }
+        Ok(())
+    }
+}
+
+/// Cuts a `#` comment that is not inside a string.
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Parses `"name"` or `["name", ...]`.
+fn parse_names(value: &str) -> Result<Vec<String>, String> {
+    let list = value.strip_prefix('[').map(|value| value.strip_suffix(']').ok_or("unterminated list"));
+    let mut rest = match list {
+        Some(list) => list?.trim(),
+        None => value,
+    };
+    let mut names = Vec::new();
+    while !rest.is_empty() {
+        let (name, after) = parse_string(rest)?;
+        names.push(name);
+        rest = after.trim_start();
+        if list.is_none() && !rest.is_empty() {
+            return Err(format!("unexpected {:?} after the key name", rest));
+        }
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+        } else if !rest.is_empty() {
+            return Err(format!("expected , between key names, found {:?}", rest));
+        }
+    }
+    if names.is_empty() {
+        return Err("no key names".to_owned());
+    }
+    Ok(names)
+}
+
+/// Parses a basic string at the start of `text`, returning it and what follows.
+fn parse_string(text: &str) -> Result<(String, &str), String> {
+    let Some(body) = text.strip_prefix('"') else {
+        return Err(format!("expected a quoted key name, found {:?}", text));
+    };
+    let mut name = String::new();
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((name, &body[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, '"')) => name.push('"'),
+                Some((_, '\\')) => name.push('\\'),
+                _ => return Err("only \\\" and \\\\ escapes are supported".to_owned()),
+            },
+            c => name.push(c),
+        }
+    }
+    Err("unterminated string".to_owned())
+}
+
+fn quote(name: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in name.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Whether a name is a game controller input rather than a keyboard key.
+fn is_pad(name: &str) -> bool {
+    name.get(..4).is_some_and(|prefix| prefix.eq_ignore_ascii_case("Pad "))
+}
+
+/// Walks through the keypad asking for a host key for each CHIP-8 key in turn,
+/// used to remap the keys interactively.
+#[derive(Clone, Debug)]
+pub struct Remap {
+    keymap: Keymap,
+    next: u8,
+    /// Names given to a key during this remap.
+    taken: Vec<String>,
+}
+
+impl Remap {
+    /// Starts from `keymap`: a key pressed replaces the keyboard keys of the CHIP-8 key
+    /// and a controller input its controller inputs, so remapping one keeps the other.
+    pub fn new(keymap: Keymap) -> Self {
+        Remap { keymap, next: 0, taken: Vec::new() }
+    }
+
+    /// What to ask the player for next.
+    pub fn prompt(&self) -> String {
+        format!("press the key for CHIP-8 key {:X} (Escape cancels)", self.next)
+    }
+
+    /// Binds the host key to the current CHIP-8 key, returning the finished layout
+    /// after the last one. A host key already given to an earlier key is ignored.
+    pub fn press(&mut self, name: &str) -> Option<Keymap> {
+        if self.taken.iter().any(|taken| taken.eq_ignore_ascii_case(name)) {
+            return None;
+        }
+        for names in &mut self.keymap.keys {
+            names.retain(|bound| !bound.eq_ignore_ascii_case(name));
+        }
+        let names = &mut self.keymap.keys[self.next as usize];
+        names.retain(|bound| is_pad(bound) != is_pad(name));
+        names.push(name.to_owned());
+        self.taken.push(name.to_owned());
+        self.next += 1;
+        (self.next == 0x10).then(|| self.keymap.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 2] = [0x12, 0x00];
+
+    #[test]
+    fn sections_override_the_standard_layout() {
+        let text = format!(
+            "[default]\n5 = [\"W\", \"Up\"] # steer\n\n[rom.{:08x}]\n5 = \"Pad B\"\nA = \"#\"\n",
+            rom_hash(&ROM)
+        );
+        let config: KeymapConfig = text.parse().unwrap();
+        let other = config.keymap_for(&[]);
+        assert_eq!(other.names(0x5), ["W", "Up"]);
+        assert_eq!(other.names(0x4), Keymap::standard().names(0x4));
+        let keymap = config.keymap_for(&ROM);
+        assert_eq!(keymap.names(0x5), ["Pad B"]);
+        assert_eq!(keymap.key_for("#"), Some(0xA));
+        assert_eq!(keymap.key_for("up"), None);
+    }
+
+    #[test]
+    fn configs_round_trip() {
+        let mut config: KeymapConfig = "[default]\nf = [\"Keypad \\\"+\\\"\", \"Pad LeftX-\"]\n".parse().unwrap();
+        config.set_rom_keymap(&ROM, &Keymap::standard());
+        let text = config.to_string();
+        assert_eq!(text.parse::<KeymapConfig>().unwrap(), config);
+        assert_eq!(config.keymap_for(&[]).key_for("keypad \"+\""), Some(0xF));
+    }
+
+    #[test]
+    fn errors_give_the_line() {
+        let error = |text: &str| text.parse::<KeymapConfig>().unwrap_err();
+        assert_eq!(error("[default]\n\nG = \"X\""), "3: invalid CHIP-8 key \"G\", expected 0 to F");
+        assert!(error("1 = \"X\"").starts_with("1: key outside"));
+        assert!(error("[default]\n1 = [\"X\", \"Y\"").starts_with("2: unterminated list"));
+        assert!(error("[default]\n1 = \"X\" \"Y\"").starts_with("2: unexpected"));
+        assert!(error("[default]\n1 = []").starts_with("2: no key names"));
+        assert!(error("[roms]").starts_with("1: unknown section"));
+        assert!(error("[rom.xyz]").starts_with("1: invalid ROM hash"));
+    }
+
+    #[test]
+    fn remapping_keeps_controller_bindings() {
+        let mut remap = Remap::new(Keymap::standard());
+        assert_eq!(remap.press("Keypad 0"), None);
+        // a key already given is ignored
+        assert_eq!(remap.press("keypad 0"), None);
+        assert!(remap.prompt().contains("key 1"));
+        let keymap = (1..0x10).find_map(|key| remap.press(&format!("F{}", key + 12))).unwrap();
+        assert_eq!(keymap.key_for("Keypad 0"), Some(0x0));
+        assert_eq!(keymap.key_for("F14"), Some(0x2));
+        assert_eq!(keymap.key_for("X"), None);
+        assert_eq!(keymap.names(0x2), ["Pad DPUp", "Pad LeftY-", "F14"]);
+    }
+}
@@ -0,0 +1,30 @@
+//! CHIP-8, SUPER-CHIP and XO-CHIP interpreter core.
+//!
+//! `Chip8` is the machine: load a ROM with `Chip8::load_rom`, then call
+//! `Chip8::run_frame` 60 times a second, pressing and releasing keys in between and
+//! drawing `Chip8::framebuffer` in a `palette::Palette` after each frame. Everything
+//! here is independent of any window, sound or input library.
+
+pub mod assembler;
+#[allow(non_snake_case)]
+pub mod chip8;
+pub mod condition;
+pub mod disasm;
+pub mod error;
+pub mod frontend;
+pub mod gif;
+pub mod headless;
+pub mod image;
+pub mod instruction;
+pub mod keymap;
+pub mod movie;
+pub mod palette;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+mod savestate;
+pub mod trace;
+
+pub use chip8::{Chip8, MemoryAccess, StepOutcome, Variant};
+pub use error::{EmulationError, MachineState, RomError, StateError};
+pub use quirks::Quirks;
@@ -1,50 +1,976 @@
-#![allow(arithmetic_overflow)]
-use std::ffi::c_void;
+#[cfg(feature = "sdl")]
+use chip8_h::chip8::{VIDEO_HEIGHT, VIDEO_WIDTH};
+#[cfg(any(feature = "sdl", feature = "tty"))]
+use chip8_h::frontend::{Audio, Clock, Display, Frontend, Hotkey, Input, SystemClock};
+use chip8_h::{assembler, chip8, condition, disasm, gif, headless, image, movie, palette, quirks, random, trace};
+#[cfg(any(feature = "sdl", feature = "tty"))]
+use chip8_h::{keymap, movie::Movie, rewind};
+#[cfg(any(feature = "sdl", feature = "tty"))]
+use std::path::PathBuf;
 
-use chip8::{VIDEO_HEIGHT, VIDEO_WIDTH};
-
-#[allow(non_snake_case)]
-#[allow(dead_code)]
-mod chip8;
+#[cfg(feature = "sdl")]
+mod audio;
+#[cfg(any(feature = "sdl", feature = "tty"))]
+mod debugger;
+#[cfg(feature = "sdl")]
 mod platform;
+#[cfg(feature = "tty")]
+mod tty;
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() != 4 {
-        eprintln!("Usage: {:?}  <Scale> <Delay> <ROM>", args[0]);
-    }
-    let videoScale = args[1].parse::<i32>().unwrap();
-    let cycle_delay = args[2].parse::<i32>().unwrap();
-    let romFilename = &args[3];
-    let mut platform = platform::Platform::new(
-        "CHIP-8 Emulator".to_owned(),
-        (VIDEO_WIDTH as i32) * videoScale,
-        (VIDEO_HEIGHT as i32) * videoScale,
-        VIDEO_WIDTH as i32,
-        VIDEO_HEIGHT as i32,
-    );
-
-    let mut chip8 = chip8::Chip8::new();
-    chip8.load_ROM(romFilename.to_owned()).unwrap();
-
-    let video_pitch = std::mem::size_of::<u32>() * (VIDEO_WIDTH as usize) ;
-
-    let mut last_cycle_time = std::time::Instant::now();
+    if args.get(1).map(String::as_str) == Some("disasm") {
+        return disasm_main(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("asm") {
+        return asm_main(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("headless") {
+        return headless_main(args);
+    }
+    if args.get(1).map(String::as_str) == Some("tty") {
+        return tty_main(args);
+    }
+    if args.get(1).map(String::as_str) == Some("trace-diff") {
+        return trace_diff_main(&args);
+    }
+    window_main(args);
+}
+
+/// Runs the ROM in a window, the default when no subcommand is given.
+#[cfg(feature = "sdl")]
+fn window_main(mut args: Vec<String>) {
+    let debug = take_flag(&mut args, "--debug");
+    let audio_settings = audio_settings(&mut args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let keymap_path = keymap_path(&mut args);
+    let rng = rng_options(&mut args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let tracer = tracer(&mut args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let movie = movie_arg(&mut args);
+    let (screenshot_scale, gif_limit, palette) = screenshot_scale(&mut args)
+        .and_then(|scale| Ok((scale, gif_limit(&mut args)?, palette(&mut args)?)))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    if debug && movie.is_some() {
+        eprintln!("--debug cannot be combined with --record or --play");
+        std::process::exit(1);
+    }
+    if args.len() != 4 && args.len() != 5 {
+        eprintln!(
+            "Usage: {:?}  [--debug] [--wave square|sine|triangle|noise] [--freq <Hz>] [--volume <0-1>] [--mute] \
+             [--keymap <FILE>] [--rng seeded|os] [--seed <N>] [--record <MOVIE> | --play <MOVIE>] \
+             [--trace <FILE|-> [--trace-binary] [--trace-pc <FROM-TO>] [--trace-cycles <FROM-TO>]] \
+             [--screenshot-scale <N>] [--gif-limit <SECONDS>] \
+             [--palette <NAME>] [--palettes <FILE>] <Scale> <InstructionsPerFrame> <ROM> [vip|chip48|schip|xo-chip[,+QUIRK|,-QUIRK]...]",
+            args[0]
+        );
+        eprintln!("       {:?}  disasm [--syntax octo|cowgod] <ROM>", args[0]);
+        eprintln!("       {:?}  asm <SOURCE> <ROM>", args[0]);
+        eprintln!("       {:?}  trace-diff <TRACE> <TRACE>", args[0]);
+        eprintln!("       {:?}  {}", args[0], TTY_USAGE);
+        eprintln!("       {:?}  {}", args[0], HEADLESS_USAGE);
+        std::process::exit(1);
+    }
+    let video_scale = args[1].parse::<u32>().ok().filter(|&scale| scale > 0).unwrap_or_else(|| {
+        eprintln!("invalid scale {}", args[1]);
+        std::process::exit(1);
+    });
+    let instructions_per_frame = args[2].parse::<u32>().unwrap_or_else(|_| {
+        eprintln!("invalid instructions per frame {}", args[2]);
+        std::process::exit(1);
+    });
+    let mut session = Session::open(&args[3], instructions_per_frame, keymap_path);
+    session.screenshot_scale = screenshot_scale;
+    session.gif_limit = gif_limit;
+    session.palette = palette;
+    let (variant, quirks) = profile(args.get(4));
+    let title = "CHIP-8 Emulator";
+    let (width, height) = (VIDEO_WIDTH * video_scale, VIDEO_HEIGHT * video_scale);
+    let platform = platform::Platform::new(title, width, height, session.keymap(), session.palette)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    let audio = platform.audio.as_ref().and_then(|subsystem| {
+        audio::SdlAudio::new(subsystem, audio_settings)
+            .map_err(|e| eprintln!("audio disabled: {}", e))
+            .ok()
+    });
+
+    let mut chip8 = session.machine(variant, quirks, rng, movie);
+    if let Some(tracer) = tracer {
+        chip8.set_trace(tracer);
+    }
+    let mut frontend = Frontend { display: platform.display, input: platform.input, audio, clock: SystemClock::new() };
+    let result = run(&mut chip8, &mut frontend, &mut session, debug.then(|| debugger::Debugger::new(debugger::stdin_commands())));
+    for message in [session.stop_movie(), session.stop_gif()].into_iter().flatten() {
+        println!("{}", message);
+    }
+    finish_trace(&mut chip8);
+    if let Err(error) = result {
+        eprintln!("{}", error);
+    }
+}
+
+/// The ROM being played and the settings that go with it.
+#[cfg(any(feature = "sdl", feature = "tty"))]
+struct Session {
+    rom_filename: String,
+    rom: Vec<u8>,
+    instructions_per_frame: u32,
+    keymaps: keymap::KeymapConfig,
+    /// Where the keymap config is read from and remapped layouts are saved.
+    keymap_path: Option<PathBuf>,
+    movie: Option<MovieMode>,
+    /// Frames run, stored in screenshots.
+    frames: u64,
+    /// `--screenshot-scale`, the size of a pixel in screenshots and GIFs.
+    screenshot_scale: u32,
+    /// The palette the display is shown in, and screenshots and GIFs taken in.
+    palette: palette::Palette,
+    /// The GIF being recorded and its filename.
+    gif: Option<(gif::GifRecorder<std::io::BufWriter<std::fs::File>>, String)>,
+    /// `--gif-limit`, how long a GIF recording may get.
+    gif_limit: std::time::Duration,
+}
+
+/// `--record <MOVIE>` or `--play <MOVIE>`.
+#[cfg(any(feature = "sdl", feature = "tty"))]
+enum MovieArg {
+    Record(PathBuf),
+    Play(Movie),
+}
+
+/// A movie going along with the session.
+#[cfg(any(feature = "sdl", feature = "tty"))]
+enum MovieMode {
+    /// Saved to the path once the session ends.
+    Recording(Movie, PathBuf),
+    /// Played back, with the number of frames played so far.
+    Playing(Movie, u32),
+}
+
+#[cfg(any(feature = "sdl", feature = "tty"))]
+impl Session {
+    /// Reads the ROM and the keymap config, exiting on errors.
+    fn open(rom_filename: &str, instructions_per_frame: u32, keymap_path: Option<PathBuf>) -> Self {
+        let rom = std::fs::read(rom_filename).unwrap_or_else(|e| {
+            eprintln!("{}: {}", rom_filename, e);
+            std::process::exit(1);
+        });
+        let keymaps = match &keymap_path {
+            Some(path) => keymap::KeymapConfig::load(path).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }),
+            None => keymap::KeymapConfig::default(),
+        };
+        Session {
+            rom_filename: rom_filename.to_owned(),
+            rom,
+            instructions_per_frame,
+            keymaps,
+            keymap_path,
+            movie: None,
+            frames: 0,
+            screenshot_scale: 1,
+            palette: palette::Palette::default(),
+            gif: None,
+            gif_limit: gif::DEFAULT_MAX_DURATION,
+        }
+    }
+
+    /// A machine with the ROM loaded and its RNG seeded, from a fresh seed unless one
+    /// was given. Playing back a movie starts it the way the movie was recorded instead.
+    fn machine(
+        &mut self,
+        variant: chip8::Variant,
+        quirks: quirks::Quirks,
+        rng: RngOptions,
+        movie: Option<MovieArg>,
+    ) -> chip8::Chip8 {
+        let machine = match movie {
+            Some(MovieArg::Play(movie)) => {
+                let machine = movie.machine(&self.rom);
+                self.instructions_per_frame = movie.instructions_per_frame;
+                self.movie = Some(MovieMode::Playing(movie, 0));
+                machine
+            }
+            record => {
+                let mut chip8 = chip8::Chip8::with_variant(variant, quirks);
+                let seed = rng.seed.unwrap_or_else(rand::random);
+                chip8.set_rng(rng.generator.source(seed));
+                if let Some(MovieArg::Record(path)) = record {
+                    if !rng.generator.is_repeatable() {
+                        eprintln!("movies cannot be recorded with the {} generator", rng.generator);
+                        std::process::exit(1);
+                    }
+                    let movie = Movie::new(&self.rom, rng.generator, seed, variant, quirks, self.instructions_per_frame);
+                    self.movie = Some(MovieMode::Recording(movie, path));
+                }
+                chip8.load_rom(&self.rom).map(|()| chip8).map_err(|e| e.to_string())
+            }
+        };
+        machine.unwrap_or_else(|e| {
+            eprintln!("{}: {}", self.rom_filename, e);
+            std::process::exit(1);
+        })
+    }
+
+    /// Records the keys held going into a frame, or replaces them with the movie's.
+    fn movie_input(&mut self, chip8: &mut chip8::Chip8) {
+        match &mut self.movie {
+            Some(MovieMode::Recording(movie, _)) => movie.record_input(chip8),
+            Some(MovieMode::Playing(movie, frame)) => {
+                movie.play_input(*frame, chip8);
+            }
+            None => {}
+        }
+    }
+
+    /// Records or checks the display after a frame. Returns a message when playback
+    /// ends, at the end of the movie or on a desync, and when a recording reaches
+    /// `movie::MAX_FRAMES` and is saved.
+    fn movie_frame(&mut self, chip8: &chip8::Chip8) -> Option<String> {
+        let message = match &mut self.movie {
+            Some(MovieMode::Recording(movie, _)) => {
+                movie.record_check(chip8);
+                if !movie.is_full() {
+                    return None;
+                }
+                let saved = self.stop_movie()?;
+                return Some(format!("movie reached its limit of {} frames, {}", movie::MAX_FRAMES, saved));
+            }
+            Some(MovieMode::Playing(movie, frame)) => {
+                *frame += 1;
+                match movie.verify(*frame, chip8) {
+                    Err(desync) => desync.to_string(),
+                    Ok(()) if *frame >= movie.len() => "movie finished".to_owned(),
+                    Ok(()) => return None,
+                }
+            }
+            None => return None,
+        };
+        // the player takes over from here
+        self.movie = None;
+        Some(message)
+    }
+
+    /// Ends the movie, saving a recording. Rewinding and loading states call this too,
+    /// since a movie replays from power-on and cannot follow a jump in time.
+    fn stop_movie(&mut self) -> Option<String> {
+        let message = match self.movie.take()? {
+            MovieMode::Recording(movie, path) => match movie.save(&path) {
+                Ok(()) => format!("movie saved to {}", path.display()),
+                Err(e) => format!("{}: {}", path.display(), e),
+            },
+            MovieMode::Playing(..) => "movie stopped".to_owned(),
+        };
+        Some(message)
+    }
+
+    /// Starts recording a GIF next to the ROM, named after the frame, or stops and
+    /// saves the one being recorded.
+    fn toggle_gif(&mut self) -> String {
+        if let Some(message) = self.stop_gif() {
+            return message;
+        }
+        let filename = format!("{}.frame{}.gif", self.rom_filename, self.frames);
+        let recorder = std::fs::File::create(&filename)
+            .and_then(|file| gif::GifRecorder::new(std::io::BufWriter::new(file), self.screenshot_scale, self.gif_limit));
+        match recorder {
+            Ok(recorder) => {
+                self.gif = Some((recorder, filename.clone()));
+                format!("recording {}", filename)
+            }
+            Err(e) => format!("{}: {}", filename, e),
+        }
+    }
+
+    /// Captures a presented frame into the GIF being recorded. Returns a message when
+    /// the recording ends, on reaching `gif_limit` or on a write error.
+    fn gif_frame(&mut self, chip8: &chip8::Chip8) -> Option<String> {
+        let (recorder, filename) = self.gif.as_mut()?;
+        match recorder.capture_chip8(chip8, &self.palette) {
+            Ok(true) => None,
+            Ok(false) => {
+                let limit = self.gif_limit.as_secs();
+                self.stop_gif().map(|message| format!("recording reached its {} s limit, {}", limit, message))
+            }
+            Err(e) => {
+                let message = format!("{}: {}", filename, e);
+                self.gif = None;
+                Some(message)
+            }
+        }
+    }
+
+    /// Ends the GIF recording, if any, and saves it.
+    fn stop_gif(&mut self) -> Option<String> {
+        let (recorder, filename) = self.gif.take()?;
+        let message = match recorder.finish() {
+            Ok(_) => format!("GIF saved to {}", filename),
+            Err(e) => format!("{}: {}", filename, e),
+        };
+        Some(message)
+    }
+
+    /// The keypad layout configured for the ROM.
+    fn keymap(&self) -> keymap::Keymap {
+        self.keymaps.keymap_for(&self.rom)
+    }
+}
+
+/// Reads `--keymap <FILE>`, defaulting to the file in the user's config directory.
+#[cfg(any(feature = "sdl", feature = "tty"))]
+fn keymap_path(args: &mut Vec<String>) -> Option<PathBuf> {
+    let path = take_option(args, "--keymap").unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    path.map(PathBuf::from).or_else(keymap::default_config_path)
+}
+
+/// Reads `--record <MOVIE>` or `--play <MOVIE>`, exiting on errors.
+#[cfg(any(feature = "sdl", feature = "tty"))]
+fn movie_arg(args: &mut Vec<String>) -> Option<MovieArg> {
+    let options = take_option(args, "--record").and_then(|record| Ok((record, take_option(args, "--play")?)));
+    let movie = match options {
+        Ok((Some(_), Some(_))) => Err("--record and --play cannot be combined".to_owned()),
+        Ok((Some(path), None)) => Ok(Some(MovieArg::Record(PathBuf::from(path)))),
+        Ok((None, Some(path))) => Movie::load(std::path::Path::new(&path)).map(|movie| Some(MovieArg::Play(movie))),
+        Ok((None, None)) => Ok(None),
+        Err(e) => Err(e),
+    };
+    movie.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+/// Runs frames as the clock asks for them until the user quits or, outside the
+/// debugger, the program halts or fails. The error is returned rather than printed
+/// so frontends can restore the terminal first.
+#[cfg(any(feature = "sdl", feature = "tty"))]
+fn run<D: Display, I: Input, A: Audio, C: Clock>(
+    chip8: &mut chip8::Chip8,
+    frontend: &mut Frontend<D, I, A, C>,
+    session: &mut Session,
+    mut debugger: Option<debugger::Debugger>,
+) -> Result<(), chip8_h::EmulationError> {
+    let mut rewind = rewind::Rewind::new(rewind::DEFAULT_INTERVAL, rewind::DEFAULT_CAPACITY);
+    let mut keypad = [false; 0x10];
     let mut quit = false;
     while !quit {
-        quit = unsafe {
-            platform.process(chip8.keypad.as_mut_ptr() as *mut i8)
+        quit = frontend.input.poll(&mut keypad);
+        for (key, &pressed) in keypad.iter().enumerate() {
+            if pressed {
+                chip8.press_key(key as u8);
+            } else {
+                chip8.release_key(key as u8);
+            }
+        }
+        for hotkey in frontend.input.take_hotkeys() {
+            handle_hotkey(chip8, frontend, session, hotkey);
+        }
+        if !frontend.clock.frame_due() {
+            continue;
+        }
+        let rewinding = frontend.input.rewinding();
+        if rewinding {
+            if let Some(message) = session.stop_movie() {
+                frontend.display.message(&message);
+            }
+            // step back one snapshot per frame while the key is held
+            rewind.step_back(chip8);
+        } else {
+            session.movie_input(chip8);
+            match debugger.as_mut() {
+                Some(debugger) => quit |= !debugger.run_frame(chip8, session.instructions_per_frame),
+                None => {
+                    chip8.run_frame(session.instructions_per_frame)?;
+                }
+            }
+            session.frames += 1;
+            if let Some(message) = session.movie_frame(chip8) {
+                frontend.display.message(&message);
+            }
+            rewind.record(chip8);
+        }
+        frontend.display.present(chip8.framebuffer(), chip8.video_width(), chip8.video_height());
+        if let Some(message) = session.gif_frame(chip8) {
+            frontend.display.message(&message);
+        }
+        frontend.audio.set_playing(chip8.machine_state().sound_timer > 0 && !rewinding);
+        let (pattern, rate) = chip8.audio_pattern();
+        // XO-CHIP programs that never load a pattern get the plain buzzer
+        let loaded = chip8.variant() == chip8::Variant::XoChip && pattern.iter().any(|&byte| byte != 0);
+        frontend.audio.set_pattern(loaded.then_some((*pattern, rate)));
+        // the debugger keeps the window open after 00FD
+        quit |= chip8.halted() && debugger.is_none();
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "sdl"))]
+fn window_main(args: Vec<String>) {
+    eprintln!("{:?} was built without the sdl feature, use one of:", args[0]);
+    eprintln!("       {:?}  {}", args[0], TTY_USAGE);
+    eprintln!("       {:?}  {}", args[0], HEADLESS_USAGE);
+    std::process::exit(1);
+}
+
+const TTY_USAGE: &str = "tty [--keymap <FILE>] [--rng seeded|os] [--seed <N>] [--record <MOVIE> | --play <MOVIE>] \
+                         [--trace <FILE> [--trace-binary] [--trace-pc <FROM-TO>] [--trace-cycles <FROM-TO>]] \
+                         [--screenshot-scale <N>] [--gif-limit <SECONDS>] [--palette <NAME>] [--palettes <FILE>] \
+                         <InstructionsPerFrame> <ROM> [vip|chip48|schip|xo-chip[,+QUIRK|,-QUIRK]...]";
+
+/// `tty [options] <InstructionsPerFrame> <ROM> [profile]`: runs the ROM in the
+/// terminal, for sessions without a display such as SSH.
+#[cfg(feature = "tty")]
+fn tty_main(mut args: Vec<String>) {
+    let keymap_path = keymap_path(&mut args);
+    let rng = rng_options(&mut args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let tracer = tracer(&mut args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let movie = movie_arg(&mut args);
+    let (screenshot_scale, gif_limit, palette) = screenshot_scale(&mut args)
+        .and_then(|scale| Ok((scale, gif_limit(&mut args)?, palette(&mut args)?)))
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    if args.len() != 4 && args.len() != 5 {
+        eprintln!("Usage: {:?}  {}", args[0], TTY_USAGE);
+        std::process::exit(1);
+    }
+    let instructions_per_frame = args[2].parse::<u32>().unwrap_or_else(|_| {
+        eprintln!("invalid instructions per frame {}", args[2]);
+        std::process::exit(1);
+    });
+    let mut session = Session::open(&args[3], instructions_per_frame, keymap_path);
+    session.screenshot_scale = screenshot_scale;
+    session.gif_limit = gif_limit;
+    session.palette = palette;
+    let (variant, quirks) = profile(args.get(4));
+    let mut chip8 = session.machine(variant, quirks, rng, movie);
+    if let Some(tracer) = tracer {
+        chip8.set_trace(tracer);
+    }
+    let terminal = tty::Terminal::new().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let mut frontend = Frontend {
+        display: terminal.display(session.palette),
+        input: terminal.input(session.keymap()),
+        audio: chip8_h::frontend::Silent,
+        clock: SystemClock::new(),
+    };
+    // the debugger reads lines from the terminal the frontend has in raw mode
+    let result = run(&mut chip8, &mut frontend, &mut session, None);
+    drop(terminal);
+    for message in [session.stop_movie(), session.stop_gif()].into_iter().flatten() {
+        println!("{}", message);
+    }
+    finish_trace(&mut chip8);
+    if let Err(error) = result {
+        eprintln!("{}", error);
+    }
+}
+
+#[cfg(not(feature = "tty"))]
+fn tty_main(args: Vec<String>) {
+    eprintln!("{:?} was built without the tty feature", args[0]);
+    std::process::exit(1);
+}
+
+/// The variant and quirks for an optional `vip|chip48|schip|xo-chip` argument, which
+/// may set or clear single quirks as in `vip,+clip_sprites,-display_wait`.
+fn profile(name: Option<&String>) -> (chip8::Variant, quirks::Quirks) {
+    let quirks = match name {
+        Some(profile) => profile.parse::<quirks::Quirks>().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+        None => quirks::Quirks::default(),
+    };
+    let base = name.and_then(|profile| profile.split(',').next()).map(|base| base.trim().to_ascii_lowercase());
+    let variant = match base.as_deref() {
+        Some("xo-chip") | Some("xochip") => chip8::Variant::XoChip,
+        _ => chip8::Variant::Chip8,
+    };
+    (variant, quirks)
+}
+
+/// Saves or loads the numbered save state slot kept next to the ROM, mutes the sound,
+/// saves a remapped layout as the ROM's keymap, saves a screenshot next to the ROM,
+/// named after the frame, or starts and stops a GIF recording. Loading a state ends
+/// the movie.
+#[cfg(any(feature = "sdl", feature = "tty"))]
+fn handle_hotkey<D: Display, I: Input, A: Audio, C: Clock>(
+    chip8: &mut chip8::Chip8,
+    frontend: &mut Frontend<D, I, A, C>,
+    session: &mut Session,
+    hotkey: Hotkey,
+) {
+    let rom_filename = session.rom_filename.clone();
+    let slot_filename = |slot: usize| format!("{}.state{}", rom_filename, slot);
+    let message = match hotkey {
+        Hotkey::SaveState(slot) => match std::fs::write(slot_filename(slot), chip8.save_state()) {
+            Ok(()) => format!("saved state {}", slot),
+            Err(e) => format!("{}: {}", slot_filename(slot), e),
+        },
+        Hotkey::LoadState(slot) => {
+            if let Some(message) = session.stop_movie() {
+                frontend.display.message(&message);
+            }
+            let loaded = std::fs::read(slot_filename(slot))
+                .map_err(|e| e.to_string())
+                .and_then(|state| chip8.load_state(&state).map_err(|e| e.to_string()));
+            match loaded {
+                Ok(()) => format!("loaded state {}", slot),
+                Err(e) => format!("{}: {}", slot_filename(slot), e),
+            }
+        }
+        Hotkey::ToggleMute => format!("sound {}", if frontend.audio.toggle_mute() { "muted" } else { "on" }),
+        Hotkey::KeymapChanged => {
+            let Some(keymap) = frontend.input.keymap() else {
+                return;
+            };
+            session.keymaps.set_rom_keymap(&session.rom, keymap);
+            match &session.keymap_path {
+                Some(path) => match session.keymaps.save(path) {
+                    Ok(()) => format!("keymap saved to {}", path.display()),
+                    Err(e) => format!("{}: {}", path.display(), e),
+                },
+                None => "keymap changed".to_owned(),
+            }
+        }
+        Hotkey::Screenshot => {
+            let mut screenshot = image::Screenshot::new(image::Format::Png);
+            screenshot.palette = session.palette;
+            screenshot.scale = session.screenshot_scale;
+            screenshot.metadata = screenshot_metadata(&rom_filename, session.frames);
+            let filename = format!("{}.frame{}.png", rom_filename, session.frames);
+            match std::fs::write(&filename, screenshot.capture(chip8)) {
+                Ok(()) => format!("screenshot saved to {}", filename),
+                Err(e) => format!("{}: {}", filename, e),
+            }
+        }
+        Hotkey::ToggleGifRecording => session.toggle_gif(),
+    };
+    frontend.display.message(&message);
+}
+
+/// Reads the trace options: `--trace` to a file, or `-` for stderr, in text or with
+/// `--trace-binary` in binary, limited to the instructions at hexadecimal addresses
+/// in `--trace-pc` and the cycles in `--trace-cycles`. Either end of a range can be
+/// left out.
+fn tracer(args: &mut Vec<String>) -> Result<Option<Box<dyn trace::TraceSink>>, String> {
+    let binary = take_flag(args, "--trace-binary");
+    let mut filter = trace::Filter::default();
+    if let Some(range) = take_option(args, "--trace-pc")? {
+        let (from, to) = parse_range(&range, 0, u16::MAX, |address| u16::from_str_radix(address, 16).ok())
+            .ok_or_else(|| format!("invalid address range {}", range))?;
+        filter.addresses = Some(from..=to);
+    }
+    if let Some(range) = take_option(args, "--trace-cycles")? {
+        let (from, to) = parse_range(&range, 0, u64::MAX, |cycle| cycle.parse().ok())
+            .ok_or_else(|| format!("invalid cycle range {}", range))?;
+        filter.cycles = Some(from..=to);
+    }
+    let Some(path) = take_option(args, "--trace")? else {
+        return Ok(None);
+    };
+    let format = if binary { trace::Format::Binary } else { trace::Format::Text };
+    if path == "-" {
+        return Ok(Some(Box::new(trace::Tracer::new(std::io::BufWriter::new(std::io::stderr()), format, filter))));
+    }
+    let file = std::fs::File::create(&path).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(Some(Box::new(trace::Tracer::new(std::io::BufWriter::new(file), format, filter))))
+}
+
+/// Parses `FROM-TO`, `FROM-`, `-TO` or a single value.
+fn parse_range<T: Copy>(text: &str, min: T, max: T, parse: impl Fn(&str) -> Option<T>) -> Option<(T, T)> {
+    let Some((from, to)) = text.split_once('-') else {
+        let value = parse(text)?;
+        return Some((value, value));
+    };
+    let from = if from.is_empty() { min } else { parse(from)? };
+    let to = if to.is_empty() { max } else { parse(to)? };
+    Some((from, to))
+}
+
+/// Stops tracing and writes out what the tracer buffered.
+fn finish_trace(chip8: &mut chip8::Chip8) {
+    if let Some(Err(e)) = chip8.take_trace().map(|mut trace| trace.flush()) {
+        eprintln!("trace: {}", e);
+    }
+}
+
+/// `trace-diff <TRACE> <TRACE>`: compares two traces, text or binary, and reports the
+/// first instruction they differ at. Exits with 0 when they agree, 1 when they differ
+/// and 2 on errors.
+fn trace_diff_main(args: &[String]) {
+    let [path_a, path_b] = &args[2..] else {
+        eprintln!("Usage: {:?}  trace-diff <TRACE> <TRACE>", args[0]);
+        std::process::exit(2);
+    };
+    let open = |path: &String| {
+        let file = std::fs::File::open(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(2);
+        });
+        trace::TraceReader::new(std::io::BufReader::new(file))
+    };
+    let (compared, divergence) = trace::diff(open(path_a), open(path_b)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+    let Some(divergence) = divergence else {
+        println!("traces agree over {} instructions", compared);
+        return;
+    };
+    match divergence {
+        trace::Divergence::Differs { a, b, fields } => {
+            println!("traces differ at cycle {} after {} instructions: {}", a.cycle, compared, fields.join(", "));
+            println!("{}: {}", path_a, a);
+            println!("{}: {}", path_b, b);
+        }
+        trace::Divergence::Ended { a_ended, next } => {
+            let (ended, other) = if a_ended { (path_a, path_b) } else { (path_b, path_a) };
+            println!("{} ends after {} instructions, {} goes on:", ended, compared, other);
+            println!("{}: {}", other, next);
+        }
+    }
+    std::process::exit(1);
+}
+
+/// `--rng` and `--seed`.
+#[derive(Clone, Copy, Debug)]
+struct RngOptions {
+    generator: random::Generator,
+    seed: Option<u64>,
+}
+
+/// Reads `--rng seeded|os` and `--seed <N>`, the seed in decimal or 0x-prefixed hexadecimal.
+fn rng_options(args: &mut Vec<String>) -> Result<RngOptions, String> {
+    let generator = match take_option(args, "--rng")? {
+        Some(name) => name.parse()?,
+        None => random::Generator::default(),
+    };
+    let seed = match take_option(args, "--seed")? {
+        Some(seed) => {
+            let parsed = match seed.strip_prefix("0x").or_else(|| seed.strip_prefix("0X")) {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => seed.parse(),
+            };
+            Some(parsed.map_err(|_| format!("invalid seed {}", seed))?)
+        }
+        None => None,
+    };
+    if seed.is_some() && !generator.is_repeatable() {
+        return Err(format!("the {} generator takes no seed", generator));
+    }
+    Ok(RngOptions { generator, seed })
+}
+
+/// Removes `flag` from `args`, returning whether it was there.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let found = args.iter().any(|arg| arg == flag);
+    args.retain(|arg| arg != flag);
+    found
+}
+
+/// Removes `option` and the value following it from `args`.
+fn take_option(args: &mut Vec<String>, option: &str) -> Result<Option<String>, String> {
+    let Some(position) = args.iter().position(|arg| arg == option) else {
+        return Ok(None);
+    };
+    if position + 1 >= args.len() {
+        return Err(format!("{} needs a value", option));
+    }
+    let value = args.remove(position + 1);
+    args.remove(position);
+    Ok(Some(value))
+}
+
+/// Reads `--screenshot-scale <N>`, 1 when not given.
+fn screenshot_scale(args: &mut Vec<String>) -> Result<u32, String> {
+    match take_option(args, "--screenshot-scale")? {
+        Some(scale) => match scale.parse() {
+            Ok(scale) if scale > 0 => Ok(scale),
+            _ => Err(format!("invalid screenshot scale {}", scale)),
+        },
+        None => Ok(1),
+    }
+}
+
+/// Reads `--palette <NAME>`, a built-in palette or one from the palette config read
+/// from `--palettes <FILE>` or the user's config directory, which can also pick the
+/// palette used without `--palette`.
+fn palette(args: &mut Vec<String>) -> Result<palette::Palette, String> {
+    let name = take_option(args, "--palette")?;
+    let config = match take_option(args, "--palettes")?.map(std::path::PathBuf::from).or_else(palette::default_config_path) {
+        Some(path) => palette::PaletteConfig::load(&path)?,
+        None => palette::PaletteConfig::default(),
+    };
+    config.palette(name.as_deref())
+}
+
+/// Reads `--gif-limit <SECONDS>`, the longest a GIF recording may get.
+fn gif_limit(args: &mut Vec<String>) -> Result<std::time::Duration, String> {
+    match take_option(args, "--gif-limit")? {
+        Some(seconds) => match seconds.parse::<f64>() {
+            Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Ok(std::time::Duration::from_secs_f64(seconds)),
+            _ => Err(format!("invalid GIF limit {}", seconds)),
+        },
+        None => Ok(gif::DEFAULT_MAX_DURATION),
+    }
+}
+
+/// What screenshots of the ROM record about where they were taken.
+fn screenshot_metadata(rom_filename: &str, frame: u64) -> Vec<(String, String)> {
+    let rom = std::path::Path::new(rom_filename).file_name().map_or(rom_filename.into(), |name| name.to_string_lossy());
+    vec![("ROM".to_owned(), rom.into_owned()), ("Frame".to_owned(), frame.to_string())]
+}
+
+/// Reads the buzzer options: `--wave`, `--freq`, `--volume` and `--mute`.
+#[cfg(feature = "sdl")]
+fn audio_settings(args: &mut Vec<String>) -> Result<audio::AudioSettings, String> {
+    let mut settings = audio::AudioSettings { muted: take_flag(args, "--mute"), ..Default::default() };
+    if let Some(wave) = take_option(args, "--wave")? {
+        settings.waveform = wave.parse()?;
+    }
+    if let Some(frequency) = take_option(args, "--freq")? {
+        settings.frequency = frequency.parse().map_err(|_| format!("invalid frequency {}", frequency))?;
+    }
+    if let Some(volume) = take_option(args, "--volume")? {
+        settings.volume = volume.parse().map_err(|_| format!("invalid volume {}", volume))?;
+    }
+    Ok(settings)
+}
+
+/// `disasm [--syntax octo|cowgod] <ROM>`: prints a listing of the ROM without running it.
+fn disasm_main(args: &[String]) {
+    let (syntax, rom_filename) = match &args[2..] {
+        [rom] => (disasm::Syntax::Cowgod, rom),
+        [flag, syntax, rom] if flag == "--syntax" => {
+            let syntax = syntax.parse::<disasm::Syntax>().unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            });
+            (syntax, rom)
+        }
+        _ => {
+            eprintln!("Usage: {:?}  disasm [--syntax octo|cowgod] <ROM>", args[0]);
+            std::process::exit(1);
+        }
+    };
+    let rom = std::fs::read(rom_filename).unwrap_or_else(|e| {
+        eprintln!("{}: {}", rom_filename, e);
+        std::process::exit(1);
+    });
+    if rom.len() > disasm::MAX_ROM_LEN {
+        eprintln!(
+            "{}: only the first {} of {} bytes fit in memory, the rest is not listed",
+            rom_filename,
+            disasm::MAX_ROM_LEN,
+            rom.len()
+        );
+    }
+    print!("{}", disasm::disassemble(&rom, syntax));
+}
+
+/// `asm <SOURCE> <ROM>`: assembles a source file into a ROM image.
+fn asm_main(args: &[String]) {
+    let [source, rom_filename] = &args[2..] else {
+        eprintln!("Usage: {:?}  asm <SOURCE> <ROM>", args[0]);
+        std::process::exit(1);
+    };
+    let rom = assembler::assemble_file(std::path::Path::new(source)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Err(e) = std::fs::write(rom_filename, rom) {
+        eprintln!("{}: {}", rom_filename, e);
+        std::process::exit(1);
+    }
+}
+
+const HEADLESS_USAGE: &str = "headless [--frames <N>] [--until <condition>] [--key <FRAME:KEY[:HOLD]>]... \
+                              [--rng seeded|os] [--seed <N>] [--play <MOVIE>] [--ascii] \
+                              [--png <FILE>] [--ppm <FILE>] [--pbm <FILE>] [--screenshot-scale <N>] \
+                              [--gif <FILE> [--gif-limit <SECONDS>]] [--palette <NAME>] [--palettes <FILE>] \
+                              [--trace <FILE|-> [--trace-binary] [--trace-pc <FROM-TO>] [--trace-cycles <FROM-TO>]] \
+                              <InstructionsPerFrame> <ROM> [vip|chip48|schip|xo-chip[,+QUIRK|,-QUIRK]...]";
+
+/// `headless ...`: runs the ROM without a window for a number of frames or until a
+/// condition holds, then prints the display or writes it as PNG, PPM or PBM images,
+/// tagged with the ROM name and frames run. `--gif` records every frame of the run
+/// as an animated GIF, up to `--gif-limit`. Without
+/// `--seed`, the RNG starts from `random::DEFAULT_SEED` so runs repeat. `--play`
+/// replays a movie instead of the `--key` presses, with the settings it was recorded
+/// with, for as many frames as it holds unless `--frames` says otherwise.
+///
+/// Exits with 0 when the run finished, the condition held or the program halted,
+/// 2 on an emulation error, 3 when the condition never held and 4 when the movie
+/// desynced.
+fn headless_main(mut args: Vec<String>) {
+    let usage = |args: &[String], error: String| -> ! {
+        if !error.is_empty() {
+            eprintln!("{}", error);
+        }
+        eprintln!("Usage: {:?}  {}", args[0], HEADLESS_USAGE);
+        std::process::exit(1);
+    };
+    let ascii = take_flag(&mut args, "--ascii");
+    let parsed = (|| -> Result<_, String> {
+        let frames = match take_option(&mut args, "--frames")? {
+            Some(frames) => Some(frames.parse().map_err(|_| format!("invalid frame count {}", frames))?),
+            None => None,
         };
-        let current_time = std::time::Instant::now();
-        let duration = current_time.duration_since(last_cycle_time);
-        let dt: f32 = duration.as_secs_f32() * 1000.0;
-        if dt as i32 > cycle_delay{
-			last_cycle_time = current_time;
-			chip8.cycle();
-            unsafe {
-			    // platform.update(chip8.video.as_ptr() as *const c_void, video_pitch as i32);
+        let until = match take_option(&mut args, "--until")? {
+            Some(condition) => Some(condition.parse::<condition::Condition>()?),
+            None => None,
+        };
+        let mut keys = Vec::new();
+        while let Some(key) = take_option(&mut args, "--key")? {
+            keys.push(key.parse::<headless::KeyPress>()?);
+        }
+        let movie = match take_option(&mut args, "--play")? {
+            Some(path) => Some(movie::Movie::load(std::path::Path::new(&path))?),
+            None => None,
+        };
+        let mut images = Vec::new();
+        for (option, format) in [("--png", image::Format::Png), ("--ppm", image::Format::Ppm), ("--pbm", image::Format::Pbm)] {
+            if let Some(path) = take_option(&mut args, option)? {
+                images.push((path, format));
             }
-		}
+        }
+        let scale = screenshot_scale(&mut args)?;
+        let gif = take_option(&mut args, "--gif")?;
+        let gif_limit = gif_limit(&mut args)?;
+        let palette = palette(&mut args)?;
+        let rng = rng_options(&mut args)?;
+        let tracer = tracer(&mut args)?;
+        Ok((frames, until, keys, movie, (images, scale, palette), (gif, gif_limit), rng, tracer))
+    })();
+    let (frames, until, keys, movie, (images, scale, palette), (gif, gif_limit), rng, tracer) =
+        parsed.unwrap_or_else(|e| usage(&args, e));
+    if args.len() != 4 && args.len() != 5 {
+        usage(&args, String::new());
+    }
+    let instructions_per_frame = args[2]
+        .parse::<u32>()
+        .unwrap_or_else(|_| usage(&args, format!("invalid instructions per frame {}", args[2])));
+    let rom_filename = &args[3];
+    let (variant, quirks) = profile(args.get(4));
+
+    let rom = std::fs::read(rom_filename).unwrap_or_else(|e| {
+        eprintln!("{}: {}", rom_filename, e);
+        std::process::exit(1);
+    });
+    let machine = match &movie {
+        Some(movie) => movie.machine(&rom),
+        None => {
+            let mut chip8 = chip8::Chip8::with_variant(variant, quirks);
+            chip8.set_rng(rng.generator.source(rng.seed.unwrap_or(random::DEFAULT_SEED)));
+            chip8.load_rom(&rom).map(|()| chip8).map_err(|e| e.to_string())
+        }
+    };
+    let mut chip8 = machine.unwrap_or_else(|e| {
+        eprintln!("{}: {}", rom_filename, e);
+        std::process::exit(1);
+    });
+    if let Some(tracer) = tracer {
+        chip8.set_trace(tracer);
+    }
+    let frames = frames.unwrap_or_else(|| movie.as_ref().map_or(600, |movie| movie.len()));
+    let instructions_per_frame = movie.as_ref().map_or(instructions_per_frame, |movie| movie.instructions_per_frame);
+    let options = headless::Options { frames, instructions_per_frame, until, keys, movie };
+    let mut recorder = gif.as_ref().map(|path| {
+        std::fs::File::create(path)
+            .and_then(|file| gif::GifRecorder::new(std::io::BufWriter::new(file), scale, gif_limit))
+            .unwrap_or_else(|e| {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(1);
+            })
+    });
+    // false once the recording reached its limit
+    let mut capturing = Ok(true);
+    let outcome = headless::run(&mut chip8, &options, |chip8| {
+        if let (Some(recorder), Ok(true)) = (&mut recorder, &capturing) {
+            capturing = recorder.capture_chip8(chip8, &palette);
+        }
+    });
+    finish_trace(&mut chip8);
+
+    let frames_run = match &outcome {
+        headless::Outcome::ConditionMet(frame) | headless::Outcome::Halted(frame) => *frame,
+        headless::Outcome::Desync(desync) => desync.frame,
+        _ => frames,
+    };
+    if let (Some(path), Some(recorder)) = (&gif, recorder) {
+        if let Ok(false) = capturing {
+            eprintln!("{}: stopped recording after {} frames, see --gif-limit", path, recorder.frames());
+        }
+        if let Err(e) = capturing.and_then(|_| recorder.finish()) {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    for (path, format) in &images {
+        let mut screenshot = image::Screenshot::new(*format);
+        screenshot.palette = palette;
+        screenshot.scale = scale;
+        screenshot.metadata = screenshot_metadata(rom_filename, frames_run as u64);
+        if let Err(e) = std::fs::write(path, screenshot.capture(&chip8)) {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    if ascii || images.is_empty() {
+        print!("{}", headless::ascii(&chip8));
     }
-    unsafe {platform.destroy()};
+    let code = match outcome {
+        headless::Outcome::ConditionMet(frame) => {
+            eprintln!("condition met after {} frames", frame);
+            0
+        }
+        headless::Outcome::Halted(frame) => {
+            eprintln!("halted after {} frames", frame);
+            0
+        }
+        headless::Outcome::Finished => 0,
+        headless::Outcome::TimedOut => {
+            eprintln!("condition not met after {} frames", frames);
+            3
+        }
+        headless::Outcome::Error(error) => {
+            eprintln!("{}", error);
+            2
+        }
+        headless::Outcome::Desync(desync) => {
+            eprintln!("{}", desync);
+            4
+        }
+    };
+    std::process::exit(code);
 }
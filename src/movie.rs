@@ -0,0 +1,391 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::chip8::{Chip8, Variant};
+use crate::quirks::Quirks;
+use crate::random::Generator;
+use crate::savestate::crc32;
+
+/// Frames between the display hashes a recording stores, one per second at 60 Hz.
+pub const CHECK_INTERVAL: u32 = 60;
+/// Format version written on the first line.
+pub const VERSION: u32 = 1;
+/// Longest movie, a day at 60 frames a second. Recording stops there and longer files
+/// are refused, so a bad frame count cannot take all memory.
+pub const MAX_FRAMES: u32 = 60 * 60 * 60 * 24;
+
+/// A recorded play session: the settings and RNG the machine started with and
+/// the keys held during every frame, plus display hashes to detect a desync.
+///
+/// Replaying starts from power-on, so the same ROM, settings, seed and keys give the
+/// same frames. The file is plain text:
+///
+/// ```text
+/// chip8-h movie 1
+/// rom 0ce70772
+/// rng seeded
+/// seed 9d2f1c07a3b6e514
+/// variant chip8
+/// quirks clip_sprites display_wait
+/// instructions_per_frame 10
+/// keys 0000 120
+/// keys 0010 5
+/// check 60 5c3ab0e1
+/// ```
+///
+/// `keys` lines hold the keypad as 16 bits, bit n for key n, for a number of frames;
+/// `check` lines the display hash after that many frames.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    /// `keymap::rom_hash` of the ROM it was recorded with.
+    pub rom_hash: u32,
+    /// A repeatable generator, not `Generator::Os`.
+    pub generator: Generator,
+    pub seed: u64,
+    pub variant: Variant,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    /// The keypad during each frame, bit n for key n.
+    frames: Vec<u16>,
+    /// Frames run and the display hash after them, in order.
+    checks: Vec<(u32, u32)>,
+}
+
+/// The display differed from the recording during playback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Desync {
+    /// Frames run when the hashes were compared.
+    pub frame: u32,
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "movie desynced after frame {}: display hash {:08x}, recorded {:08x}",
+            self.frame, self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for Desync {}
+
+impl Movie {
+    /// Starts an empty recording of `rom` with the given settings and generator.
+    pub fn new(
+        rom: &[u8],
+        generator: Generator,
+        seed: u64,
+        variant: Variant,
+        quirks: Quirks,
+        instructions_per_frame: u32,
+    ) -> Self {
+        Movie {
+            rom_hash: crc32(rom),
+            generator,
+            seed,
+            variant,
+            quirks,
+            instructions_per_frame,
+            frames: Vec::new(),
+            checks: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        text.parse().map_err(|e| format!("{}:{}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_string())
+    }
+
+    /// The machine the movie starts from: powered on with its settings and seed and
+    /// `rom` loaded. Fails when `rom` is not the ROM it was recorded with.
+    pub fn machine(&self, rom: &[u8]) -> Result<Chip8, String> {
+        if crc32(rom) != self.rom_hash {
+            return Err(format!("the movie was recorded with ROM {:08x}, not {:08x}", self.rom_hash, crc32(rom)));
+        }
+        let mut chip8 = Chip8::with_variant(self.variant, self.quirks);
+        chip8.set_rng(self.generator.source(self.seed));
+        chip8.load_rom(rom).map_err(|e| e.to_string())?;
+        Ok(chip8)
+    }
+
+    /// Number of frames recorded.
+    pub fn len(&self) -> u32 {
+        self.frames.len() as u32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Whether the movie is `MAX_FRAMES` long, so that no more frames can be recorded.
+    pub fn is_full(&self) -> bool {
+        self.len() >= MAX_FRAMES
+    }
+
+    /// Records the keys held going into the next frame; call it right before running it.
+    /// Frames past `MAX_FRAMES` are not recorded, see `is_full`.
+    pub fn record_input(&mut self, chip8: &Chip8) {
+        if self.is_full() {
+            return;
+        }
+        let keys = (0..0x10).filter(|&key| chip8.key_pressed(key)).fold(0, |keys, key| keys | 1 << key);
+        self.frames.push(keys);
+    }
+
+    /// Stores the display hash every `CHECK_INTERVAL` frames; call it after each frame.
+    /// Once the movie is full, frames are no longer counted and no more hashes are stored.
+    pub fn record_check(&mut self, chip8: &Chip8) {
+        let frame = self.len();
+        let checked = self.checks.last().is_some_and(|&(last, _)| last >= frame);
+        if frame.is_multiple_of(CHECK_INTERVAL) && !checked {
+            self.checks.push((frame, display_hash(chip8)));
+        }
+    }
+
+    /// Sets the keypad to the keys recorded for `frame`, counting from 0, before it
+    /// runs. Returns false past the end of the movie, releasing every key.
+    pub fn play_input(&self, frame: u32, chip8: &mut Chip8) -> bool {
+        let keys = self.frames.get(frame as usize).copied();
+        for key in 0..0x10 {
+            if keys.unwrap_or(0) & 1 << key != 0 {
+                chip8.press_key(key);
+            } else {
+                chip8.release_key(key);
+            }
+        }
+        keys.is_some()
+    }
+
+    /// Compares the display after `frames` frames with the recording, when it stored
+    /// a hash for that frame.
+    pub fn verify(&self, frames: u32, chip8: &Chip8) -> Result<(), Desync> {
+        let Ok(i) = self.checks.binary_search_by_key(&frames, |&(frame, _)| frame) else {
+            return Ok(());
+        };
+        let (expected, actual) = (self.checks[i].1, display_hash(chip8));
+        if expected != actual {
+            return Err(Desync { frame: frames, expected, actual });
+        }
+        Ok(())
+    }
+}
+
+/// CRC-32 of the display size and pixels, each pixel the bit planes it is lit in, so
+/// that hashes do not depend on the palette shown.
+fn display_hash(chip8: &Chip8) -> u32 {
+    let mut bytes = Vec::with_capacity(8 + chip8.framebuffer().len());
+    bytes.extend(chip8.video_width().to_be_bytes());
+    bytes.extend(chip8.video_height().to_be_bytes());
+    bytes.extend_from_slice(chip8.framebuffer());
+    crc32(&bytes)
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "chip8-h movie {}", VERSION)?;
+        writeln!(f, "rom {:08x}", self.rom_hash)?;
+        writeln!(f, "rng {}", self.generator)?;
+        writeln!(f, "seed {:016x}", self.seed)?;
+        let variant = match self.variant {
+            Variant::Chip8 => "chip8",
+            Variant::XoChip => "xo-chip",
+        };
+        writeln!(f, "variant {}", variant)?;
+        let mut quirks = self.quirks;
+        write!(f, "quirks")?;
+        for (name, _) in quirks.flags().into_iter().filter(|(_, set)| **set) {
+            write!(f, " {}", name)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "instructions_per_frame {}", self.instructions_per_frame)?;
+        for run in self.frames.chunk_by(|a, b| a == b) {
+            writeln!(f, "keys {:04x} {}", run[0], run.len())?;
+        }
+        for (frame, hash) in &self.checks {
+            writeln!(f, "check {} {:08x}", frame, hash)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Movie {
+    type Err = String;
+
+    /// Parses a movie file. Errors start with the line number.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut lines = text.lines().enumerate().map(|(number, line)| (number + 1, line.trim()));
+        match lines.next() {
+            Some((_, header)) if header == format!("chip8-h movie {}", VERSION) => {}
+            Some((_, header)) if header.starts_with("chip8-h movie ") => {
+                return Err(format!("1: unsupported movie version {:?}", &header["chip8-h movie ".len()..]));
+            }
+            _ => return Err("1: not a chip8-h movie".to_owned()),
+        }
+        let mut movie = Movie::new(&[], Generator::Seeded, 0, Variant::Chip8, Quirks::default(), 0);
+        let mut seen = Vec::new();
+        for (number, line) in lines.filter(|(_, line)| !line.is_empty()) {
+            let error = |message: String| format!("{}: {}", number, message);
+            let (field, value) = line.split_once(' ').unwrap_or((line, ""));
+            let value = value.trim();
+            let hex32 = |value: &str| u32::from_str_radix(value, 16).map_err(|_| error(format!("invalid hash {:?}", value)));
+            match field {
+                "rom" => movie.rom_hash = hex32(value)?,
+                "rng" => {
+                    movie.generator = value.parse().map_err(error)?;
+                    if !movie.generator.is_repeatable() {
+                        return Err(error(format!("the {} generator cannot be replayed", movie.generator)));
+                    }
+                }
+                "seed" => movie.seed = u64::from_str_radix(value, 16).map_err(|_| error(format!("invalid seed {:?}", value)))?,
+                "variant" => {
+                    movie.variant = match value {
+                        "chip8" => Variant::Chip8,
+                        "xo-chip" => Variant::XoChip,
+                        _ => return Err(error(format!("unknown variant {:?}", value))),
+                    }
+                }
+                "quirks" => {
+                    let mut quirks = Quirks::default();
+                    for name in value.split_whitespace() {
+                        quirks.set(name, true).map_err(error)?;
+                    }
+                    movie.quirks = quirks;
+                }
+                "instructions_per_frame" => {
+                    movie.instructions_per_frame =
+                        value.parse().map_err(|_| error(format!("invalid instructions per frame {:?}", value)))?
+                }
+                "keys" => {
+                    let (keys, count) = value.split_once(' ').unwrap_or((value, "1"));
+                    let keys = u16::from_str_radix(keys, 16).map_err(|_| error(format!("invalid keys {:?}", keys)))?;
+                    let count: u32 = count.trim().parse().map_err(|_| error(format!("invalid frame count {:?}", count)))?;
+                    if count > MAX_FRAMES - movie.len() {
+                        return Err(error(format!("the movie is longer than {} frames", MAX_FRAMES)));
+                    }
+                    movie.frames.extend(std::iter::repeat_n(keys, count as usize));
+                }
+                "check" => {
+                    let Some((frame, hash)) = value.split_once(' ') else {
+                        return Err(error("expected check FRAME HASH".to_owned()));
+                    };
+                    let frame: u32 = frame.parse().map_err(|_| error(format!("invalid frame {:?}", frame)))?;
+                    if movie.checks.last().is_some_and(|&(last, _)| last >= frame) {
+                        return Err(error("checks out of order".to_owned()));
+                    }
+                    movie.checks.push((frame, hex32(hash.trim())?));
+                }
+                _ => return Err(error(format!("unknown field {:?}", field))),
+            }
+            seen.push(field);
+        }
+        for required in ["rom", "seed", "variant", "instructions_per_frame"] {
+            if !seen.contains(&required) {
+                return Err(format!("{}: missing {}", text.lines().count(), required));
+            }
+        }
+        Ok(movie)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Draws the sprite of the last key pressed at a random position, forever.
+    const ROM: [u8; 14] = [0xF0, 0x0A, 0xF0, 0x29, 0xC1, 0x3F, 0xC2, 0x1F, 0x00, 0xE0, 0xD1, 0x25, 0x12, 0x00];
+
+    /// Records `keys`, one frame each, then `idle` frames with no key down.
+    fn record(keys: &[u8], idle: u32) -> Movie {
+        let mut movie = Movie::new(&ROM, Generator::Seeded, 7, Variant::Chip8, Quirks::vip(), 20);
+        let mut chip8 = movie.machine(&ROM).unwrap();
+        let frames = keys.iter().map(|&key| Some(key)).chain((0..idle).map(|_| None));
+        for key in frames {
+            (0..0x10).for_each(|key| chip8.release_key(key));
+            if let Some(key) = key {
+                chip8.press_key(key);
+            }
+            movie.record_input(&chip8);
+            chip8.run_frame(movie.instructions_per_frame).unwrap();
+            movie.record_check(&chip8);
+        }
+        movie
+    }
+
+    fn replay(movie: &Movie) -> Result<(), Desync> {
+        let mut chip8 = movie.machine(&ROM).unwrap();
+        let mut frame = 0;
+        while movie.play_input(frame, &mut chip8) {
+            chip8.run_frame(movie.instructions_per_frame).unwrap();
+            frame += 1;
+            movie.verify(frame, &chip8)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn movies_round_trip_and_replay() {
+        let movie = record(&[1, 1, 1, 5, 5, 0xA], 200);
+        let text = movie.to_string();
+        assert!(text.contains("keys 0002 3\nkeys 0020 2\nkeys 0400 1\nkeys 0000 200\n"));
+        assert!(text.contains("quirks shift_uses_vy load_store_increments_i logic_resets_vf clip_sprites display_wait\n"));
+        let parsed: Movie = text.parse().unwrap();
+        assert_eq!(parsed, movie);
+        assert_eq!(parsed.len(), 206);
+        assert_eq!(replay(&parsed), Ok(()));
+    }
+
+    #[test]
+    fn other_input_desyncs() {
+        let movie = record(&[3], 120);
+        let text = movie.to_string().replace("keys 0008 1", "keys 0010 1");
+        let error = replay(&text.parse().unwrap()).unwrap_err();
+        assert_eq!(error.frame, CHECK_INTERVAL);
+    }
+
+    #[test]
+    fn absurd_frame_counts_are_refused() {
+        let header = "chip8-h movie 1\nrom 0\nseed 0\nvariant chip8\ninstructions_per_frame 10\n";
+        let error = |body: &str| format!("{}{}", header, body).parse::<Movie>().unwrap_err();
+        assert_eq!(error("keys 0000 99999999999999999999"), "6: invalid frame count \"99999999999999999999\"");
+        let too_long = format!("6: the movie is longer than {} frames", MAX_FRAMES);
+        assert_eq!(error(&format!("keys 0000 {}", MAX_FRAMES + 1)), too_long);
+        assert!(error(&format!("keys 0000 {}\nkeys 0001 1", MAX_FRAMES)).starts_with("7: the movie is longer"));
+        assert!(format!("{}keys 0000 {}", header, MAX_FRAMES).parse::<Movie>().is_ok());
+    }
+
+    #[test]
+    fn recording_stops_at_the_maximum_length() {
+        let mut movie = record(&[], 0);
+        movie.frames = vec![0; MAX_FRAMES as usize - 2];
+        let mut chip8 = movie.machine(&ROM).unwrap();
+        chip8.press_key(4);
+        for _ in 0..5 {
+            movie.record_input(&chip8);
+            movie.record_check(&chip8);
+        }
+        assert!(movie.is_full());
+        assert_eq!(movie.len(), MAX_FRAMES);
+        assert_eq!(movie.checks, [(MAX_FRAMES, display_hash(&chip8))]);
+        let text = movie.to_string();
+        assert!(text.ends_with(&format!("keys 0010 2\ncheck {} {:08x}\n", MAX_FRAMES, display_hash(&chip8))));
+        assert_eq!(text.parse(), Ok(movie));
+    }
+
+    #[test]
+    fn bad_movies_give_the_line() {
+        let error = |text: &str| text.parse::<Movie>().unwrap_err();
+        assert_eq!(error("chip8-h movie 2\n"), "1: unsupported movie version \"2\"");
+        assert_eq!(error("a movie\n"), "1: not a chip8-h movie");
+        assert_eq!(error("chip8-h movie 1\nrom 0\n\nrng os\n"), "4: the os generator cannot be replayed");
+        assert!(error("chip8-h movie 1\nquirks wrap\n").starts_with("2: unknown quirk \"wrap\""));
+        assert!(error("chip8-h movie 1\ncheck 60 0\ncheck 60 0\n").starts_with("3: checks out of order"));
+        assert_eq!(error("chip8-h movie 1\nrom 0\n"), "2: missing seed");
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::keymap::{self, strip_comment};
+
+/// The colours the display is shown in, as RGBA8888, indexed by the bit planes a pixel
+/// is lit in (see `Chip8::framebuffer`): unlit, lit, and for XO-CHIP the second plane
+/// alone and both planes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub colours: [u32; 4],
+}
+
+/// The built-in palettes, by the names `--palette` takes.
+pub const BUILT_IN: [(&str, Palette); 6] = [
+    ("classic", Palette { colours: [0x000000FF, 0xFFFFFFFF, 0xAAAAAAFF, 0x555555FF] }),
+    ("amber", Palette { colours: [0x1A0F00FF, 0xFFB000FF, 0xB57D00FF, 0x684200FF] }),
+    ("green-phosphor", Palette { colours: [0x001A00FF, 0x33FF33FF, 0x22B022FF, 0x116511FF] }),
+    ("lcd", Palette { colours: [0x9BBC0FFF, 0x0F380FFF, 0x306230FF, 0x8BAC0FFF] }),
+    // Octo's background, fill, fill 2 and blend colours
+    ("octo", Palette { colours: [0x996600FF, 0xFFCC00FF, 0xFF6600FF, 0x662200FF] }),
+    ("high-contrast", Palette { colours: [0x000000FF, 0xFFFFFFFF, 0xFFFF00FF, 0x00FFFFFF] }),
+];
+
+impl Palette {
+    /// The built-in palette called `name`, ignoring case.
+    pub fn built_in(name: &str) -> Option<Palette> {
+        BUILT_IN.iter().find(|(built_in, _)| built_in.eq_ignore_ascii_case(name)).map(|&(_, palette)| palette)
+    }
+
+    /// A palette for monochrome programs, with the XO-CHIP colours shaded between the
+    /// two, two thirds and one third of the way to `lit`.
+    pub fn two_colour(unlit: u32, lit: u32) -> Self {
+        let mix = |thirds: u32| {
+            let (from, to) = (unlit.to_be_bytes(), lit.to_be_bytes());
+            let channel = |i: usize| ((from[i] as u32 * (3 - thirds) + to[i] as u32 * thirds) / 3) as u8;
+            u32::from_be_bytes([channel(0), channel(1), channel(2), channel(3)])
+        };
+        Palette { colours: [unlit, lit, mix(2), mix(1)] }
+    }
+
+    /// The colour of a pixel of `Chip8::framebuffer`.
+    pub fn colour(&self, pixel: u8) -> u32 {
+        self.colours[(pixel & 0x3) as usize]
+    }
+
+    /// The colours of a whole framebuffer.
+    pub fn apply(&self, framebuffer: &[u8]) -> Vec<u32> {
+        framebuffer.iter().map(|&pixel| self.colour(pixel)).collect()
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        BUILT_IN[0].1
+    }
+}
+
+/// The palette config file: palettes of its own and which palette to use when
+/// `--palette` is not given.
+///
+/// ```toml
+/// palette = "dusk"
+///
+/// dusk = ["#1A1C2C", "#F4F4F4"]
+/// sunset = ["#2B0F54", "#FFD319", "#FF901F", "#AB1F65"]
+/// ```
+///
+/// A palette lists the unlit and lit colours, as `#RRGGBB` or `#RRGGBBAA`, then
+/// optionally the XO-CHIP second plane and both planes colours; see `Palette`.
+/// Palettes here hide built-in ones of the same name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PaletteConfig {
+    /// The `palette = "name"` line.
+    pub default: Option<String>,
+    pub palettes: BTreeMap<String, Palette>,
+}
+
+impl PaletteConfig {
+    /// Reads a config file; a missing file gives no palettes beyond the built-in ones.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => text.parse().map_err(|e| format!("{}:{}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(PaletteConfig::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    /// The palette called `name`, from the config or built in, or the config's
+    /// default without a name.
+    pub fn palette(&self, name: Option<&str>) -> Result<Palette, String> {
+        let Some(name) = name.or(self.default.as_deref()) else {
+            return Ok(Palette::default());
+        };
+        self.palettes
+            .iter()
+            .find(|(custom, _)| custom.eq_ignore_ascii_case(name))
+            .map(|(_, &palette)| palette)
+            .or_else(|| Palette::built_in(name))
+            .ok_or_else(|| {
+                let names: Vec<&str> =
+                    BUILT_IN.iter().map(|&(name, _)| name).chain(self.palettes.keys().map(String::as_str)).collect();
+                format!("unknown palette {:?}, expected one of {}", name, names.join(", "))
+            })
+    }
+}
+
+/// `palettes.toml` next to the keymap config.
+pub fn default_config_path() -> Option<PathBuf> {
+    Some(keymap::default_config_path()?.with_file_name("palettes.toml"))
+}
+
+impl std::str::FromStr for PaletteConfig {
+    type Err = String;
+
+    /// Parses the subset of TOML the config uses. Errors start with the line number.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut config = PaletteConfig::default();
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: String| format!("{}: {}", number + 1, message);
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((name, value)) = line.split_once('=') else {
+                return Err(error(format!("expected NAME = [\"#RRGGBB\", ...], found {:?}", line)));
+            };
+            let (name, value) = (name.trim().trim_matches('"'), value.trim());
+            if name == "palette" {
+                let default = value.strip_prefix('"').and_then(|value| value.strip_suffix('"'));
+                let default = default.ok_or_else(|| error(format!("expected a quoted palette name, found {:?}", value)))?;
+                config.default = Some(default.to_owned());
+                continue;
+            }
+            let Some(list) = value.strip_prefix('[').and_then(|value| value.strip_suffix(']')) else {
+                return Err(error(format!("expected a list of colours, found {:?}", value)));
+            };
+            let colours: Vec<u32> = list
+                .split(',')
+                .map(str::trim)
+                .filter(|colour| !colour.is_empty())
+                .map(|colour| parse_colour(colour).map_err(error))
+                .collect::<Result<_, _>>()?;
+            let palette = match colours[..] {
+                [unlit, lit] => Palette::two_colour(unlit, lit),
+                [unlit, lit, second, both] => Palette { colours: [unlit, lit, second, both] },
+                _ => return Err(error(format!("palette {:?} has {} colours, expected 2 or 4", name, colours.len()))),
+            };
+            config.palettes.insert(name.to_owned(), palette);
+        }
+        Ok(config)
+    }
+}
+
+/// Parses `"#RRGGBB"` or `"#RRGGBBAA"` as RGBA8888.
+fn parse_colour(text: &str) -> Result<u32, String> {
+    let invalid = || format!("invalid colour {}, expected \"#RRGGBB\"", text);
+    let hex = text.strip_prefix('"').and_then(|text| text.strip_suffix('"')).ok_or_else(invalid)?;
+    let hex = hex.strip_prefix('#').filter(|hex| hex.chars().all(|c| c.is_ascii_hexdigit())).ok_or_else(invalid)?;
+    let value = u32::from_str_radix(hex, 16).map_err(|_| invalid())?;
+    match hex.len() {
+        6 => Ok(value << 8 | 0xFF),
+        8 => Ok(value),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configs_parse() {
+        let config: PaletteConfig = r##"
+            # the palette used without --palette
+            palette = "dusk"
+
+            dusk = ["#1A1C2C", "#F4F4F4"]  # two colours
+            "sunset" = ["#2B0F54", "#FFD319", "#FF901F80", "#AB1F65",]
+        "##
+        .parse()
+        .unwrap();
+        assert_eq!(config.default.as_deref(), Some("dusk"));
+        assert_eq!(config.palettes["dusk"].colours[..2], [0x1A1C2CFF, 0xF4F4F4FF]);
+        assert_eq!(config.palettes["sunset"].colours, [0x2B0F54FF, 0xFFD319FF, 0xFF901F80, 0xAB1F65FF]);
+    }
+
+    #[test]
+    fn two_colour_palettes_shade_the_xo_chip_colours() {
+        assert_eq!(Palette::two_colour(0x000000FF, 0xFFFFFFFF), Palette::default());
+        let palette = Palette::two_colour(0x30000000, 0x000030FF);
+        assert_eq!(palette.colours[2..], [0x100020AA, 0x20001055]);
+        assert_eq!(palette.colour(0x6), palette.colours[2]);
+    }
+
+    #[test]
+    fn palettes_are_found_by_name() {
+        let config: PaletteConfig = "AMBER = [\"#010203\", \"#040506\"]".parse().unwrap();
+        assert_eq!(Palette::built_in("Green-Phosphor"), Some(BUILT_IN[2].1));
+        assert_eq!(config.palette(None), Ok(Palette::default()));
+        // custom palettes hide the built-in ones
+        assert_eq!(config.palette(Some("amber")).unwrap().colours[0], 0x010203FF);
+        assert_eq!(config.palette(Some("LCD")), Ok(BUILT_IN[3].1));
+        let error = config.palette(Some("sepia")).unwrap_err();
+        assert!(error.starts_with("unknown palette \"sepia\", expected one of classic, amber"), "{}", error);
+        assert!(error.ends_with(", AMBER"), "{}", error);
+
+        let config = PaletteConfig { default: Some("octo".to_owned()), ..PaletteConfig::default() };
+        assert_eq!(config.palette(None), Ok(BUILT_IN[4].1));
+    }
+
+    #[test]
+    fn errors_name_their_line() {
+        let error = |text: &str| text.parse::<PaletteConfig>().unwrap_err();
+        assert_eq!(error("\nbad"), "2: expected NAME = [\"#RRGGBB\", ...], found \"bad\"");
+        assert_eq!(error("palette = dusk"), "1: expected a quoted palette name, found \"dusk\"");
+        assert_eq!(error("dusk = \"#000000\""), "1: expected a list of colours, found \"\\\"#000000\\\"\"");
+        assert_eq!(error("dusk = [\"#000000\"]"), "1: palette \"dusk\" has 1 colours, expected 2 or 4");
+        assert_eq!(error("\n\ndusk = [\"#00000\", \"#FFFFFF\"]"), "3: invalid colour \"#00000\", expected \"#RRGGBB\"");
+        assert_eq!(error("dusk = [000000, \"#FFFFFF\"]"), "1: invalid colour 000000, expected \"#RRGGBB\"");
+        assert_eq!(error("dusk = [\"#GG0000\", \"#FFFFFF\"]"), "1: invalid colour \"#GG0000\", expected \"#RRGGBB\"");
+    }
+}
@@ -1,214 +1,293 @@
-use sdl2::libc::c_int;
-use sdl2::{sys::*};
-use std::ffi::{CString,c_void};
+use std::collections::{BTreeSet, HashMap};
 
-use std::ptr::null;
+use chip8_h::frontend::{Display, Hotkey, Input, SAVE_SLOTS};
+use chip8_h::keymap::{self, Keymap, Remap};
+use chip8_h::palette::Palette;
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::{AudioSubsystem, EventPump, GameControllerSubsystem};
+
+/// How far a stick or trigger has to move, out of 32767, to count as a press.
+const AXIS_DEADZONE: i16 = 8000;
+
+/// The SDL window, keyboard, controllers and audio subsystem. SDL shuts down once all
+/// of them are dropped.
 pub struct Platform {
-    window: *mut SDL_Window,
-    renderer: *mut SDL_Renderer,
-    texture: *mut SDL_Texture,
+    pub display: SdlDisplay,
+    pub input: SdlInput,
+    /// `None` when SDL could not start its audio subsystem.
+    pub audio: Option<AudioSubsystem>,
 }
 
 impl Platform {
-    pub fn new(title: String, width: i32, height: i32,texture_width:i32,texture_height:i32) -> Self {
-		unsafe{SDL_Init(SDL_INIT_VIDEO);}
-		
-        let window = unsafe {
-			SDL_CreateWindow(
-				CString::new(title).unwrap().as_ptr(),
-				SDL_WINDOWPOS_CENTERED_MASK as i32,
-				SDL_WINDOWPOS_CENTERED_MASK as i32,
-				width.into(),
-				height.into(),
-				SDL_WindowFlags::SDL_WINDOW_SHOWN as u32,
-			)
+    pub fn new(title: &str, width: u32, height: u32, keymap: Keymap, palette: Palette) -> Result<Self, String> {
+        let sdl = sdl2::init()?;
+        let window = sdl
+            .video()?
+            .window(title, width, height)
+            .position_centered()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let canvas = window.into_canvas().accelerated().build().map_err(|e| e.to_string())?;
+        // likewise a missing controller subsystem only costs the controllers
+        let controllers = sdl.game_controller().map_err(|e| eprintln!("controllers disabled: {}", e)).ok();
+        if let Some(controllers) = &controllers {
+            load_controller_mappings(controllers);
+        }
+        let input = SdlInput {
+            events: sdl.event_pump()?,
+            keymap,
+            remap: None,
+            hotkeys: Vec::new(),
+            rewinding: false,
+            controllers,
+            pads: HashMap::new(),
+            held: Default::default(),
         };
-        let renderer = unsafe{
-            SDL_CreateRenderer(window, -1, SDL_RendererFlags::SDL_RENDERER_ACCELERATED as u32)
+        // a missing audio driver only costs the sound
+        let audio = sdl.audio().map_err(|e| eprintln!("audio disabled: {}", e)).ok();
+        Ok(Platform { display: SdlDisplay { canvas, palette }, input, audio })
+    }
+}
+
+/// Adds the mappings in `gamecontrollerdb.txt` next to the keymap config, for pads
+/// missing from the database built into SDL. `SDL_GAMECONTROLLERCONFIG` works too.
+fn load_controller_mappings(controllers: &GameControllerSubsystem) {
+    let Some(path) = keymap::default_config_path().map(|path| path.with_file_name("gamecontrollerdb.txt")) else {
+        return;
+    };
+    if path.exists() {
+        if let Err(e) = controllers.load_mappings(&path) {
+            eprintln!("{}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Draws the display scaled to fill the window, in a palette.
+pub struct SdlDisplay {
+    canvas: Canvas<Window>,
+    palette: Palette,
+}
+
+impl Display for SdlDisplay {
+    fn present(&mut self, framebuffer: &[u8], width: u32, height: u32) {
+        // textures borrow their creator, so a fresh one per frame is simpler than keeping
+        // one alive across resolution changes; at 128x64 it is cheap
+        let creator = self.canvas.texture_creator();
+        let Ok(mut texture) = creator.create_texture_streaming(PixelFormatEnum::RGBA8888, width, height) else {
+            return;
+        };
+        let pixels: Vec<u8> = framebuffer.iter().flat_map(|&pixel| self.palette.colour(pixel).to_ne_bytes()).collect();
+        if texture.update(None, &pixels, width as usize * 4).is_err() {
+            return;
+        }
+        self.canvas.clear();
+        let _ = self.canvas.copy(&texture, None, None);
+        self.canvas.present();
+    }
+}
+
+/// Maps keyboard and controller events to the keypad and the hotkeys: F1 to F4 load
+/// a save state slot, Shift+F1 to Shift+F4 save one, F5 remaps the keypad, F10 starts
+/// and stops a GIF recording, F12 takes a screenshot, M mutes, Backspace rewinds and
+/// Escape quits.
+///
+/// Controllers are picked up as they are plugged in; any pad in SDL's controller
+/// database works, its inputs bound by name like keys are.
+pub struct SdlInput {
+    events: EventPump,
+    keymap: Keymap,
+    /// Set while F5 remapping waits for keys.
+    remap: Option<Remap>,
+    /// Hotkeys pressed since the last `take_hotkeys`.
+    hotkeys: Vec<Hotkey>,
+    /// Whether the rewind key, Backspace, is held.
+    rewinding: bool,
+    controllers: Option<GameControllerSubsystem>,
+    /// Open controllers by joystick instance id; they close when dropped.
+    pads: HashMap<u32, GameController>,
+    /// The bound inputs held down for each key, by controller instance id or `None` for
+    /// the keyboard. A key bound to several inputs is released once none is held.
+    held: [BTreeSet<(Option<u32>, String)>; 0x10],
+}
+
+impl SdlInput {
+    /// Feeds a key press or controller input to the remap in progress, returning
+    /// false for events it has no use for.
+    fn remap_event(&mut self, event: &Event) -> bool {
+        let Some(remap) = &mut self.remap else {
+            return false;
         };
-        let texture = unsafe{
-            SDL_CreateTexture(renderer, SDL_PixelFormatEnum::SDL_PIXELFORMAT_RGBA8888 as u32, SDL_TextureAccess::SDL_TEXTUREACCESS_STREAMING as c_int, texture_width as c_int, texture_height as c_int)
+        let name = match event {
+            Event::KeyDown { keycode: Some(Keycode::ESCAPE), .. } => {
+                println!("remapping cancelled");
+                self.remap = None;
+                return true;
+            }
+            Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => keycode.name(),
+            Event::ControllerButtonDown { button, .. } => button_name(*button),
+            Event::ControllerAxisMotion { axis, value, .. } => match axis_direction(*value) {
+                Some(direction) => axis_name(*axis, direction),
+                // a stick coming back to the centre is not a press
+                None => return true,
+            },
+            _ => return false,
         };
-        Platform{window,renderer,texture}
+        let prompt = remap.prompt();
+        if let Some(keymap) = remap.press(&name) {
+            self.keymap = keymap;
+            self.remap = None;
+            self.hotkeys.push(Hotkey::KeymapChanged);
+        } else if remap.prompt() != prompt {
+            // a held stick keeps sending motion, ask again only once it was taken
+            println!("{}", remap.prompt());
+        }
+        true
     }
-    
-    pub unsafe fn update(&mut self,buffer:*const c_void,pitch:i32){
-        SDL_UpdateTexture(self.texture, null(), buffer, pitch);
-		SDL_RenderClear(self.renderer);
-		SDL_RenderCopy(self.renderer, self.texture, null(), null());
-		SDL_RenderPresent(self.renderer);
+
+    /// Marks an input as held or released and updates the key it is bound to, if any.
+    fn set_held(&mut self, keypad: &mut [bool; 0x10], pad: Option<u32>, name: String, down: bool) {
+        let Some(key) = self.keymap.key_for(&name) else {
+            return;
+        };
+        let held = &mut self.held[key as usize];
+        if down {
+            held.insert((pad, name));
+        } else {
+            held.remove(&(pad, name));
+        }
+        keypad[key as usize] = !held.is_empty();
+    }
+
+    fn connect(&mut self, index: u32) {
+        let Some(controllers) = &self.controllers else {
+            return;
+        };
+        match controllers.open(index) {
+            Ok(pad) => {
+                println!("controller connected: {}", pad.name());
+                self.pads.insert(pad.instance_id(), pad);
+            }
+            Err(e) => eprintln!("could not open controller {}: {}", index, e),
+        }
     }
-    
-    pub unsafe fn destroy(&mut self){
-        SDL_DestroyTexture(self.texture);
-        SDL_DestroyRenderer(self.renderer);
-		SDL_DestroyWindow(self.window);
-		SDL_Quit();
+}
+
+/// The `Keymap` name of a controller button.
+fn button_name(button: Button) -> String {
+    format!("Pad {}", button.string())
+}
+
+/// The `Keymap` name of a stick or trigger pushed towards `direction`, '-' or '+'.
+fn axis_name(axis: Axis, direction: char) -> String {
+    format!("Pad {}{}", axis.string(), direction)
+}
+
+/// Which way an axis is pushed past the deadzone, if at all.
+fn axis_direction(value: i16) -> Option<char> {
+    if value < -AXIS_DEADZONE {
+        Some('-')
+    } else if value > AXIS_DEADZONE {
+        Some('+')
+    } else {
+        None
     }
-    pub unsafe  fn process(&mut self,keys:*mut i8)->bool{
+}
+
+impl Input for SdlInput {
+    fn poll(&mut self, keypad: &mut [bool; 0x10]) -> bool {
         let mut quit = false;
+        let events: Vec<Event> = self.events.poll_iter().collect();
+        for event in events {
+            if self.remap_event(&event) {
+                continue;
+            }
+            match event {
+                Event::ControllerDeviceAdded { which, .. } => self.connect(which),
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    if let Some(pad) = self.pads.remove(&which) {
+                        println!("controller disconnected: {}", pad.name());
+                        // nothing will release the inputs it held
+                        for (key, held) in self.held.iter_mut().enumerate() {
+                            held.retain(|(held_by, _)| *held_by != Some(which));
+                            keypad[key] = !held.is_empty();
+                        }
+                    }
+                }
+                Event::ControllerButtonDown { which, button, .. } => {
+                    self.set_held(keypad, Some(which), button_name(button), true)
+                }
+                Event::ControllerButtonUp { which, button, .. } => {
+                    self.set_held(keypad, Some(which), button_name(button), false)
+                }
+                Event::ControllerAxisMotion { which, axis, value, .. } => {
+                    // release both directions first, they may share a key
+                    for direction in ['-', '+'] {
+                        self.set_held(keypad, Some(which), axis_name(axis, direction), false);
+                    }
+                    if let Some(direction) = axis_direction(value) {
+                        self.set_held(keypad, Some(which), axis_name(axis, direction), true);
+                    }
+                }
+                Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::ESCAPE), .. } => quit = true,
+                Event::KeyDown { keycode: Some(keycode), keymod, repeat, .. } => {
+                    let function_key = keycode.into_i32() - Keycode::F1.into_i32();
+                    if (0..SAVE_SLOTS as i32).contains(&function_key) {
+                        let slot = function_key as usize + 1;
+                        if !repeat {
+                            let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                            self.hotkeys.push(if shift { Hotkey::SaveState(slot) } else { Hotkey::LoadState(slot) });
+                        }
+                    } else if keycode == Keycode::F5 {
+                        let remap = Remap::new(self.keymap.clone());
+                        println!("{}", remap.prompt());
+                        self.remap = Some(remap);
+                        self.held = Default::default();
+                        keypad.fill(false);
+                    } else if keycode == Keycode::F10 {
+                        if !repeat {
+                            self.hotkeys.push(Hotkey::ToggleGifRecording);
+                        }
+                    } else if keycode == Keycode::F12 {
+                        if !repeat {
+                            self.hotkeys.push(Hotkey::Screenshot);
+                        }
+                    } else if keycode == Keycode::BACKSPACE {
+                        self.rewinding = true;
+                    } else if keycode == Keycode::M {
+                        if !repeat {
+                            self.hotkeys.push(Hotkey::ToggleMute);
+                        }
+                    } else {
+                        self.set_held(keypad, None, keycode.name(), true);
+                    }
+                }
+                Event::KeyUp { keycode: Some(keycode), .. } => {
+                    if keycode == Keycode::BACKSPACE {
+                        self.rewinding = false;
+                    } else {
+                        self.set_held(keypad, None, keycode.name(), false);
+                    }
+                }
+                _ => {}
+            }
+        }
+        quit
+    }
+
+    fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        std::mem::take(&mut self.hotkeys)
+    }
 
-		let mut event = std::mem::MaybeUninit::<SDL_Event>::uninit();
-
-		while unsafe { SDL_PollEvent(event.as_mut_ptr()) } != 0
-		{
-			let event: SDL_Event = unsafe { event.assume_init() };
-			
-			match event.type_ {
-				x if x==(sdl2::sys::SDL_EventType::SDL_QUIT as u32)  => {
-					quit = true;
-				},
-	
-				x if x== (sdl2::sys::SDL_EventType::SDL_KEYDOWN as u32) => {
-					match unsafe { event.key.keysym.sym } {
-						x if x==(sdl2::sys::SDL_KeyCode::SDLK_ESCAPE as i32) => {
-							quit = true;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_x as i32) => {
-							*(keys.wrapping_add(0)) = 1;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_1 as i32) => {
-							*(keys.wrapping_add(1)) = 1;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_2 as i32) => {
-							*(keys.wrapping_add(2)) = 1;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_3 as i32) => {
-							*(keys.wrapping_add(3)) = 1;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_q as i32) => {
-							*(keys.wrapping_add(4)) = 1;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_w as i32) => {
-							*(keys.wrapping_add(5)) = 1;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_e as i32) => {
-							*(keys.wrapping_add(6)) = 1;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_a as i32) => {
-							*(keys.wrapping_add(7)) = 1;
-						},
-	
-						x if x==(sdl2::sys::SDL_KeyCode::SDLK_s as i32) => {
-							*(keys.wrapping_add(8)) = 1;
-						},
-	
-						x if x==(sdl2::sys::SDL_KeyCode::SDLK_d as i32) => {
-							*(keys.wrapping_add(9))  = 1;
-						},
-	
-						x if x==(sdl2::sys::SDL_KeyCode::SDLK_z as i32) => {
-							*(keys.wrapping_add(0xA)) = 1;
-						},
-	
-						x if x==(sdl2::sys::SDL_KeyCode::SDLK_c as i32) => {
-							*(keys.wrapping_add(0xB)) = 1;
-						},
-	
-						x if x==(sdl2::sys::SDL_KeyCode::SDLK_4 as i32) => {
-							*(keys.wrapping_add(0xC))= 1;
-						},
-	
-						x if x==(sdl2::sys::SDL_KeyCode::SDLK_r as i32) => {
-							*(keys.wrapping_add(0xD))  = 1;
-						},
-	
-						x if x==(sdl2::sys::SDL_KeyCode::SDLK_f as i32)=> {
-							*(keys.wrapping_add(0xE)) = 1;
-						},
-	
-						x if x==(sdl2::sys::SDL_KeyCode::SDLK_v as i32)=> {
-							*(keys.wrapping_add(0xF))  = 1;
-						},
-	
-						_ => {} // Handle other keys if needed
-					}
-				},
-	
-				x if x == (sdl2::sys::SDL_EventType::SDL_KEYUP as u32)   => {
-					match unsafe { event.key.keysym.sym } {
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_x as i32) => {
-							*(keys.wrapping_add(0)) = 0;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_1 as i32) => {
-							*(keys.wrapping_add(1)) = 0;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_2 as i32) => {
-							*(keys.wrapping_add(2)) = 0;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_3 as i32) => {
-							*(keys.wrapping_add(3)) = 0;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_q as i32) => {
-							*(keys.wrapping_add(4)) = 0;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_w as i32) => {
-							*(keys.wrapping_add(5)) = 0;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_e as i32) => {
-							*(keys.wrapping_add(6)) = 0;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_a as i32) => {
-							*(keys.wrapping_add(7)) = 0;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_s as i32) => {
-							*(keys.wrapping_add(8)) = 0;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_d as i32) => {
-							*(keys.wrapping_add(9)) = 0;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_z as i32) => {
-							*(keys.wrapping_add(0xA)) = 0;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_c as i32) => {
-							*(keys.wrapping_add(0xB)) = 0;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_4 as i32) => {
-							*(keys.wrapping_add(0xC)) = 0;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_r as i32) => {
-							*(keys.wrapping_add(0xD)) = 0;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_f as i32) => {
-							*(keys.wrapping_add(0xE)) = 0;
-						},
-	
-						x if x == (sdl2::sys::SDL_KeyCode::SDLK_v as i32) => {
-							*(keys.wrapping_add(0xF)) = 0;
-						},
-	
-						_ => {} // Handle other keys if needed
-					}
-				},
-	
-				_ => {} // Handle other event types if needed
-			}
-		}
-	
-		quit
+    fn rewinding(&self) -> bool {
+        self.rewinding
     }
-    
 
+    fn keymap(&self) -> Option<&Keymap> {
+        Some(&self.keymap)
+    }
 }
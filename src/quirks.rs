@@ -0,0 +1,183 @@
+use std::str::FromStr;
+
+/// Behaviour switches for the opcodes that historical interpreters disagree on.
+/// Every flag can be set on its own; the associated functions return the
+/// combinations used by the well known interpreters, and `FromStr` takes one of them
+/// with flags changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8xy6`/`8xyE` shift Vy and store the result in Vx (COSMAC VIP).
+    /// When unset, Vx is shifted in place and Vy is ignored.
+    pub shift_uses_vy: bool,
+    /// `Fx55`/`Fx65` leave I pointing past the last register stored or loaded.
+    pub load_store_increments_i: bool,
+    /// `Bnnn` behaves as `Bxnn` and jumps to xnn + Vx instead of nnn + V0.
+    pub jump_with_vx: bool,
+    /// `8xy1`/`8xy2`/`8xy3` reset VF to 0.
+    pub logic_resets_vf: bool,
+    /// Sprites drawn past the edge of the screen are clipped.
+    /// When unset, they wrap around to the opposite edge.
+    pub clip_sprites: bool,
+    /// `Dxyn` waits for the next vertical blank before drawing,
+    /// limiting a ROM to one sprite per frame.
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter.
+    pub const fn vip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_with_vx: false,
+            logic_resets_vf: true,
+            clip_sprites: true,
+            display_wait: true,
+        }
+    }
+
+    /// CHIP-48 on the HP-48 calculators, an alias of `schip`. SUPER-CHIP 1.1 kept its
+    /// behaviour except for `Fx55`/`Fx65`, which on CHIP-48 leave I increased by x
+    /// rather than x + 1, a difference no flag covers.
+    pub const fn chip48() -> Self {
+        Self::schip()
+    }
+
+    /// SUPER-CHIP 1.1.
+    pub const fn schip() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_with_vx: true,
+            logic_resets_vf: false,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    /// XO-CHIP as implemented by Octo.
+    pub const fn xo_chip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            jump_with_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+}
+
+impl Quirks {
+    /// The flags by the names profiles and movie files use.
+    pub fn flags(&mut self) -> [(&'static str, &mut bool); 6] {
+        [
+            ("shift_uses_vy", &mut self.shift_uses_vy),
+            ("load_store_increments_i", &mut self.load_store_increments_i),
+            ("jump_with_vx", &mut self.jump_with_vx),
+            ("logic_resets_vf", &mut self.logic_resets_vf),
+            ("clip_sprites", &mut self.clip_sprites),
+            ("display_wait", &mut self.display_wait),
+        ]
+    }
+
+    /// Sets or clears the flag called `name`, see `flags`.
+    pub fn set(&mut self, name: &str, value: bool) -> Result<(), String> {
+        let mut flags = self.flags();
+        let Some((_, flag)) = flags.iter_mut().find(|(flag, _)| *flag == name) else {
+            let names: Vec<&str> = flags.iter().map(|&(name, _)| name).collect();
+            return Err(format!("unknown quirk {:?}, expected one of {}", name, names.join(", ")));
+        };
+        **flag = value;
+        Ok(())
+    }
+}
+
+impl Default for Quirks {
+    /// The behaviour this emulator has always had: shifts in place, I untouched by
+    /// `Fx55`/`Fx65`, `Bnnn` through V0, VF kept by logic ops and wrapping sprites.
+    fn default() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_with_vx: false,
+            logic_resets_vf: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = String;
+
+    /// Parses a profile name followed by flags to set or clear, such as
+    /// `vip,+clip_sprites,-display_wait`. Flags alone change the default profile.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut quirks = Quirks::default();
+        for (i, part) in text.split(',').map(str::trim).enumerate() {
+            let flag = |name: &str| name.to_ascii_lowercase().replace('-', "_");
+            if let Some(name) = part.strip_prefix('+') {
+                quirks.set(&flag(name), true)?;
+            } else if let Some(name) = part.strip_prefix('-') {
+                quirks.set(&flag(name), false)?;
+            } else if i == 0 {
+                quirks = profile(part)?;
+            } else {
+                return Err(format!("expected +QUIRK or -QUIRK after the profile, found {:?}", part));
+            }
+        }
+        Ok(quirks)
+    }
+}
+
+/// The quirks of a profile by name.
+fn profile(name: &str) -> Result<Quirks, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "default" => Ok(Quirks::default()),
+        "vip" | "chip8" | "chip-8" => Ok(Quirks::vip()),
+        "chip48" | "chip-48" => Ok(Quirks::chip48()),
+        "schip" | "superchip" | "super-chip" => Ok(Quirks::schip()),
+        "xochip" | "xo-chip" => Ok(Quirks::xo_chip()),
+        _ => Err(format!("unknown quirk profile {:?}", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_parse_by_name() {
+        assert_eq!("VIP".parse(), Ok(Quirks::vip()));
+        assert_eq!("super-chip".parse(), Ok(Quirks::schip()));
+        assert_eq!("chip48".parse(), Ok(Quirks::schip()));
+        assert_eq!("xo-chip".parse(), Ok(Quirks::xo_chip()));
+        assert!("octo".parse::<Quirks>().is_err());
+    }
+
+    #[test]
+    fn flags_override_the_profile() {
+        let quirks: Quirks = "vip,+jump_with_vx,-display_wait".parse().unwrap();
+        assert_eq!(quirks, Quirks { jump_with_vx: true, display_wait: false, ..Quirks::vip() });
+        let quirks: Quirks = " +clip-sprites , +Logic_Resets_VF".parse().unwrap();
+        assert_eq!(quirks, Quirks { clip_sprites: true, logic_resets_vf: true, ..Quirks::default() });
+    }
+
+    #[test]
+    fn bad_overrides_are_errors() {
+        assert!("vip,+wrap".parse::<Quirks>().unwrap_err().starts_with("unknown quirk \"wrap\""));
+        assert!("vip,schip".parse::<Quirks>().is_err());
+        assert!("vip,".parse::<Quirks>().is_err());
+    }
+
+    #[test]
+    fn every_flag_has_a_name() {
+        let mut quirks = Quirks::default();
+        let names: Vec<&str> = quirks.flags().iter().map(|&(name, _)| name).collect();
+        for name in names {
+            quirks.set(name, true).unwrap();
+        }
+        assert!(quirks.flags().iter().all(|(_, set)| **set));
+    }
+}
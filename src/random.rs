@@ -0,0 +1,172 @@
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+/// Seed of the generator a new `Chip8` starts with, so runs repeat unless a
+/// frontend picks another.
+pub const DEFAULT_SEED: u64 = 0xC8;
+
+/// Where Cxkk gets its random bytes from.
+pub trait RandomSource {
+    /// Short name stored in save states next to `state`, to tell generators apart.
+    fn name(&self) -> &'static str;
+
+    /// The next random byte.
+    fn next_byte(&mut self) -> u8;
+
+    /// The generator's state for save states, empty when it has none.
+    fn state(&self) -> Vec<u8>;
+
+    /// Restores a state returned by `state`, returning false and leaving the
+    /// generator as it was when `state` is invalid.
+    fn restore(&mut self, state: &[u8]) -> bool;
+}
+
+/// SplitMix64, a small and fast generator whose whole state is one `u64`, so any
+/// seed is valid and states are cheap to save.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Seeded {
+    state: u64,
+}
+
+impl Seeded {
+    pub fn new(seed: u64) -> Self {
+        Seeded { state: seed }
+    }
+}
+
+impl Default for Seeded {
+    fn default() -> Self {
+        Seeded::new(DEFAULT_SEED)
+    }
+}
+
+impl RandomSource for Seeded {
+    fn name(&self) -> &'static str {
+        "seeded"
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        ((z ^ (z >> 31)) >> 56) as u8
+    }
+
+    fn state(&self) -> Vec<u8> {
+        self.state.to_be_bytes().to_vec()
+    }
+
+    fn restore(&mut self, state: &[u8]) -> bool {
+        let Ok(state) = state.try_into() else {
+            return false;
+        };
+        self.state = u64::from_be_bytes(state);
+        true
+    }
+}
+
+/// Entropy from the operating system. Runs using it cannot be repeated.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsEntropy;
+
+impl RandomSource for OsEntropy {
+    fn name(&self) -> &'static str {
+        "os"
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        (OsRng.next_u32() & 0xFF) as u8
+    }
+
+    fn state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn restore(&mut self, state: &[u8]) -> bool {
+        state.is_empty()
+    }
+}
+
+/// The built-in generators, as chosen on the command line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Generator {
+    #[default]
+    Seeded,
+    Os,
+}
+
+impl Generator {
+    /// A new generator of this kind; `Os` ignores the seed.
+    pub fn source(self, seed: u64) -> Box<dyn RandomSource> {
+        match self {
+            Generator::Seeded => Box::new(Seeded::new(seed)),
+            Generator::Os => Box::new(OsEntropy),
+        }
+    }
+
+    /// Whether runs using it repeat given the same seed.
+    pub fn is_repeatable(self) -> bool {
+        self != Generator::Os
+    }
+}
+
+impl std::fmt::Display for Generator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Generator::Seeded => "seeded",
+            Generator::Os => "os",
+        })
+    }
+}
+
+impl std::str::FromStr for Generator {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "seeded" => Ok(Generator::Seeded),
+            "os" => Ok(Generator::Os),
+            _ => Err(format!("unknown random number generator {:?}, expected seeded or os", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(source: &mut dyn RandomSource, count: usize) -> Vec<u8> {
+        (0..count).map(|_| source.next_byte()).collect()
+    }
+
+    #[test]
+    fn restored_generators_repeat_their_numbers() {
+        let mut source = Generator::Seeded.source(42);
+        draw(source.as_mut(), 5);
+        let state = source.state();
+        let expected = draw(source.as_mut(), 20);
+
+        let mut restored = Generator::Seeded.source(7);
+        assert!(restored.restore(&state));
+        assert_eq!(draw(restored.as_mut(), 20), expected);
+    }
+
+    #[test]
+    fn invalid_states_are_refused() {
+        let mut seeded = Seeded::new(1);
+        assert!(!seeded.restore(&[1, 2, 3]));
+        assert_eq!(seeded, Seeded::new(1));
+        assert!(!OsEntropy.restore(&[0]));
+    }
+
+    #[test]
+    fn generators_parse_by_name() {
+        for generator in [Generator::Seeded, Generator::Os] {
+            assert_eq!(generator.to_string().parse(), Ok(generator));
+        }
+        assert_eq!("SEEDED".parse(), Ok(Generator::Seeded));
+        assert!("vip".parse::<Generator>().is_err());
+        assert!(!Generator::Os.is_repeatable());
+    }
+}
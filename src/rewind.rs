@@ -0,0 +1,216 @@
+use std::collections::VecDeque;
+
+use crate::chip8::Chip8;
+
+/// Frames between snapshots by default, 10 per second at 60 Hz.
+pub const DEFAULT_INTERVAL: u32 = 6;
+/// Snapshots kept by default, covering the last 10 seconds.
+pub const DEFAULT_CAPACITY: usize = 100;
+
+/// One step back in the history: how to turn a snapshot into the one taken before it.
+struct Entry {
+    /// Length of the older snapshot.
+    len: usize,
+    /// When set, `runs` encodes the older snapshot on its own rather than its
+    /// difference from the newer one, because their lengths differ.
+    full: bool,
+    runs: Vec<u8>,
+}
+
+/// Bounded history of save states taken every few frames, for rewinding.
+///
+/// Only the newest snapshot is kept whole; every older one is stored as the XOR of
+/// itself with the snapshot after it, with the runs of zero bytes this leaves for
+/// unchanged memory compressed away.
+pub struct Rewind {
+    interval: u32,
+    capacity: usize,
+    frames: u32,
+    latest: Option<Vec<u8>>,
+    history: VecDeque<Entry>,
+}
+
+impl Rewind {
+    /// Snapshots every `interval` frames, keeping at most `capacity` of them.
+    pub fn new(interval: u32, capacity: usize) -> Self {
+        Rewind {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            frames: 0,
+            latest: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Called once per frame of normal execution; takes a snapshot every `interval` frames.
+    pub fn record(&mut self, chip8: &Chip8) {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+        let snapshot = chip8.save_state();
+        if let Some(previous) = self.latest.take() {
+            let full = previous.len() != snapshot.len();
+            let delta: Vec<u8> = if full {
+                previous.clone()
+            } else {
+                previous.iter().zip(&snapshot).map(|(old, new)| old ^ new).collect()
+            };
+            self.history.push_back(Entry { len: previous.len(), full, runs: encode_runs(&delta) });
+            if self.history.len() >= self.capacity {
+                self.history.pop_front();
+            }
+        }
+        self.latest = Some(snapshot);
+    }
+
+    /// Takes the newest snapshot out of the history and restores it, keeping the keys
+    /// currently held. Returns false once the history is exhausted.
+    pub fn step_back(&mut self, chip8: &mut Chip8) -> bool {
+        let Some(snapshot) = self.latest.take() else {
+            return false;
+        };
+        self.latest = self.history.pop_back().map(|entry| {
+            let delta = decode_runs(&entry.runs, entry.len);
+            if entry.full {
+                delta
+            } else {
+                delta.iter().zip(&snapshot).map(|(delta, new)| delta ^ new).collect()
+            }
+        });
+        self.frames = 0;
+        let keypad = chip8.keypad;
+        let restored = chip8.load_state(&snapshot).is_ok();
+        chip8.keypad = keypad;
+        restored
+    }
+}
+
+/// Encodes `data` as pairs of a run of zero bytes and a run of literal bytes,
+/// each length written as a little-endian base-128 varint.
+fn encode_runs(data: &[u8]) -> Vec<u8> {
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let zeros = data[i..].iter().take_while(|&&byte| byte == 0).count();
+        i += zeros;
+        let literals = data[i..].iter().take_while(|&&byte| byte != 0).count();
+        write_varint(&mut runs, zeros);
+        write_varint(&mut runs, literals);
+        runs.extend_from_slice(&data[i..i + literals]);
+        i += literals;
+    }
+    runs
+}
+
+fn decode_runs(runs: &[u8], len: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(len);
+    let mut i = 0;
+    while i < runs.len() {
+        let zeros = read_varint(runs, &mut i);
+        let literals = read_varint(runs, &mut i);
+        data.resize(data.len() + zeros, 0);
+        data.extend_from_slice(&runs[i..i + literals]);
+        i += literals;
+    }
+    data.resize(len, 0);
+    data
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], i: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some(&byte) = data.get(*i) {
+        *i += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ADD V2, 1; JP 0x200 - counts frames in V2.
+    const COUNTER: [u8; 4] = [0x72, 0x01, 0x12, 0x00];
+
+    #[test]
+    fn runs_round_trip() {
+        let mut long = vec![0; 300];
+        long.extend([1, 2, 3]);
+        long.resize(700, 0);
+        long.extend(vec![0xFF; 200]);
+        for data in [vec![], vec![0; 10], vec![5; 10], vec![0, 0, 7, 0, 9, 9, 0], long] {
+            let runs = encode_runs(&data);
+            assert_eq!(decode_runs(&runs, data.len()), data);
+        }
+        // trailing zeros cost nothing, the length restores them
+        assert_eq!(encode_runs(&[4, 0, 0, 0, 0]), [0, 1, 4, 4, 0]);
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, 1 << 40] {
+            let mut data = Vec::new();
+            write_varint(&mut data, value);
+            let mut i = 0;
+            assert_eq!(read_varint(&data, &mut i), value);
+            assert_eq!(i, data.len());
+        }
+    }
+
+    #[test]
+    fn steps_back_through_every_snapshot() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&COUNTER).unwrap();
+        let mut rewind = Rewind::new(2, 10);
+        let mut snapshots = Vec::new();
+        for frame in 1..=10 {
+            chip8.run_frame(2).unwrap();
+            rewind.record(&chip8);
+            if frame % 2 == 0 {
+                snapshots.push(chip8.save_state());
+            }
+        }
+        chip8.press_key(0xA);
+        while let Some(snapshot) = snapshots.pop() {
+            assert!(rewind.step_back(&mut chip8));
+            let mut expected = Chip8::new();
+            expected.load_state(&snapshot).unwrap();
+            assert_eq!(chip8.machine_state(), expected.machine_state());
+            assert!(chip8.key_pressed(0xA));
+        }
+        assert_eq!(chip8.machine_state().registers[2], 2);
+        assert!(!rewind.step_back(&mut chip8));
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&COUNTER).unwrap();
+        let mut rewind = Rewind::new(1, 3);
+        for _ in 0..10 {
+            chip8.run_frame(2).unwrap();
+            rewind.record(&chip8);
+        }
+        let mut steps = 0;
+        while rewind.step_back(&mut chip8) {
+            steps += 1;
+        }
+        assert_eq!(steps, 3);
+        assert_eq!(chip8.machine_state().registers[2], 8);
+    }
+}
@@ -0,0 +1,201 @@
+use crate::error::StateError;
+
+/// First bytes of every save state.
+pub const MAGIC: [u8; 4] = *b"C8ST";
+/// Bumped whenever the layout written by `Chip8::save_state` changes.
+pub const VERSION: u16 = 1;
+
+/// Builds a save state: header, big-endian fields, then a CRC-32 of everything before it.
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut data = MAGIC.to_vec();
+        data.extend(VERSION.to_be_bytes());
+        StateWriter { data }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend(value.to_be_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Writes a length-prefixed block, for fields whose size depends on the variant.
+    pub fn block(&mut self, bytes: &[u8]) {
+        self.data.extend((bytes.len() as u32).to_be_bytes());
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let checksum = crc32(&self.data);
+        self.data.extend(checksum.to_be_bytes());
+        self.data
+    }
+}
+
+/// Reads the fields of a save state after its header and checksum have been verified.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Checks the magic, version and checksum of `state`.
+    pub fn new(state: &'a [u8]) -> Result<Self, StateError> {
+        let header = MAGIC.len() + 2;
+        if state.len() < header + 4 {
+            return Err(StateError::Truncated);
+        }
+        if state[..MAGIC.len()] != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = u16::from_be_bytes([state[4], state[5]]);
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let (body, checksum) = state.split_at(state.len() - 4);
+        if crc32(body).to_be_bytes() != checksum {
+            return Err(StateError::ChecksumMismatch);
+        }
+        Ok(StateReader { data: &body[header..] })
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("boolean")),
+        }
+    }
+
+    /// Fills `array` with the next `N` bytes.
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    pub fn block(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.take(4)?;
+        let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+        self.take(len)
+    }
+
+    /// Fails unless every byte of the state has been read.
+    pub fn finish(self) -> Result<(), StateError> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err(StateError::Invalid("trailing data"))
+        }
+    }
+}
+
+/// CRC-32 (IEEE), as used by zip and PNG.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::random::Generator;
+
+    /// Draws two random bytes into V0 and V1 and counts in V2, forever.
+    const ROM: [u8; 8] = [0xC0, 0xFF, 0xC1, 0xFF, 0x72, 0x01, 0x12, 0x00];
+
+    fn running() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&ROM).unwrap();
+        chip8.run_frame(10).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn restored_state_draws_the_same_random_numbers() {
+        let mut chip8 = running();
+        let state = chip8.save_state();
+        let expected: Vec<_> = (0..3).map(|_| {
+            chip8.run_frame(10).unwrap();
+            chip8.machine_state()
+        }).collect();
+
+        let mut restored = Chip8::new();
+        restored.seed_rng(1234);
+        restored.load_state(&state).unwrap();
+        let actual: Vec<_> = (0..3).map(|_| {
+            restored.run_frame(10).unwrap();
+            restored.machine_state()
+        }).collect();
+        assert_eq!(actual, expected);
+        assert_eq!(restored.save_state(), chip8.save_state());
+    }
+
+    #[test]
+    fn header_and_checksum_are_checked() {
+        let state = running().save_state();
+        let mut chip8 = Chip8::new();
+
+        let mut bad_magic = state.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(chip8.load_state(&bad_magic), Err(StateError::BadMagic));
+
+        let mut corrupt = state.clone();
+        corrupt[100] ^= 1;
+        assert_eq!(chip8.load_state(&corrupt), Err(StateError::ChecksumMismatch));
+
+        let mut future = state.clone();
+        future[4..6].copy_from_slice(&(VERSION + 1).to_be_bytes());
+        assert_eq!(chip8.load_state(&future), Err(StateError::UnsupportedVersion(VERSION + 1)));
+
+        assert_eq!(chip8.load_state(&state[..8]), Err(StateError::Truncated));
+        let mut cut = state[..state.len() - 30].to_vec();
+        cut.extend_from_slice(&crc32(&cut).to_be_bytes());
+        assert_eq!(chip8.load_state(&cut), Err(StateError::Truncated));
+        assert_eq!(chip8.machine_state(), Chip8::new().machine_state());
+    }
+
+    #[test]
+    fn states_from_another_generator_are_rejected() {
+        let state = running().save_state();
+        let mut chip8 = Chip8::new();
+        chip8.set_rng(Generator::Os.source(1));
+        let expected = StateError::GeneratorMismatch { saved: "seeded".into(), current: "os" };
+        assert_eq!(chip8.load_state(&state), Err(expected));
+        assert_eq!(chip8.machine_state(), Chip8::new().machine_state());
+    }
+}
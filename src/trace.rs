@@ -0,0 +1,437 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::ops::RangeInclusive;
+
+use crate::error::MachineState;
+use crate::instruction::Instruction;
+
+/// First bytes of a binary trace.
+pub const MAGIC: [u8; 4] = *b"C8TR";
+/// Bytes per record in a binary trace.
+const RECORD_SIZE: usize = 33;
+
+/// The machine as an instruction starts: the cycle it runs in, counted from power-on,
+/// the PC, the opcode and the registers and timers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub registers: [u8; 0x10],
+    pub index: u16,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl TraceRecord {
+    pub fn new(cycle: u64, state: &MachineState) -> Self {
+        TraceRecord {
+            cycle,
+            pc: state.pc,
+            opcode: state.opcode,
+            registers: state.registers,
+            index: state.index,
+            sp: state.sp,
+            delay_timer: state.delay_timer,
+            sound_timer: state.sound_timer,
+        }
+    }
+
+    /// The fields that differ from `other`'s, leaving out the cycle.
+    pub fn differences(&self, other: &TraceRecord) -> Vec<String> {
+        let mut fields = Vec::new();
+        let mut compare = |name: &str, a: u16, b: u16| {
+            if a != b {
+                fields.push(name.to_owned());
+            }
+        };
+        compare("PC", self.pc, other.pc);
+        compare("opcode", self.opcode, other.opcode);
+        for (i, (&a, &b)) in self.registers.iter().zip(&other.registers).enumerate() {
+            compare(&format!("V{:X}", i), a as u16, b as u16);
+        }
+        compare("I", self.index, other.index);
+        compare("SP", self.sp as u16, other.sp as u16);
+        compare("DT", self.delay_timer as u16, other.delay_timer as u16);
+        compare("ST", self.sound_timer as u16, other.sound_timer as u16);
+        fields
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(self.cycle.to_be_bytes());
+        out.extend(self.pc.to_be_bytes());
+        out.extend(self.opcode.to_be_bytes());
+        out.extend(self.registers);
+        out.extend(self.index.to_be_bytes());
+        out.extend([self.sp, self.delay_timer, self.sound_timer]);
+    }
+
+    fn decode(bytes: &[u8; RECORD_SIZE]) -> Self {
+        let u16_at = |i: usize| u16::from_be_bytes([bytes[i], bytes[i + 1]]);
+        let mut cycle = [0; 8];
+        cycle.copy_from_slice(&bytes[..8]);
+        let mut registers = [0; 0x10];
+        registers.copy_from_slice(&bytes[12..28]);
+        TraceRecord {
+            cycle: u64::from_be_bytes(cycle),
+            pc: u16_at(8),
+            opcode: u16_at(10),
+            registers,
+            index: u16_at(28),
+            sp: bytes[30],
+            delay_timer: bytes[31],
+            sound_timer: bytes[32],
+        }
+    }
+}
+
+/// One line of a text trace, in fixed-width columns so that traces line up in a diff:
+///
+/// ```text
+///        812 0226 D015 | 00 0A 00 ... 00 | I=0234 SP=1 DT=00 ST=00 | DRW V0, V1, 5
+/// ```
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>10} {:04X} {:04X} |", self.cycle, self.pc, self.opcode)?;
+        for value in self.registers {
+            write!(f, " {:02X}", value)?;
+        }
+        write!(f, " | I={:04X} SP={:X} DT={:02X} ST={:02X} | ", self.index, self.sp, self.delay_timer, self.sound_timer)?;
+        match Instruction::decode(self.opcode) {
+            Some(instruction) => write!(f, "{}", instruction),
+            None => write!(f, "???"),
+        }
+    }
+}
+
+impl std::str::FromStr for TraceRecord {
+    type Err = String;
+
+    /// Parses a line written by `Display`, ignoring the disassembly.
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid trace line {:?}", line);
+        let mut columns = line.split('|').map(str::trim);
+        let (Some(position), Some(registers), Some(rest)) = (columns.next(), columns.next(), columns.next()) else {
+            return Err(invalid());
+        };
+        let hex16 = |text: &str| u16::from_str_radix(text, 16).map_err(|_| invalid());
+        let hex8 = |text: &str| u8::from_str_radix(text, 16).map_err(|_| invalid());
+        let [cycle, pc, opcode] = position.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err(invalid());
+        };
+        let registers: Vec<u8> = registers.split_whitespace().map(hex8).collect::<Result<_, _>>()?;
+        let registers: [u8; 0x10] = registers.try_into().map_err(|_| invalid())?;
+        let field = |name: &str| {
+            rest.split_whitespace()
+                .find_map(|field| field.strip_prefix(name)?.strip_prefix('='))
+                .ok_or_else(invalid)
+        };
+        Ok(TraceRecord {
+            cycle: cycle.parse().map_err(|_| invalid())?,
+            pc: hex16(pc)?,
+            opcode: hex16(opcode)?,
+            registers,
+            index: hex16(field("I")?)?,
+            sp: hex8(field("SP")?)?,
+            delay_timer: hex8(field("DT")?)?,
+            sound_timer: hex8(field("ST")?)?,
+        })
+    }
+}
+
+/// Receives a record for every instruction `Chip8::cycle` starts, see `Chip8::set_trace`.
+pub trait TraceSink {
+    fn record(&mut self, record: &TraceRecord);
+
+    /// Writes out anything buffered, reporting the first error met while tracing.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Which instructions to trace; `None` lets everything through.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Filter {
+    /// Addresses of the instructions traced.
+    pub addresses: Option<RangeInclusive<u16>>,
+    /// Cycles traced.
+    pub cycles: Option<RangeInclusive<u64>>,
+}
+
+impl Filter {
+    pub fn accepts(&self, record: &TraceRecord) -> bool {
+        self.addresses.as_ref().is_none_or(|addresses| addresses.contains(&record.pc))
+            && self.cycles.as_ref().is_none_or(|cycles| cycles.contains(&record.cycle))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// A line per instruction, see `TraceRecord`'s `Display`.
+    #[default]
+    Text,
+    /// `MAGIC`, then 33 big-endian bytes per instruction, about a third of the text.
+    Binary,
+}
+
+/// Writes the records `filter` accepts to `out`.
+pub struct Tracer<W: Write> {
+    out: W,
+    format: Format,
+    filter: Filter,
+    /// The first write error, reported by `flush`; nothing is written after it.
+    error: Option<io::Error>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(mut out: W, format: Format, filter: Filter) -> Self {
+        let error = match format {
+            Format::Binary => out.write_all(&MAGIC).err(),
+            Format::Text => None,
+        };
+        Tracer { out, format, filter, error, buffer: Vec::with_capacity(RECORD_SIZE) }
+    }
+}
+
+impl<W: Write> TraceSink for Tracer<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        if self.error.is_some() || !self.filter.accepts(record) {
+            return;
+        }
+        let written = match self.format {
+            Format::Text => writeln!(self.out, "{}", record),
+            Format::Binary => {
+                self.buffer.clear();
+                record.encode(&mut self.buffer);
+                self.out.write_all(&self.buffer)
+            }
+        };
+        self.error = written.err();
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.out.flush(),
+        }
+    }
+}
+
+/// Reads the records of a text or binary trace, telling them apart by `MAGIC`.
+pub struct TraceReader<R: BufRead> {
+    input: R,
+    /// Unknown until the first read.
+    binary: Option<bool>,
+    line: usize,
+}
+
+impl<R: BufRead> TraceReader<R> {
+    pub fn new(input: R) -> Self {
+        TraceReader { input, binary: None, line: 0 }
+    }
+
+    fn next_binary(&mut self) -> Option<Result<TraceRecord, String>> {
+        let mut bytes = [0; RECORD_SIZE];
+        let mut read = 0;
+        while read < RECORD_SIZE {
+            match self.input.read(&mut bytes[read..]) {
+                Ok(0) if read == 0 => return None,
+                Ok(0) => return Some(Err("truncated record at the end of the trace".to_owned())),
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Some(Err(e.to_string())),
+            }
+        }
+        Some(Ok(TraceRecord::decode(&bytes)))
+    }
+
+    fn next_text(&mut self) -> Option<Result<TraceRecord, String>> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            self.line += 1;
+            match self.input.read_line(&mut line) {
+                Ok(0) => return None,
+                Ok(_) if line.trim().is_empty() => continue,
+                Ok(_) => return Some(line.trim_end().parse().map_err(|e| format!("{}: {}", self.line, e))),
+                Err(e) => return Some(Err(format!("{}: {}", self.line, e))),
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TraceReader<R> {
+    type Item = Result<TraceRecord, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.binary.is_none() {
+            let start = match self.input.fill_buf() {
+                Ok(start) => start,
+                Err(e) => return Some(Err(e.to_string())),
+            };
+            let binary = start.starts_with(&MAGIC);
+            if binary {
+                self.input.consume(MAGIC.len());
+            }
+            self.binary = Some(binary);
+        }
+        if self.binary == Some(true) {
+            self.next_binary()
+        } else {
+            self.next_text()
+        }
+    }
+}
+
+/// Where two traces part ways.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Divergence {
+    /// The first instruction, by cycle, at which they differ, and the fields that do.
+    Differs { a: TraceRecord, b: TraceRecord, fields: Vec<String> },
+    /// One trace stops while the other goes on; `a_ended` tells which stopped.
+    Ended { a_ended: bool, next: TraceRecord },
+}
+
+/// Compares two traces, aligning their records on the cycle count so that traces
+/// with different filters or starting points can be compared where they overlap.
+/// Returns the number of instructions compared and the first divergence.
+pub fn diff<A, B>(a: A, b: B) -> Result<(u64, Option<Divergence>), String>
+where
+    A: Iterator<Item = Result<TraceRecord, String>>,
+    B: Iterator<Item = Result<TraceRecord, String>>,
+{
+    let (mut a, mut b) = (a, b);
+    let mut compared = 0;
+    let (mut next_a, mut next_b) = (a.next().transpose()?, b.next().transpose()?);
+    loop {
+        match (next_a, next_b) {
+            (None, None) => return Ok((compared, None)),
+            (None, Some(next)) => return Ok((compared, Some(Divergence::Ended { a_ended: true, next }))),
+            (Some(next), None) => return Ok((compared, Some(Divergence::Ended { a_ended: false, next }))),
+            // skip ahead in whichever trace is behind
+            (Some(record_a), Some(record_b)) if record_a.cycle < record_b.cycle => next_a = a.next().transpose()?,
+            (Some(record_a), Some(record_b)) if record_a.cycle > record_b.cycle => next_b = b.next().transpose()?,
+            (Some(record_a), Some(record_b)) => {
+                let fields = record_a.differences(&record_b);
+                if !fields.is_empty() {
+                    return Ok((compared, Some(Divergence::Differs { a: record_a, b: record_b, fields })));
+                }
+                compared += 1;
+                (next_a, next_b) = (a.next().transpose()?, b.next().transpose()?);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+
+    /// LD V3, 0x10; LD I, 0x301; ADD V3, 1; JP 0x204.
+    const COUNTER: [u8; 8] = [0x63, 0x10, 0xA3, 0x01, 0x73, 0x01, 0x12, 0x04];
+
+    /// The records of the first `cycles` instructions of `COUNTER`.
+    fn records(cycles: u64) -> Vec<TraceRecord> {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&COUNTER).unwrap();
+        (0..cycles)
+            .map(|cycle| {
+                let record = TraceRecord::new(cycle, &chip8.machine_state());
+                chip8.cycle().unwrap();
+                record
+            })
+            .collect()
+    }
+
+    fn write(records: &[TraceRecord], format: Format, filter: Filter) -> Vec<u8> {
+        let mut tracer = Tracer::new(Vec::new(), format, filter);
+        records.iter().for_each(|record| tracer.record(record));
+        tracer.flush().unwrap();
+        tracer.out
+    }
+
+    fn read(trace: &[u8]) -> Result<Vec<TraceRecord>, String> {
+        TraceReader::new(trace).collect()
+    }
+
+    #[test]
+    fn text_lines_line_up() {
+        let mut registers = [0; 0x10];
+        registers[1] = 0x0A;
+        let record = TraceRecord {
+            cycle: 812,
+            pc: 0x226,
+            opcode: 0xD015,
+            registers,
+            index: 0x234,
+            sp: 1,
+            delay_timer: 0,
+            sound_timer: 0x1F,
+        };
+        let line = record.to_string();
+        assert!(line.starts_with("       812 0226 D015 | 00 0A 00 00 "), "{}", line);
+        assert!(line.contains(" 00 | I=0234 SP=1 DT=00 ST=1F | "), "{}", line);
+        assert_eq!(line.parse(), Ok(record));
+    }
+
+    #[test]
+    fn traces_round_trip() {
+        let records = records(10);
+        assert_eq!(records[3].registers[3], 0x11);
+        for format in [Format::Text, Format::Binary] {
+            let trace = write(&records, format, Filter::default());
+            assert_eq!(read(&trace), Ok(records.clone()));
+        }
+        let binary = write(&records, Format::Binary, Filter::default());
+        assert_eq!(binary.len(), MAGIC.len() + records.len() * RECORD_SIZE);
+        assert_eq!(read(&[]), Ok(Vec::new()));
+        assert_eq!(read(&MAGIC), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn filters_pick_addresses_and_cycles() {
+        let records = records(10);
+        let filter = Filter { addresses: Some(0x204..=0x204), cycles: None };
+        let traced = read(&write(&records, Format::Text, filter)).unwrap();
+        assert_eq!(traced.iter().map(|record| record.cycle).collect::<Vec<_>>(), [2, 4, 6, 8]);
+        let filter = Filter { addresses: Some(0x200..=0x204), cycles: Some(1..=4) };
+        let traced = read(&write(&records, Format::Binary, filter)).unwrap();
+        assert_eq!(traced.iter().map(|record| record.cycle).collect::<Vec<_>>(), [1, 2, 4]);
+    }
+
+    #[test]
+    fn bad_traces_are_errors() {
+        let mut text = write(&records(2), Format::Text, Filter::default());
+        text.extend(b"\n  12 0200 |\n");
+        assert_eq!(read(&text), Err("4: invalid trace line \"  12 0200 |\"".to_owned()));
+        let line = records(1)[0].to_string().replace("I=0000", "I=XYZ");
+        assert_eq!(read(line.as_bytes()), Err(format!("1: invalid trace line {:?}", line)));
+
+        let mut binary = write(&records(2), Format::Binary, Filter::default());
+        binary.pop();
+        assert_eq!(read(&binary), Err("truncated record at the end of the trace".to_owned()));
+    }
+
+    #[test]
+    fn diffs_find_the_first_divergence() {
+        let records = records(10);
+        assert_eq!(diff(records.iter().cloned().map(Ok), records.iter().cloned().map(Ok)), Ok((10, None)));
+
+        let mut changed = records.clone();
+        changed[6].registers[3] = 0;
+        changed[6].index = 0;
+        let divergence = Divergence::Differs { a: records[6], b: changed[6], fields: vec!["V3".into(), "I".into()] };
+        assert_eq!(diff(records.iter().cloned().map(Ok), changed.into_iter().map(Ok)), Ok((6, Some(divergence))));
+
+        // traces are aligned on the cycle, and compared where both have records
+        let late = records[4..].iter().cloned().map(Ok);
+        let divergence = Divergence::Ended { a_ended: true, next: records[8] };
+        assert_eq!(diff(records[..8].iter().cloned().map(Ok), late), Ok((4, Some(divergence))));
+
+        let broken = [Ok(records[0]), Err("2: invalid trace line".to_owned())];
+        assert_eq!(diff(records.iter().cloned().map(Ok), broken.into_iter()), Err("2: invalid trace line".into()));
+    }
+}
@@ -0,0 +1,291 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use chip8_h::chip8::HIRES_VIDEO_HEIGHT;
+use chip8_h::frontend::{Display, Hotkey, Input, SAVE_SLOTS};
+use chip8_h::keymap::{Keymap, Remap};
+use chip8_h::palette::Palette;
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+/// Terminal line for messages such as remapping prompts, below the tallest display.
+const STATUS_LINE: u16 = (HIRES_VIDEO_HEIGHT / 2 + 1) as u16;
+/// How long a key stays down after the terminal last reported it, when it can't report
+/// releases. Held keys are re-reported by autorepeat, whose first repeat comes after
+/// a delay of typically 250 to 500 ms, so this outlasts that delay and a held key
+/// stays down throughout. A tap is held as long.
+const KEY_HOLD: Duration = Duration::from_millis(550);
+
+/// Puts the terminal in raw mode on an alternate screen, restoring it when dropped.
+pub struct Terminal {
+    /// Whether key releases are reported (kitty keyboard protocol).
+    releases: bool,
+}
+
+impl Terminal {
+    pub fn new() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+        if releases {
+            execute!(stdout, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        Ok(Terminal { releases })
+    }
+
+    pub fn display(&self, palette: Palette) -> TtyDisplay {
+        // COLORTERM is how terminals advertise 24-bit colour
+        let truecolor = std::env::var("COLORTERM").is_ok_and(|value| value == "truecolor" || value == "24bit");
+        let colours = palette.colours.map(|colour| if truecolor { rgb(colour) } else { ansi256(colour) });
+        TtyDisplay { colours, last_frame: Vec::new(), size: (0, 0) }
+    }
+
+    pub fn input(&self, keymap: Keymap) -> TtyInput {
+        TtyInput {
+            releases: self.releases,
+            keymap,
+            remap: None,
+            pressed_at: [None; 0x10],
+            rewind_pressed_at: None,
+            hotkeys: Vec::new(),
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        if self.releases {
+            let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(stdout, ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Draws two pixel rows per line with the upper half block, in the palette's colours
+/// on 24-bit colour terminals and in the closest of the 256 xterm colours elsewhere.
+pub struct TtyDisplay {
+    /// The palette as the terminal shows it.
+    colours: [Color; 4],
+    /// The frame on screen, to skip redrawing unchanged frames over slow connections.
+    last_frame: Vec<u8>,
+    size: (u32, u32),
+}
+
+impl TtyDisplay {
+    fn draw(&mut self, framebuffer: &[u8], width: u32, height: u32) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        if self.size != (width, height) {
+            queue!(out, ResetColor, Clear(ClearType::All))?;
+        }
+        let pixel = |x: u32, y: u32| framebuffer.get((y * width + x) as usize).copied().unwrap_or(0);
+        for row in 0..height.div_ceil(2) {
+            queue!(out, MoveTo(0, row as u16))?;
+            let mut colours = None;
+            for x in 0..width {
+                let (top, bottom) = (pixel(x, row * 2) & 0x3, pixel(x, row * 2 + 1) & 0x3);
+                if colours != Some((top, bottom)) {
+                    let (fg, bg) = (self.colours[top as usize], self.colours[bottom as usize]);
+                    queue!(out, SetForegroundColor(fg), SetBackgroundColor(bg))?;
+                    colours = Some((top, bottom));
+                }
+                queue!(out, Print('▀'))?;
+            }
+            queue!(out, ResetColor)?;
+        }
+        out.flush()
+    }
+}
+
+impl Display for TtyDisplay {
+    fn present(&mut self, framebuffer: &[u8], width: u32, height: u32) {
+        if self.size == (width, height) && self.last_frame == framebuffer {
+            return;
+        }
+        // a failed write leaves the old frame up, the next one tries again
+        if self.draw(framebuffer, width, height).is_ok() {
+            self.size = (width, height);
+            self.last_frame = framebuffer.to_vec();
+        }
+    }
+
+    fn message(&mut self, text: &str) {
+        status(text);
+    }
+}
+
+fn rgb(pixel: u32) -> Color {
+    let [r, g, b, _] = pixel.to_be_bytes();
+    Color::Rgb { r, g, b }
+}
+
+/// The closest of the 256 xterm colours: the 6x6x6 cube from 16 or the grey ramp from 232.
+fn ansi256(pixel: u32) -> Color {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let [r, g, b, _] = pixel.to_be_bytes();
+    let distance = |[cr, cg, cb]: [u8; 3]| {
+        [(r, cr), (g, cg), (b, cb)].iter().map(|&(a, b)| (a.abs_diff(b) as u32).pow(2)).sum::<u32>()
+    };
+    let nearest = |value: u8| (0..6).min_by_key(|&i| LEVELS[i].abs_diff(value)).unwrap_or(0);
+    let cube = [nearest(r), nearest(g), nearest(b)];
+    // grey i is 8 + 10 * i
+    let grey = (((r as u32 + g as u32 + b as u32) / 3).saturating_sub(3) / 10).min(23) as u8;
+    let level = 8 + 10 * grey;
+    if distance([level; 3]) < distance(cube.map(|i| LEVELS[i])) {
+        Color::AnsiValue(232 + grey)
+    } else {
+        Color::AnsiValue(16 + 36 * cube[0] as u8 + 6 * cube[1] as u8 + cube[2] as u8)
+    }
+}
+
+/// Maps key presses to the keypad and the hotkeys: F1 to F4 load a save state slot,
+/// Shift+F1 to Shift+F4 save one, F5 remaps the keypad, F10 starts and stops a GIF
+/// recording, F12 takes a screenshot, Backspace rewinds, Escape or Ctrl+C quits.
+///
+/// Most terminals only report presses, so a key is released `KEY_HOLD` after it was
+/// last reported unless the terminal reports the release itself.
+pub struct TtyInput {
+    releases: bool,
+    keymap: Keymap,
+    /// Set while F5 remapping waits for keys.
+    remap: Option<Remap>,
+    /// When each key was last reported down, `None` once released.
+    pressed_at: [Option<Instant>; 0x10],
+    rewind_pressed_at: Option<Instant>,
+    hotkeys: Vec<Hotkey>,
+}
+
+impl TtyInput {
+    fn held(&self, pressed_at: Option<Instant>, now: Instant) -> bool {
+        // with release events a key stays down until its release arrives
+        pressed_at.is_some_and(|pressed_at| self.releases || now < pressed_at + KEY_HOLD)
+    }
+
+    /// Handles a key event, returning true when it asks to quit.
+    fn key(&mut self, event: KeyEvent, now: Instant) -> bool {
+        let pressed = event.kind != KeyEventKind::Release;
+        if let Some(remap) = &mut self.remap {
+            if event.kind != KeyEventKind::Press {
+                return false;
+            }
+            if event.code == KeyCode::Esc {
+                status("remapping cancelled");
+                self.remap = None;
+            } else if let Some(name) = key_name(event.code) {
+                if let Some(keymap) = remap.press(&name) {
+                    status("");
+                    self.keymap = keymap;
+                    self.remap = None;
+                    self.hotkeys.push(Hotkey::KeymapChanged);
+                } else {
+                    status(&remap.prompt());
+                }
+            }
+            return false;
+        }
+        match event.code {
+            KeyCode::Esc if pressed => return true,
+            KeyCode::Char('c') if pressed && event.modifiers.contains(KeyModifiers::CONTROL) => return true,
+            KeyCode::F(n) if (1..=SAVE_SLOTS as u8).contains(&n) && event.kind == KeyEventKind::Press => {
+                let slot = n as usize;
+                let shift = event.modifiers.contains(KeyModifiers::SHIFT);
+                self.hotkeys.push(if shift { Hotkey::SaveState(slot) } else { Hotkey::LoadState(slot) });
+            }
+            KeyCode::F(5) if event.kind == KeyEventKind::Press => {
+                let remap = Remap::new(self.keymap.clone());
+                status(&remap.prompt());
+                self.remap = Some(remap);
+                self.pressed_at = [None; 0x10];
+            }
+            KeyCode::F(10) if event.kind == KeyEventKind::Press => self.hotkeys.push(Hotkey::ToggleGifRecording),
+            KeyCode::F(12) if event.kind == KeyEventKind::Press => self.hotkeys.push(Hotkey::Screenshot),
+            KeyCode::Backspace => self.rewind_pressed_at = pressed.then_some(now),
+            code => {
+                if let Some(key) = key_name(code).and_then(|name| self.keymap.key_for(&name)) {
+                    self.pressed_at[key as usize] = pressed.then_some(now);
+                }
+            }
+        }
+        false
+    }
+}
+
+/// The SDL name of a key, as used by `Keymap`.
+fn key_name(code: KeyCode) -> Option<String> {
+    let name = match code {
+        KeyCode::Char(' ') => "Space",
+        KeyCode::Char(c) => return Some(c.to_uppercase().collect()),
+        KeyCode::Left => "Left",
+        KeyCode::Right => "Right",
+        KeyCode::Up => "Up",
+        KeyCode::Down => "Down",
+        KeyCode::Enter => "Return",
+        KeyCode::Tab => "Tab",
+        KeyCode::Home => "Home",
+        KeyCode::End => "End",
+        KeyCode::PageUp => "PageUp",
+        KeyCode::PageDown => "PageDown",
+        KeyCode::Insert => "Insert",
+        KeyCode::Delete => "Delete",
+        _ => return None,
+    };
+    Some(name.to_owned())
+}
+
+/// Shows a message on the status line, below the display.
+fn status(message: &str) {
+    let mut stdout = io::stdout();
+    let _ = execute!(stdout, MoveTo(0, STATUS_LINE), ResetColor, Clear(ClearType::CurrentLine), Print(message));
+}
+
+impl Input for TtyInput {
+    fn poll(&mut self, keypad: &mut [bool; 0x10]) -> bool {
+        let now = Instant::now();
+        let mut quit = false;
+        while event::poll(Duration::ZERO).unwrap_or(false) {
+            match event::read() {
+                Ok(Event::Key(event)) => quit |= self.key(event, now),
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        for (pressed, &pressed_at) in keypad.iter_mut().zip(&self.pressed_at) {
+            *pressed = self.held(pressed_at, now);
+        }
+        quit
+    }
+
+    fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        std::mem::take(&mut self.hotkeys)
+    }
+
+    fn rewinding(&self) -> bool {
+        self.held(self.rewind_pressed_at, Instant::now())
+    }
+
+    fn keymap(&self) -> Option<&Keymap> {
+        Some(&self.keymap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colours_map_to_the_closest_xterm_colour() {
+        assert_eq!(ansi256(0x000000FF), Color::AnsiValue(16));
+        assert_eq!(ansi256(0xFFFFFFFF), Color::AnsiValue(231));
+        assert_eq!(ansi256(0xFFB000FF), Color::AnsiValue(16 + 36 * 5 + 6 * 3));
+        // a grey between two cube levels is closer on the grey ramp
+        assert_eq!(ansi256(0xAAAAAAFF), Color::AnsiValue(248));
+    }
+}